@@ -10,25 +10,26 @@
 // use mesh::ShapeMesh;
 
 use std::{
+    path::PathBuf,
     rc::Rc,
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use cgmath::{Point3, Rad};
+use cgmath::EuclideanSpace;
 use winit::{
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
 
 use crate::{
-    camera::Projection,
     control::{CamControl, Fly as FlyControl, KeySwitcher, Orbit as OrbitControl},
     event::{EventHandler, EventResponse, QuitHandler},
     mesh::ShapeMesh,
     prelude::*,
-    shape::{Mandelbulb, Shape},
+    scene::{CameraSettings, Scene},
+    shape::{AnimatedShape, ParamValue, Shape},
     sky::Sky,
     wgpu::{DrawContext, Wgpu},
 };
@@ -88,22 +89,70 @@ struct App {
     sky: Sky,
     shape: Arc<dyn Shape>,
     mesh: ShapeMesh,
+
+    /// Creates `shape` for the current `time`.
+    animation: Box<dyn AnimatedShape>,
+    time: f32,
+    paused: bool,
+
+    /// The scene the app was started with and where it is saved to.
+    scene: Scene,
+    scene_path: PathBuf,
 }
 
+/// The near plane is never moved closer to the camera than this (see
+/// `App::update`).
+const MIN_NEAR_PLANE: f32 = 1e-12;
+
+/// Where the scene is saved if the app wasn't started with a scene file.
+const DEFAULT_SCENE_PATH: &str = "scene.ron";
+
 impl App {
     async fn new(window: Rc<Window>) -> Result<Self> {
         let wgpu = Wgpu::new(&window).await.context("failed to initialize wgpu")?;
 
-        // Initialize our projection parameters.
-        let proj = Projection::new(Rad(1.0), 0.000_04..10.0, window.inner_size().into());
+        // The first command line argument is either a scene file (ending in
+        // `.ron`) or a shape expression. All following arguments have the
+        // form `name=value` and override shape parameters.
+        let mut args = std::env::args().skip(1);
+        let (mut scene, scene_path) = match args.next() {
+            Some(path) if path.ends_with(".ron") => {
+                let path = PathBuf::from(path);
+                (Scene::load(&path)?, path)
+            }
+            Some(src) => (Scene::with_shape(&src), PathBuf::from(DEFAULT_SCENE_PATH)),
+            None => (Scene::default(), PathBuf::from(DEFAULT_SCENE_PATH)),
+        };
 
-        let orbit = OrbitControl::around(Point3::new(0.0, 0.0, 0.0), proj);
-        let fly = FlyControl::new(orbit.camera().clone(), window.clone());
+        let (animation, mut shape) = scene.parse_shape()?;
+        for arg in args {
+            let (name, value) = param_from_arg(&*shape, &arg)
+                .with_context(|| format!("invalid parameter override '{}'", arg))?;
+            scene.params.insert(name, value);
+        }
+        scene.apply_params(&mut shape)?;
+        if animation.is_animated() {
+            info!("Shape is animated (press P to pause)");
+        }
+        for param in shape.params() {
+            debug!("Shape parameter {} = {}", param.name, shape.param(param.name).unwrap());
+        }
+
+        let camera = scene.camera.to_camera(window.inner_size().into())
+            .context("invalid camera settings")?;
+        let bb = scene.mesh.region_for(&*shape)?;
+        let center = bb.start.midpoint(bb.end).cast().unwrap();
+        let orbit = OrbitControl::looking_at(camera, center);
+        let fly = FlyControl::new(camera, window.clone());
         let switcher = KeySwitcher::new(orbit, fly, VirtualKeyCode::F);
 
         let sky = Sky::new(&wgpu.device, wgpu.swap_chain_format)?;
-        let shape = Arc::new(Mandelbulb::classic(6, 2.5)) as Arc<dyn Shape>;
-        let mesh = ShapeMesh::new(&wgpu.device, wgpu.swap_chain_format, shape.clone())?;
+        let mesh = ShapeMesh::new(
+            &wgpu.device,
+            wgpu.swap_chain_format,
+            shape.clone(),
+            scene.mesh.clone(),
+        )?;
 
         Ok(Self {
             window,
@@ -116,18 +165,72 @@ impl App {
             sky,
             shape,
             mesh,
+            animation: Box::new(animation),
+            time: 0.0,
+            paused: false,
+            scene,
+            scene_path,
         })
     }
 
+    /// Saves the current shape parameters and camera to the scene file.
+    fn save_scene(&mut self) -> Result<()> {
+        // The parameters of an animated shape change all the time. Storing
+        // them would stop the animation when loading the scene.
+        if !self.animation.is_animated() {
+            self.scene.store_params(&*self.shape);
+        }
+        // The near and far plane are adjusted automatically (see `update`),
+        // so we keep the configured ones.
+        self.scene.camera = CameraSettings {
+            near_plane: self.scene.camera.near_plane,
+            far_plane: self.scene.camera.far_plane,
+            ..CameraSettings::from_camera(&self.control.camera())
+        };
+        self.scene.save(&self.scene_path)?;
+        info!("Saved scene to '{}'", self.scene_path.display());
+
+        Ok(())
+    }
+
     fn update(&mut self) {
         let now = Instant::now();
         let delta = now - self.last_update;
         self.last_update = now;
 
+        if self.animation.is_animated() && !self.paused {
+            self.time += delta.as_secs_f32();
+            if let Err(e) = self.animate() {
+                error!("Stopping animation: {:?}", e);
+                self.animation = Box::new(self.shape.clone());
+            }
+        }
+
         self.control.update(delta.as_secs_f32(), &*self.shape);
+
+        // When zooming in deep, the configured near plane would clip the
+        // surface, so we move it closer to the camera. The far plane is moved
+        // by the same factor to keep the depth buffer precision.
+        let distance = self.shape.min_distance_from_f64(self.control.camera().position).abs();
+        let CameraSettings { near_plane, far_plane, .. } = self.scene.camera;
+        let near = ((0.5 * distance) as f32).clamp(MIN_NEAR_PLANE, near_plane);
+        let projection = self.control.projection_mut();
+        projection.near_plane = near;
+        projection.far_plane = far_plane * (near / near_plane);
+
         self.mesh.update(self.wgpu.device.clone(), &self.control.camera());
     }
 
+    /// Creates the shape for the current time and passes it to the mesh.
+    fn animate(&mut self) -> Result<()> {
+        let mut shape = self.animation.at(self.time)?;
+        self.scene.apply_params(&mut shape)?;
+        self.mesh.set_shape(shape.clone());
+        self.shape = shape;
+
+        Ok(())
+    }
+
     fn draw(&mut self) -> Result<()> {
         self.fps_timer.register_frame();
         if let Some(fps) = self.fps_timer.report_fps() {
@@ -168,10 +271,82 @@ impl EventHandler for App {
             return EventResponse::Break;
         }
 
+        if let Event::WindowEvent {
+            event: WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F5),
+                    ..
+                },
+                ..
+            },
+            ..
+        } = e
+        {
+            if let Err(e) = self.save_scene() {
+                error!("Failed to save scene: {:?}", e);
+            }
+            return EventResponse::Break;
+        }
+
+        if let Event::WindowEvent {
+            event: WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::P),
+                    ..
+                },
+                ..
+            },
+            ..
+        } = e
+        {
+            if self.animation.is_animated() {
+                self.paused = !self.paused;
+                let state = if self.paused { "paused" } else { "resumed" };
+                info!("Animation {} at {:.2}s", state, self.time);
+            }
+            return EventResponse::Break;
+        }
+
+        if let Event::WindowEvent {
+            event: WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::M),
+                    ..
+                },
+                ..
+            },
+            ..
+        } = e
+        {
+            // Stored in the scene, so that it's saved, too.
+            let algorithm = self.scene.mesh.algorithm.next();
+            self.scene.mesh.algorithm = algorithm;
+            self.mesh.set_algorithm(algorithm);
+            info!("Switched to mesh algorithm {:?}", algorithm);
+            return EventResponse::Break;
+        }
+
         crate::event::handle_with(e, &mut [&mut QuitHandler, &mut self.control])
     }
 }
 
+/// Parses a shape parameter from a command line argument of the form
+/// `name=value`.
+fn param_from_arg(shape: &dyn Shape, arg: &str) -> Result<(String, ParamValue)> {
+    let eq = arg.find('=').ok_or(anyhow!("expected 'name=value'"))?;
+    let (name, value) = (&arg[..eq], &arg[eq + 1..]);
+
+    let ty = shape.params().iter()
+        .find(|p| p.name == name)
+        .map(|p| p.ty)
+        .ok_or(anyhow!("shape has no parameter '{}'", name))?;
+    let value = ParamValue::parse(ty, value)?;
+    Ok((name.to_string(), value))
+}
+
 
 
 /// How often the FPS are reported. Longer times lead to more delay and more
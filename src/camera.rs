@@ -12,9 +12,13 @@ const THETA_SAFE_EPSILON: Rad<f32> = Rad(0.02);
 
 /// Saves the camera position and look direction as well as projection
 /// parameters.
+///
+/// The position is stored with double precision to allow deep zooms. Only
+/// positions relative to the camera are converted to `f32` for rendering (see
+/// `view_transform`).
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Camera {
-    pub position: Point3<f32>,
+    pub position: Point3<f64>,
     direction: Vector3<f32>,
     pub projection: Projection,
 }
@@ -23,7 +27,7 @@ impl Camera {
     /// Creates a new instance.
     ///
     /// `dir` mustn't be zero.
-    pub fn new(pos: Point3<f32>, dir: Vector3<f32>, proj: Projection) -> Self {
+    pub fn new(pos: Point3<f64>, dir: Vector3<f32>, proj: Projection) -> Self {
         assert!(!dir.is_zero());
 
         Camera {
@@ -66,10 +70,14 @@ impl Camera {
     }
 
     /// Returns the matrix representing the transformation into view space.
+    ///
+    /// This transformation is camera relative: it only contains the rotation
+    /// and expects points relative to the camera position (`p - position`).
+    /// Otherwise, we would lose too much precision close to the shape.
     pub fn view_transform(&self) -> Matrix4<f32> {
-        Matrix4::look_at_rh(
-            self.position,
-            self.position + self.direction,
+        Matrix4::look_to_rh(
+            Point3::origin(),
+            self.direction,
             Vector3::new(0.0, 0.0, 1.0),
         )
     }
@@ -29,6 +29,10 @@ const FAST_MOVE_MULTIPLIER: f32 = 3.0;
 /// big.
 const TURN_PER_PIXEL: Rad<f32> = Rad(0.00025);
 
+/// The movement speed is scaled by the distance to the shape, but never
+/// below this factor, so that the camera can't get stuck on the surface.
+const MIN_DISTANCE_MULTIPLIER: f64 = 1e-12;
+
 pub struct Fly {
     cam: Camera,
 
@@ -88,16 +92,19 @@ impl CamControl for Fly {
             1.0
         };
 
-        let distance_multiplier = (2.0 * shape.min_distance_from(self.cam.position).abs())
-            .clamp(0.0005, 2.0);
+        // The speed is proportional to the distance from the shape, which
+        // allows flying very close to the surface when zooming in deep.
+        let distance_multiplier = (2.0 * shape.min_distance_from_f64(self.cam.position).abs())
+            .clamp(MIN_DISTANCE_MULTIPLIER, 2.0);
 
         let up_vec = Vector3::new(0.0, 0.0, 1.0);
         let left_vec = -self.cam.direction().cross(up_vec).normalize();
-        self.cam.position += distance_multiplier * speed_multiplier * delta * (
+        let movement = speed_multiplier * delta * (
             self.cam.direction() * self.forward_speed +
             left_vec * self.left_speed +
             up_vec * self.up_speed
         );
+        self.cam.position += distance_multiplier * movement.cast::<f64>().unwrap();
     }
 
     fn as_event_handler(&mut self) -> &mut dyn EventHandler {
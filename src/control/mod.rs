@@ -118,7 +118,7 @@ impl<A: CamControl, B: CamControl> CamControl for KeySwitcher<A, B> {
             lerp(
                 self.second.camera().position,
                 self.first.camera().position,
-                self.amount_first,
+                self.amount_first.into(),
             ),
             lerp(
                 self.second.camera().direction(),
@@ -1,4 +1,4 @@
-use cgmath::{prelude::*, Point3, Rad, Vector3};
+use cgmath::{prelude::*, Point3, Rad};
 use winit::event::{Event, KeyboardInput, WindowEvent};
 
 use crate::{
@@ -26,7 +26,7 @@ const ZOOM_SPEED: f32 = 1.0;
 
 /// Offers orbital control around a fixed origin point.
 pub struct Orbit {
-    origin: Point3<f32>,
+    origin: Point3<f64>,
     cam: Camera,
 
     // These four values are used for smooth rotations. The `speed` values
@@ -44,15 +44,17 @@ pub struct Orbit {
 }
 
 impl Orbit {
-    /// Creates an orbital control around the given point with the given
-    /// projection.
-    pub fn around(origin: Point3<f32>, proj: Projection) -> Self {
-        let init_dir = Vector3::new(1.0, 0.0, 0.0).normalize();
-        let distance = 3.0;
+    /// Creates an orbital control starting with the given camera. The camera
+    /// orbits around the point on its view ray which is closest to `center`.
+    pub fn looking_at(cam: Camera, center: Point3<f64>) -> Self {
+        const MIN_DISTANCE: f64 = 0.001;
+
+        let dir = cam.direction().cast::<f64>().unwrap();
+        let distance = (center - cam.position).dot(dir).max(MIN_DISTANCE);
 
         Orbit {
-            origin,
-            cam: Camera::new(origin + -(init_dir * distance), init_dir, proj),
+            origin: cam.position + dir * distance,
+            cam,
             theta_speed: Rad(0.0),
             theta_accel: Rad(0.0),
             phi_speed: Rad(0.0),
@@ -61,12 +63,12 @@ impl Orbit {
         }
     }
 
-    fn distance(&self) -> f32 {
+    fn distance(&self) -> f64 {
         (self.cam.position - self.origin).magnitude()
     }
 
-    fn update_distance(&mut self, distance: f32) {
-        self.cam.position = self.origin + distance * -self.cam.direction();
+    fn update_distance(&mut self, distance: f64) {
+        self.cam.position = self.origin + distance * -self.cam.direction().cast().unwrap();
     }
 }
 
@@ -99,11 +101,11 @@ impl CamControl for Orbit {
         phi += self.phi_speed * delta;
 
         self.cam.look_at_sphere(theta, phi);
-        self.cam.position = self.origin + self.distance() * -self.cam.direction();
+        self.update_distance(self.distance());
 
         // Update distance from origin
         let rate_of_change = self.zoom_speed * delta;
-        let new_distance = self.distance() * 2.0f32.powf(rate_of_change);
+        let new_distance = self.distance() * 2.0f64.powf(rate_of_change.into());
         self.update_distance(new_distance);
     }
 
@@ -113,7 +115,7 @@ impl CamControl for Orbit {
 
     fn match_view(&mut self, other: &Camera) {
         let view_dir = self.origin - other.position;
-        self.cam.look_in(view_dir);
+        self.cam.look_in(view_dir.cast().unwrap());
         self.cam.position = other.position;
     }
 }
@@ -13,6 +13,7 @@ mod event;
 mod math;
 mod mesh;
 mod octree;
+mod scene;
 mod shape;
 mod sky;
 mod wgpu;
@@ -47,3 +47,9 @@ impl Lerp<f32> for Point3<f32> {
         self * (1.0 - t) + other.to_vec() * t
     }
 }
+
+impl Lerp<f64> for Point3<f64> {
+    fn lerp(self, other: Self, t: f64) -> Self {
+        self * (1.0 - t) + other.to_vec() * t
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet},
+    time::Duration,
     fmt,
     ops,
 };
@@ -13,17 +14,52 @@ use crate::{
     octree::Span,
     util::{
         ToArr,
-        iter::cube,
         grid::GridTable,
         time::DurationExt,
     },
 };
-use super::Vertex;
-
+use super::{EdgeRefinement, Vertex};
+
+
+/// If the distance between two grid corners is at least this times the
+/// largest absolute coordinate in the box, the shape is sampled with `f32`
+/// precision (which allows for faster batch evaluation). Otherwise `f64` is
+/// used.
+const F32_MIN_RELATIVE_STEP: f64 = 1e-4;
+
+/// Sphere tracing along an edge stops as soon as the DE is smaller than this
+/// times the length of the edge.
+const SPHERE_TRACING_EPSILON: f64 = 1e-4;
+
+/// An algorithm turning the (sampled) distance field of a shape into a
+/// triangle mesh. See `MeshAlgorithm` for the available implementations.
+pub trait Mesher: Send + Sync {
+    /// Generates the mesh for the part of `shape` inside `span`. The shape
+    /// is sampled on a grid with `resolution` cells along each axis. If the
+    /// grid of the parent octree node is given, its samples are reused
+    /// where possible (see `DistanceGrid::sample`).
+    ///
+    /// Triangles are counter-clockwise when seen from outside of the shape.
+    fn mesh(
+        &self,
+        span: &Span,
+        shape: &dyn Shape,
+        resolution: u32,
+        parent: Option<&DistanceGrid>,
+    ) -> (MeshBuffer, Timings);
+}
 
 pub struct MeshBuffer {
+    /// All vertex positions are relative to this point. This way, they can be
+    /// stored as `f32` without losing precision for tiny octree nodes.
+    pub(crate) origin: Point3<f64>,
     pub(crate) vertices: Vec<Vertex>,
     pub(crate) indices: Vec<u32>,
+
+    /// The sampled distances the mesh was generated from. They are kept to
+    /// speed up generating the meshes of the children when the octree leaf
+    /// is split.
+    pub(crate) grid: DistanceGrid,
 }
 
 impl MeshBuffer {
@@ -31,6 +67,8 @@ impl MeshBuffer {
         span: &Span,
         shape: &dyn Shape,
         resolution: u32,
+        mesher: &dyn Mesher,
+        parent: Option<&DistanceGrid>,
     ) -> (Self, Timings) {
         assert!(span.start.x < span.end.x);
         assert!(span.start.y < span.end.y);
@@ -38,356 +76,408 @@ impl MeshBuffer {
         assert!(resolution != 0);
         assert!(resolution.is_power_of_two());
 
-        Self::naive_surface_nets(span, shape, resolution)
+        let (buf, timings) = mesher.mesh(span, shape, resolution, parent);
+        trace!(
+            "Generated {:6} points, {:6} faces in {}",
+            buf.vertices.len(),
+            buf.indices.len() / 3,
+            timings,
+        );
+
+        (buf, timings)
     }
 
-    /// Implementation of the "Surface Nets" algorithm.
+    /// Adds skirts hiding the cracks between this mesh and the meshes of
+    /// coarser neighbors.
     ///
-    /// In particular, in this implementation the position of the vertex inside
-    /// the 3D-cell is simply the centroid of all edge crossings. This rather
-    /// easy version is described [in this article][1] ("naive surface nets").
+    /// Neighboring octree leaves of different size sample the shape with
+    /// different cell sizes, so their surfaces don't exactly meet. For each
+    /// face of `span` (in the order -x, +x, -y, +y, -z, +z) with a depth in
+    /// `depths`, every boundary edge of the mesh on or beyond that face is
+    /// extruded into the shape, along the normals of its vertices. Seen from
+    /// outside, this covers the gap to the other surface as long as that is
+    /// at most `depth` below ours.
     ///
-    /// The article will also help understand this algorithm. Compared to
-    /// other algorithms for rendering iso surfaces, this one is relatively
-    /// easy to implement while still working fairly nice.
+    /// The gap can be seen from both leaves, so the skirts are visible from
+    /// both sides.
+    pub fn add_skirts(&mut self, span: &Span, depths: [Option<f64>; 6]) {
+        if depths.iter().all(Option::is_none) {
+            return;
+        }
+
+        // In a closed mesh, each edge is used once in both directions. The
+        // remaining edges are on the boundary of the mesh.
+        let mut boundary = HashSet::new();
+        for t in self.indices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (t[i], t[(i + 1) % 3]);
+                if !boundary.remove(&(b, a)) {
+                    boundary.insert((a, b));
+                }
+            }
+        }
+
+        // Marching cubes puts the boundary vertices exactly on the faces, so
+        // we need some tolerance.
+        let origin = self.origin;
+        let vertices = &self.vertices;
+        let step = self.grid.step;
+        let on_face = |v: u32, face: usize| {
+            let axis = face / 2;
+            let p = origin[axis] + vertices[v as usize].position[axis] as f64;
+            let epsilon = 0.01 * step[axis];
+            if face.is_multiple_of(2) {
+                p <= span.start[axis] + epsilon
+            } else {
+                p >= span.end[axis] - epsilon
+            }
+        };
+
+        let mut edges = boundary.into_iter()
+            .filter_map(|(a, b)| {
+                (0..6)
+                    .find(|&face| depths[face].is_some() && on_face(a, face) && on_face(b, face))
+                    .map(|face| (a, b, depths[face].unwrap()))
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|&(a, b, _)| (a, b));
+
+        // The extruded copy of each vertex (per depth, for vertices in the
+        // corners).
+        let mut extruded = HashMap::new();
+        let vertices = &mut self.vertices;
+        let mut extrude = |v: u32, depth: f64| {
+            *extruded.entry((v, depth.to_bits())).or_insert_with(|| {
+                let mut vertex = vertices[v as usize];
+                for i in 0..3 {
+                    vertex.position[i] -= vertex.normal[i] * depth as f32;
+                }
+                vertices.push(vertex);
+                vertices.len() as u32 - 1
+            })
+        };
+
+        for (a, b, depth) in edges {
+            let (a_low, b_low) = (extrude(a, depth), extrude(b, depth));
+            self.indices.extend_from_slice(&[
+                a, b, b_low,
+                a, b_low, a_low,
+
+                // The same quad with the other orientation
+                a, b_low, b,
+                a, a_low, b_low,
+            ]);
+        }
+    }
+}
+
+
+// ===========================================================================
+// ===== Helpers shared by all meshers
+// ===========================================================================
+
+/// All 12 edges of a cell, specified by their corner indices. The index of
+/// the corner at `(x, y, z)` (each 0 or 1) is `4x + 2y + z`.
+pub(super) const EDGES: [(u8, u8); 12] = [
+    // Edges whose endpoints differ in the x coordinate (first
+    // corner id is -x, second is +x).
+    (0, 4),     //    -y -z
+    (1, 5),     //    -y +z
+    (2, 6),     //    +y -z
+    (3, 7),     //    +y +z
+
+    // Edges whose endpoints differ in the y coordinate (first
+    // corner id is -y, second is +y).
+    (0, 2),     // -x    -z
+    (1, 3),     // -x    +z
+    (4, 6),     // +x    -z
+    (5, 7),     // +x    +z
+
+    // Edges whose endpoints differ in the z coordinate (first
+    // corner id is -z, second is +z).
+    (0, 1),     // -x -y
+    (2, 3),     // -x +y
+    (4, 5),     // +x -y
+    (6, 7),     // +x +y
+];
+
+/// Returns the offset of the given corner (see `EDGES`) from the lower corner
+/// of a cell, in units of cells.
+pub(super) fn corner_offset(corner: u8) -> (u32, u32, u32) {
+    ((corner as u32 >> 2) & 1, (corner as u32 >> 1) & 1, corner as u32 & 1)
+}
+
+/// A regular grid of `resolution` cells along each axis covering `span`
+/// with the estimated minimal distances from the shape at all corners.
+pub(crate) struct DistanceGrid {
+    pub(super) dists: GridTable<f64>,
+
+    /// The world space distance between two corners.
+    pub(super) step: Vector3<f64>,
+    start: Point3<f64>,
+}
+
+impl DistanceGrid {
+    /// Samples the shape at all corners.
     ///
-    /// In the future we might want to switch to the "Dual Contouring" scheme
-    /// as it preserves sharp features of the shape (see #2).
+    /// If `parent` is given and every other corner of this grid coincides
+    /// with one of its corners (as is the case for the grid of an octree
+    /// leaf and the one of its parent, see `Seed`), those samples are reused.
     ///
-    /// [1]: https://0fps.net/2012/07/12/smooth-voxel-terrain-part-2/
-    fn naive_surface_nets(
+    /// If the shape has a fast batch API (see `Shape::has_fast_batch`) and
+    /// `f32` is precise enough for this box, we evaluate one row of corners
+    /// at a time with it. Otherwise, we sample each point in double
+    /// precision.
+    pub(super) fn sample(
         span: &Span,
         shape: &dyn Shape,
         resolution: u32,
-    ) -> (Self, Timings) {
-        // Adjust span to avoid holes in between two boxes
-        let span = {
-            let overflow = (span.end - span.start) / resolution as f32;
-            span.start + -overflow .. span.end + overflow
+        parent: Option<&DistanceGrid>,
+    ) -> Self {
+        let across_span = span.end - span.start;
+        let step = across_span / resolution as f64;
+        let corner = |x: u32, y: u32, z: u32| {
+            let v = Vector3::new(x as f64, y as f64, z as f64) / (resolution as f64);
+            span.start + across_span.mul_element_wise(v)
         };
 
-        let before_first = Instant::now();
+        let seed = parent.and_then(|parent| Seed::new(parent, span.start, step));
+        let known = |x: u32, y: u32, z: u32| seed.as_ref().and_then(|s| s.get((x, y, z)));
+
+        let max_coord = span.start.to_arr().iter()
+            .chain(&span.end.to_arr())
+            .fold(0.0, |max: f64, c| max.max(c.abs()));
+        let min_step = step.x.min(step.y).min(step.z);
+        let f32_sufficient = min_step >= F32_MIN_RELATIVE_STEP * max_coord;
+
+        let dists = if shape.has_fast_batch() && f32_sufficient {
+            let mut row = Vec::with_capacity(resolution as usize + 1);
+            GridTable::fill_rows_with(resolution + 1, |x, y, out| {
+                row.clear();
+                row.extend(
+                    (0..=resolution)
+                        .filter(|&z| known(x, y, z).is_none())
+                        .map(|z| corner(x, y, z).cast::<f32>().unwrap())
+                );
 
-        // First Step:
-        // ===========
-        //
-        // We partition our box into regular cells. For each corner in between
-        // the cells we calculate and save the estimated minimal distance from
-        // the shape.
-        let across_span = span.end - span.start;
-        let dists = GridTable::fill_with(resolution + 1, |x, y, z| {
-            let v = Vector3::new(x as f32, y as f32, z as f32) / (resolution as f32);
-            let p = span.start + across_span.mul_element_wise(v);
+                let mut sampled = shape.batch_min_distance_from(&row).into_iter();
+                out.extend((0..=resolution).map(|z| {
+                    known(x, y, z).unwrap_or_else(|| sampled.next().unwrap().into())
+                }));
+            })
+        } else {
+            GridTable::fill_with(resolution + 1, |x, y, z| {
+                known(x, y, z).unwrap_or_else(|| shape.min_distance_from_f64(corner(x, y, z)))
+            })
+        };
 
-            shape.min_distance_from(p)
-        });
+        Self { dists, step, start: span.start }
+    }
 
-        let before_second = Instant::now();
-
-
-        // Second Step:
-        // ============
-        //
-        // Next, we will iterate over all cells of the box (unlike before
-        // where we iterated over corners). For each cell crossing the shape's
-        // surface, we will generate one vertex. The `points` grid table holds
-        // the index of the vertex corresponding to the cell, or `None` if the
-        // cell does not cross the surface.
-        //
-        let mut vertices = Vec::new();
-
-        // The world space distance between two corners/between the
-        // center points of two cells.
-        let step = (span.end - span.start) / resolution as f32;
-        let corner_offsets = [
-            Vector3::new(   0.0,    0.0,    0.0),
-            Vector3::new(   0.0,    0.0, step.z),
-            Vector3::new(   0.0, step.y,    0.0),
-            Vector3::new(   0.0, step.y, step.z),
-            Vector3::new(step.x,    0.0,    0.0),
-            Vector3::new(step.x,    0.0, step.z),
-            Vector3::new(step.x, step.y,    0.0),
-            Vector3::new(step.x, step.y, step.z),
-        ];
+    /// The world space position of the given corner.
+    pub(super) fn corner(&self, (x, y, z): (u32, u32, u32)) -> Point3<f64> {
+        self.start + Vector3::new(x as f64, y as f64, z as f64).mul_element_wise(self.step)
+    }
 
-        let points = GridTable::fill_with(resolution, |x, y, z| {
-            // The estimated minimal distances of all eight corners calculated
-            // in the prior step.
-            let distances = [
-                dists[(x    , y    , z    )],
-                dists[(x    , y    , z + 1)],
-                dists[(x    , y + 1, z    )],
-                dists[(x    , y + 1, z + 1)],
-                dists[(x + 1, y    , z    )],
-                dists[(x + 1, y    , z + 1)],
-                dists[(x + 1, y + 1, z    )],
-                dists[(x + 1, y + 1, z + 1)],
-            ];
-
-            // First, check if the current cell is only partially inside the
-            // shape (if the cell intersects the shape's surface). If that's
-            // not the case, we won't generate a vertex for this cell.
-            let no_shape_crossing = {
-                let first = distances[0].is_sign_positive();
-                let mut all_same = true;
-                for d in &distances[1..] {
-                    if d.is_sign_positive() != first {
-                        all_same = false;
-                        break;
-                    }
-                }
+    /// Whether the corner is outside of the shape.
+    pub(super) fn is_outside(&self, corner: (u32, u32, u32)) -> bool {
+        self.dists[corner].is_sign_positive()
+    }
 
-                all_same
-            };
+    /// Returns the point where the edge between the two given (neighboring)
+    /// corners crosses the surface. The distances at both corners must have
+    /// different signs.
+    ///
+    /// Depending on `refinement`, the shape is queried a few more times to
+    /// narrow down the crossing (see #1). In the end, we interpolate linearly
+    /// between the two closest known points on both sides of the surface,
+    /// weighted by their distances.
+    fn edge_crossing(
+        &self,
+        from: (u32, u32, u32),
+        to: (u32, u32, u32),
+        shape: &dyn Shape,
+        refinement: EdgeRefinement,
+    ) -> Point3<f64> {
+        // Here we want to make sure that `outside` is the point outside of
+        // the shape (positive distance) and `inside` the one inside.
+        let (mut outside, mut inside) = if self.is_outside(from) {
+            ((self.corner(from), self.dists[from]), (self.corner(to), self.dists[to]))
+        } else {
+            ((self.corner(to), self.dists[to]), (self.corner(from), self.dists[from]))
+        };
 
-            if no_shape_crossing {
-                // FIXME
-                // This is a bit hacky, but we will never access this number
-                return u32::MAX;
+        match refinement {
+            EdgeRefinement::Linear => {}
+            EdgeRefinement::Bisection { steps } => {
+                for _ in 0..steps {
+                    let mid = outside.0.midpoint(inside.0);
+                    let d = shape.min_distance_from_f64(mid);
+                    if d.is_sign_positive() {
+                        outside = (mid, d);
+                    } else {
+                        inside = (mid, d);
+                    }
+                }
             }
+            EdgeRefinement::SphereTracing { steps } => {
+                // As the DE is a lower bound, we can step from the outside
+                // corner towards the inside one by the DE without crossing
+                // the surface.
+                let edge = inside.0 - outside.0;
+                let dir = edge.normalize();
+                let epsilon = SPHERE_TRACING_EPSILON * edge.magnitude();
+                for _ in 0..steps {
+                    if outside.1 < epsilon {
+                        break;
+                    }
 
-            // World position of this cell's lower corner
-            let p0 = span.start + Vector3::new(x as f32, y as f32, z as f32)
-                .mul_element_wise(step);
-
-            // We want to iterate over all 12 edges of the cell. Here, we list
-            // all edges by specifying their corner indices.
-            const EDGES: [(u8, u8); 12] = [
-                // Edges whose endpoints differ in the x coordinate (first
-                // corner id is -x, second is +x).
-                (0, 4),     //    -y -z
-                (1, 5),     //    -y +z
-                (2, 6),     //    +y -z
-                (3, 7),     //    +y +z
-
-                // Edges whose endpoints differ in the y coordinate (first
-                // corner id is -y, second is +y).
-                (0, 2),     // -x    -z
-                (1, 3),     // -x    +z
-                (4, 6),     // +x    -z
-                (5, 7),     // +x    +z
-
-                // Edges whose endpoints differ in the z coordinate (first
-                // corner id is -z, second is +z).
-                (0, 1),     // -x -y
-                (2, 3),     // -x +y
-                (4, 5),     // +x -y
-                (6, 7),     // +x +y
-            ];
-
-            // Get all edge crossings. These are points where the edges of the
-            // current cell intersect the surface... more or less. We do NOT
-            // find the correct crossing point by ray marching, as this would
-            // require more queries to the shape (our current bottleneck for
-            // mandelbulb).
-            //
-            // Instead, we simply weight both endpoints of the edge by the
-            // already calculated distances. Improving this might be worth
-            // experimenting (see #1).
-            let edge_crossings = EDGES.iter().cloned()
-                .map(|(from, to)| (from as usize, to as usize))
-
-                // We are only interested in the edges with shape crossing. The
-                // edge crosses the shape iff the endpoints' estimated minimal
-                // distances have different signs ("minus" means: inside the
-                // shape).
-                .filter(|&(from, to)| {
-                    distances[from].is_sign_positive() != distances[to].is_sign_positive()
-                })
-
-                // Next, we convert the edge into a vertex on said edge. We
-                // could just use the center point of the two endpoints. But
-                // weighting each endpoint with the estimated minimal distance
-                // from the shape results in a mesh more closely representing
-                // the shape.
-                .map(|(from, to)| {
-                    // Here we want to make sure that `d_from` is  negative and
-                    // `d_to` is positive.
-                    //
-                    // Remember: we already know that both distances have
-                    // different signs!
-                    let (d_from, d_to) = if distances[from] < 0.0 {
-                        (distances[from], distances[to])
+                    let p = outside.0 + dir * outside.1;
+                    let d = shape.min_distance_from_f64(p);
+                    if d.is_sign_positive() {
+                        outside = (p, d);
                     } else {
-                        (-distances[from], -distances[to])
-                    };
+                        // Only possible due to rounding errors or a DE
+                        // violating its contract.
+                        inside = (p, d);
+                        break;
+                    }
+                }
+            }
+        }
 
-                    // This condition is only true if `d_from == -0.0`. In
-                    // theory this might happen, so we better deal with it.
-                    let weight_from = if d_to == d_from {
-                        0.5
-                    } else {
-                        // Here we calculate the weight (a number between 0 and
-                        // 1 inclusive) for the `from` endpoint. `delta` is
-                        // the difference between the two distances.
-                        //
-                        // First we will shift the distance to "the right",
-                        // making it positive. Then, we scale it by delta.
-                        //
-                        // - d_from + delta is always >= 0.0
-                        // - d_from + delta is always <= delta
-                        // ==> `(d_from + delta) / delta` is always in 0...1
-                        //
-                        // For d_from == 0 and d_to > 0:
-                        // - d_from + delta == delta
-                        // ==> result is: delta / delta == 1
-                        //
-                        // For d_from < 0 and d_to == 0:
-                        // - d_from + delta == 0
-                        // ==> result is: 0 / delta == 0
-                        let delta = d_to - d_from;
-                        (d_from + delta) / delta
-                    };
-
-                    lerp(p0 + corner_offsets[from], p0 + corner_offsets[to], weight_from)
-                });
-
-            // As described in the article above, we simply use the centroid
-            // of all edge crossings.
-            let (count, total_displacement) = edge_crossings.fold(
-                (0, Vector3::zero()),
-                |(count, sum), p| (count + 1, sum + p.to_vec()));
-            let p = Point3::origin() + (total_displacement / count as f32);
-
-            // Now we only calculate some meta data which might be used to
-            // color the vertex.
-            let dist_p = shape.min_distance_from(p);
-
-            let normal = {
-                let delta = 0.7 * (span.end - span.start) / resolution as f32;
-                Vector3::new(
-                    shape.min_distance_from(p + Vector3::unit_x() * delta.x)
-                        - shape.min_distance_from(p +  Vector3::unit_x() * -delta.x),
-                    shape.min_distance_from(p + Vector3::unit_y() * delta.y)
-                        - shape.min_distance_from(p +  Vector3::unit_y() * -delta.y),
-                    shape.min_distance_from(p + Vector3::unit_z() * delta.z)
-                        - shape.min_distance_from(p +  Vector3::unit_z() * -delta.z),
-                ).normalize()
-            };
+        // `d_out` is positive or +0 and `d_in` is negative or -0, so the
+        // weight is in 0...1. Both can only be 0 at the same time if there
+        // is a surface point at both ends.
+        let (d_out, d_in) = (outside.1, inside.1);
+        let weight_inside = if d_out == d_in { 0.5 } else { d_out / (d_out - d_in) };
+        lerp(outside.0, inside.0, weight_inside)
+    }
+}
 
-            vertices.push(Vertex {
-                position: p.to_vec().to_arr(),
-                normal: normal.to_arr(),
-                distance_from_surface: dist_p,
-            });
+/// Maps corners of a grid to the coinciding corners of its parent's grid.
+///
+/// The parent's cells are twice as large, so only every other corner along
+/// each axis (one in eight corners) coincides with one of the parent's. All
+/// others have to be sampled.
+struct Seed<'a> {
+    parent: &'a DistanceGrid,
+
+    /// The position of this grid's first corner relative to the parent's,
+    /// in units of this grid's cells.
+    offset: [i64; 3],
+}
 
-            vertices.len() as u32 - 1
+impl<'a> Seed<'a> {
+    /// Returns `None` if the grids are not aligned as described above.
+    fn new(parent: &'a DistanceGrid, start: Point3<f64>, step: Vector3<f64>) -> Option<Self> {
+        const EPSILON: f64 = 1e-6;
+
+        let ratio = parent.step.div_element_wise(step);
+        let offset = (start - parent.start).div_element_wise(step);
+        let aligned = (0..3).all(|axis| {
+            (ratio[axis] - 2.0).abs() < EPSILON
+                && (offset[axis] - offset[axis].round()).abs() < EPSILON
         });
 
-        let before_third = Instant::now();
-
-
-        // Third step:
-        // ===========
-        //
-        // We already have all vertices, now we need to generate the faces
-        // of our resulting mesh. For each edge crossing the surface of our
-        // shape, we will generate one face. This face's vertices are the
-        // vertices inside the four cells the edge is adjacent to.
-        //
-        let mut indices = Vec::new();
-        for (x, y, z) in cube(resolution) {
-            // We iterate over all edges by iterating over all lower corners of
-            // all cells.
-            //
-            // About all those `unwrap()` calls: if the edge is crossing the
-            // surface (which is checked in the if conditions below), then we
-            // generated a vertex for all of the adjacent cells (as they,
-            // by definition, also cross the surface). So the Options we access
-            // are always `Some()`.
-
-            let base_sign = dists[(x, y, z)].is_sign_positive();
-
-            // Edge from the current corner pointing in +x direction
-            if y > 0 && z > 0 && base_sign != dists[(x + 1, y, z)].is_sign_positive()  {
-                let v0 = points[(x, y - 1, z - 1)];
-                let v1 = points[(x, y - 1, z    )];
-                let v2 = points[(x, y    , z - 1)];
-                let v3 = points[(x, y    , z    )];
-
-                indices.extend_from_slice(&
-                    // distance negative, triangle cw
-                    if dists[(x, y, z)] < 0.0 {
-                        [
-                            v0, v2, v1,
-                            v1, v2, v3,
-                        ]
-                    } else {
-                        // ccw
-                        [
-                            v0, v1, v2,
-                            v1, v3, v2,
-                        ]
-                    }
-                );
-            }
+        if !aligned {
+            return None;
+        }
 
-            // Edge from the current corner pointing in +y direction
-            if x > 0 && z > 0 && base_sign != dists[(x, y + 1, z)].is_sign_positive()  {
-                let v0 = points[(x - 1, y, z - 1)];
-                let v1 = points[(x - 1, y, z    )];
-                let v2 = points[(x,     y, z - 1)];
-                let v3 = points[(x,     y, z    )];
-
-                indices.extend_from_slice(&
-                    // distance negative, triangle cw
-                    if dists[(x, y, z)] < 0.0 {
-                        [
-                            v0, v1, v2,
-                            v1, v3, v2,
-                        ]
-                    } else {
-                        // ccw
-                        [
-                            v0, v2, v1,
-                            v1, v2, v3,
-                        ]
-                    }
-                );
-            }
+        let offset = [0, 1, 2].map(|axis| offset[axis].round() as i64);
+        Some(Self { parent, offset })
+    }
 
-            // Edge from the current corner pointing in +z direction
-            if x > 0 && y > 0 && base_sign != dists[(x, y, z + 1)].is_sign_positive()  {
-                let v0 = points[(x - 1, y - 1, z)];
-                let v1 = points[(x - 1, y    , z)];
-                let v2 = points[(x,     y - 1, z)];
-                let v3 = points[(x,     y    , z)];
-
-                indices.extend_from_slice(&
-                    // distance negative, triangle cw
-                    if dists[(x, y, z)] < 0.0 {
-                        [
-                            v0, v2, v1,
-                            v1, v2, v3,
-                        ]
-                    } else {
-                        // ccw
-                        [
-                            v0, v1, v2,
-                            v1, v3, v2,
-                        ]
-                    }
-                );
+    /// Returns the parent's sample at the given corner, if there is one.
+    fn get(&self, (x, y, z): (u32, u32, u32)) -> Option<f64> {
+        let size = self.parent.dists.size() as i64;
+        let mut parent_corner = [0; 3];
+        for (axis, &c) in [x, y, z].iter().enumerate() {
+            let k = self.offset[axis] + c as i64;
+            if k % 2 != 0 || k < 0 || k / 2 >= size {
+                return None;
             }
+            parent_corner[axis] = (k / 2) as u32;
         }
 
-        let after_third = Instant::now();
-        let timings = Timings {
-            first: before_second - before_first,
-            second: before_third - before_second,
-            third: after_third -  before_third,
-            vertices: vertices.len() as u32,
-            faces: indices.len() as u32 / 6,
-        };
+        let [px, py, pz] = parent_corner;
+        Some(self.parent.dists[(px, py, pz)])
+    }
+}
 
-        trace!(
-            "Generated {:6} points, {:6} faces in {}",
-            vertices.len(),
-            indices.len() / 6,
-            timings,
-        );
+/// The points where the edges of a `DistanceGrid` cross the surface. Each
+/// crossing is only searched once, even though it's shared by up to four
+/// cells.
+pub(super) struct EdgeCrossings {
+    /// For each axis, the index into `points` of the crossing on the edge
+    /// from each corner in the positive direction of the axis (or `u32::MAX`
+    /// if that edge doesn't cross the surface).
+    indices: [GridTable<u32>; 3],
+    pub(super) points: Vec<Point3<f64>>,
+}
+
+impl EdgeCrossings {
+    pub(super) fn find(grid: &DistanceGrid, shape: &dyn Shape, refinement: EdgeRefinement) -> Self {
+        let size = grid.dists.size();
+        let mut points = Vec::new();
+        let indices = [0, 1, 2].map(|axis| {
+            GridTable::fill_with(size, |x, y, z| {
+                let from = (x, y, z);
+                let to = match axis {
+                    0 if x + 1 < size => (x + 1, y, z),
+                    1 if y + 1 < size => (x, y + 1, z),
+                    2 if z + 1 < size => (x, y, z + 1),
+                    _ => return u32::MAX,
+                };
+
+                if grid.is_outside(from) == grid.is_outside(to) {
+                    return u32::MAX;
+                }
+
+                points.push(grid.edge_crossing(from, to, shape, refinement));
+                points.len() as u32 - 1
+            })
+        });
 
-        (MeshBuffer { vertices, indices }, timings)
+        Self { indices, points }
+    }
+
+    /// Returns the index of the crossing on the edge from `corner` in the
+    /// positive direction of `axis`, if that edge crosses the surface.
+    pub(super) fn index(&self, corner: (u32, u32, u32), axis: usize) -> Option<u32> {
+        Some(self.indices[axis][corner]).filter(|&i| i != u32::MAX)
+    }
+
+    /// Like `index`, but returns the crossing itself.
+    pub(super) fn get(&self, corner: (u32, u32, u32), axis: usize) -> Option<Point3<f64>> {
+        self.index(corner, axis).map(|i| self.points[i as usize])
+    }
+}
+
+/// Creates the vertex at `p`, calculating the normal and some meta data
+/// which might be used to color the vertex. `step` is the size of a cell.
+pub(super) fn vertex_at(
+    shape: &dyn Shape,
+    p: Point3<f64>,
+    origin: Point3<f64>,
+    step: Vector3<f64>,
+) -> Vertex {
+    let (dist_p, color) = shape.colored_distance_from(p);
+
+    // Shapes which can't calculate the normal analytically use central
+    // differences, with a step size a bit smaller than a cell.
+    let normal = shape.normal_at(p, 0.7 * step.x);
+
+    // Only the position relative to the origin is converted to `f32`.
+    Vertex {
+        position: (p - origin).cast::<f32>().unwrap().to_arr(),
+        normal: normal.cast::<f32>().unwrap().to_arr(),
+        distance_from_surface: dist_p as f32,
+        iterations: color.map_or(-1.0, |c| c.iterations as f32),
+        smooth_iterations: color.map_or(0.0, |c| c.smooth_iterations),
+        orbit_trap: color.map_or([0.0; 4], |c| {
+            [c.trap_planes.x, c.trap_planes.y, c.trap_planes.z, c.trap_origin]
+        }),
     }
 }
 
@@ -395,13 +485,16 @@ impl MeshBuffer {
 /// Stores some information about how long various passes of the mesh
 /// generation algorithm were running as well as how many vertices and faces
 /// were created.
+///
+/// What the passes are depends on the mesher, but the first one always
+/// samples the shape on the grid.
 #[derive(Default, Clone, Copy)]
 pub struct Timings {
-    first: Duration,
-    second: Duration,
-    third: Duration,
-    vertices: u32,
-    faces: u32,
+    pub(super) first: Duration,
+    pub(super) second: Duration,
+    pub(super) third: Duration,
+    pub(super) vertices: u32,
+    pub(super) faces: u32,
 }
 
 impl fmt::Display for Timings {
@@ -432,3 +525,167 @@ impl ops::Add for Timings {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use cgmath::{prelude::*, Point3, Vector3};
+
+    use crate::shape::{Shape, Sphere};
+    use super::super::{EdgeRefinement, MeshAlgorithm};
+    use super::{DistanceGrid, MeshBuffer};
+
+    #[test]
+    fn closed_and_oriented() {
+        let sphere = Sphere::new(Point3::new(0.1, -0.2, 0.05), 1.0);
+        let span = Point3::new(-1.5, -1.5, -1.5)..Point3::new(1.5, 1.5, 1.5);
+        let resolution = 16;
+        let cell = 3.0 / resolution as f64;
+
+        let algorithms = [
+            MeshAlgorithm::SurfaceNets,
+            MeshAlgorithm::DualContouring,
+            MeshAlgorithm::MarchingCubes,
+        ];
+        for &algorithm in &algorithms {
+            let mesher = algorithm.mesher(EdgeRefinement::Linear);
+            let (buf, _) = MeshBuffer::generate_for_box(&span, &sphere, resolution, &*mesher, None);
+            let position = |i: u32| {
+                buf.origin + Vector3::from(buf.vertices[i as usize].position).cast().unwrap()
+            };
+
+            assert!(!buf.indices.is_empty());
+            // Vertices are inside of cells crossing the surface (the surface
+            // nets cells are a bit larger than `cell`).
+            for v in 0..buf.vertices.len() as u32 {
+                let d = sphere.min_distance_from_f64(position(v));
+                assert!(d.abs() < 2.0 * cell, "{:?}: vertex {} is off by {}", algorithm, v, d);
+            }
+
+            // Each edge has to be used exactly once in both directions.
+            let mut edges = HashMap::new();
+            for t in buf.indices.chunks(3) {
+                for i in 0..3 {
+                    *edges.entry((t[i], t[(i + 1) % 3])).or_insert(0) += 1;
+                }
+            }
+            for (&(a, b), &count) in &edges {
+                assert_eq!(count, 1, "{:?}: edge {}-{} used {} times", algorithm, a, b, count);
+                assert_eq!(edges.get(&(b, a)), Some(&1), "{:?}: mesh not closed", algorithm);
+            }
+
+            // The triangles face outwards, so the volume is positive. It's
+            // close to the sphere's volume (a bit smaller, as the vertices of
+            // surface nets are inside of the convex sphere).
+            let volume = buf.indices.chunks(3)
+                .map(|t| {
+                    let [a, b, c] = [position(t[0]), position(t[1]), position(t[2])];
+                    a.to_vec().dot(b.to_vec().cross(c.to_vec())) / 6.0
+                })
+                .sum::<f64>();
+            let expected = 4.0 / 3.0 * std::f64::consts::PI;
+            assert!((volume / expected - 1.0).abs() < 0.1, "{:?}: volume {}", algorithm, volume);
+        }
+    }
+
+    #[test]
+    fn skirts() {
+        // The sphere is only cut by the +x face of the box.
+        let sphere = Sphere::new(Point3::origin(), 1.0);
+        let span = Point3::new(-1.5, -1.5, -1.5)..Point3::new(0.0, 1.5, 1.5);
+        let algorithms = [
+            MeshAlgorithm::SurfaceNets,
+            MeshAlgorithm::DualContouring,
+            MeshAlgorithm::MarchingCubes,
+        ];
+        for &algorithm in &algorithms {
+            let mesher = algorithm.mesher(EdgeRefinement::Linear);
+            let (mut buf, _) = MeshBuffer::generate_for_box(&span, &sphere, 16, &*mesher, None);
+
+            let mut edges = HashSet::new();
+            for t in buf.indices.chunks(3) {
+                for i in 0..3 {
+                    edges.insert((t[i], t[(i + 1) % 3]));
+                }
+            }
+            let boundary = edges.iter().filter(|&&(a, b)| !edges.contains(&(b, a))).count();
+            assert!(boundary > 0);
+
+            // No skirts on faces without any mesh
+            let (vertices, indices) = (buf.vertices.len(), buf.indices.len());
+            let mut depths = [Some(0.1); 6];
+            depths[1] = None;
+            buf.add_skirts(&span, depths);
+            assert_eq!(buf.indices.len(), indices, "{:?}", algorithm);
+
+            // Each boundary edge is extruded into two quads (one per side),
+            // which hang into the shape.
+            buf.add_skirts(&span, [None, Some(0.1), None, None, None, None]);
+            assert_eq!(buf.indices.len(), indices + 12 * boundary, "{:?}", algorithm);
+            for v in &buf.vertices[vertices..] {
+                let p = buf.origin + Vector3::from(v.position).cast().unwrap();
+                assert!(sphere.min_distance_from_f64(p) < 0.0, "{:?}: {:?}", algorithm, p);
+            }
+        }
+    }
+
+    #[test]
+    fn refinement() {
+        // The vertices of marching cubes are exactly the edge crossings.
+        let sphere = Sphere::new(Point3::new(0.1, -0.2, 0.05), 1.0);
+        let span = Point3::new(-1.5, -1.5, -1.5)..Point3::new(1.5, 1.5, 1.5);
+        let max_error = |refinement| {
+            let mesher = MeshAlgorithm::MarchingCubes.mesher(refinement);
+            let (buf, _) = MeshBuffer::generate_for_box(&span, &sphere, 16, &*mesher, None);
+            buf.vertices.iter()
+                .map(|v| {
+                    let p = buf.origin + Vector3::from(v.position).cast().unwrap();
+                    sphere.min_distance_from_f64(p).abs()
+                })
+                .fold(0.0, f64::max)
+        };
+
+        assert!(max_error(EdgeRefinement::Linear) < 1e-2);
+        assert!(max_error(EdgeRefinement::Bisection { steps: 12 }) < 1e-5);
+        assert!(max_error(EdgeRefinement::SphereTracing { steps: 32 }) < 1e-4);
+    }
+
+    #[test]
+    fn seeded_grid() {
+        // The parent is sampled from another shape, so that we can tell
+        // which samples were reused.
+        let parent_span = Point3::new(-1.0, -1.0, -1.0)..Point3::new(1.0, 1.0, 1.0);
+        let other = Sphere::new(Point3::origin(), 2.0);
+        let parent = DistanceGrid::sample(&parent_span, &other, 8, None);
+        let sphere = Sphere::new(Point3::origin(), 0.5);
+
+        // A child grid starting at a corner of the parent, one starting in
+        // between two corners (and reaching outside of the parent) and one
+        // not aligned at all.
+        let cell = 0.125;
+        let cases = [
+            (Point3::new(-1.0, 0.0, -1.0), true),
+            (Point3::new(-0.875, -0.25, 0.375), true),
+            (Point3::new(-0.9, 0.0, 0.0), false),
+        ];
+        for &(start, aligned) in &cases {
+            let span = start..start + Vector3::new(8.0, 8.0, 8.0) * cell;
+            let child = DistanceGrid::sample(&span, &sphere, 8, Some(&parent));
+            let offset = (start - parent_span.start) / cell;
+
+            for (x, y, z) in crate::util::iter::cube(9) {
+                let c = Vector3::new(x as f64, y as f64, z as f64) + offset;
+                let p = child.corner((x, y, z));
+                let from_parent = aligned
+                    && (0..3).all(|axis| c[axis].round() as i64 % 2 == 0 && c[axis] <= 16.0);
+                let expected = if from_parent {
+                    parent.dists[((c.x / 2.0) as u32, (c.y / 2.0) as u32, (c.z / 2.0) as u32)]
+                } else {
+                    sphere.min_distance_from_f64(p)
+                };
+                assert!((child.dists[(x, y, z)] - expected).abs() < 1e-6, "at {:?}", p);
+            }
+        }
+    }
+}
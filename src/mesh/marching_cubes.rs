@@ -0,0 +1,172 @@
+use std::time::Instant;
+
+use crate::{
+    shape::Shape,
+    octree::Span,
+    util::iter::cube,
+};
+use super::{
+    EdgeRefinement,
+    buffer::{self, DistanceGrid, EdgeCrossings, MeshBuffer, Mesher, Timings, EDGES},
+};
+
+
+/// The corners of each face of a cell (see `EDGES` for the corner indices),
+/// counter-clockwise when seen from outside of the cell.
+const FACES: [[u8; 4]; 6] = [
+    [0, 1, 3, 2],   // -x
+    [4, 6, 7, 5],   // +x
+    [0, 4, 5, 1],   // -y
+    [2, 3, 7, 6],   // +y
+    [0, 2, 6, 4],   // -z
+    [1, 5, 7, 3],   // +z
+];
+
+/// Implementation of the classic "Marching Cubes" algorithm [1].
+///
+/// Unlike with surface nets, the vertices are placed on the edges of the
+/// grid (one per edge crossing the surface) and each cell is triangulated
+/// on its own, depending on which of its eight corners are inside of the
+/// shape. Instead of hard coding the usual table with the triangles for all
+/// 256 cases, we derive it when creating the mesher (see `triangulate`).
+///
+/// As the vertices on the boundary of the box only depend on the samples on
+/// the boundary, neighboring boxes of the same size fit together without
+/// any overlap.
+///
+/// [1]: http://paulbourke.net/geometry/polygonise/
+pub struct MarchingCubes {
+    /// The triangles for each case, as indices into `EDGES`. Bit `i` of the
+    /// case is set if corner `i` is inside of the shape.
+    table: Vec<Vec<[u8; 3]>>,
+    refinement: EdgeRefinement,
+}
+
+impl MarchingCubes {
+    pub fn new(refinement: EdgeRefinement) -> Self {
+        Self {
+            table: (0..=255).map(triangulate).collect(),
+            refinement,
+        }
+    }
+}
+
+impl Mesher for MarchingCubes {
+    fn mesh(
+        &self,
+        span: &Span,
+        shape: &dyn Shape,
+        resolution: u32,
+        parent: Option<&DistanceGrid>,
+    ) -> (MeshBuffer, Timings) {
+        let before_first = Instant::now();
+
+        // First step: sample the shape at all corners of the grid.
+        let grid = DistanceGrid::sample(span, shape, resolution, parent);
+
+        let before_second = Instant::now();
+
+        // Second step: create one vertex for each edge of the grid crossing
+        // the surface. The vertex indices are the same as the crossing
+        // indices.
+        let crossings = EdgeCrossings::find(&grid, shape, self.refinement);
+        let vertices = crossings.points.iter()
+            .map(|&p| buffer::vertex_at(shape, p, span.start, grid.step))
+            .collect::<Vec<_>>();
+
+        let before_third = Instant::now();
+
+        // Third step: triangulate each cell according to the table.
+        let mut indices = Vec::new();
+        for (x, y, z) in cube(resolution) {
+            let corner = |c: u8| {
+                let (dx, dy, dz) = buffer::corner_offset(c);
+                (x + dx, y + dy, z + dz)
+            };
+
+            let case = (0..8)
+                .filter(|&c| !grid.is_outside(corner(c)))
+                .fold(0usize, |case, c| case | (1 << c));
+
+            for triangle in &self.table[case] {
+                // `EDGES` is ordered by axis and the first corner of each edge
+                // is the lower one.
+                indices.extend(triangle.iter().map(|&edge| {
+                    let axis = edge as usize / 4;
+                    crossings.index(corner(EDGES[edge as usize].0), axis).unwrap()
+                }));
+            }
+        }
+
+        let after_third = Instant::now();
+        let timings = Timings {
+            first: before_second - before_first,
+            second: before_third - before_second,
+            third: after_third - before_third,
+            vertices: vertices.len() as u32,
+            faces: indices.len() as u32 / 3,
+        };
+
+        (MeshBuffer { origin: span.start, vertices, indices, grid }, timings)
+    }
+}
+
+/// Returns the triangles (as indices into `EDGES`) for the given case (see
+/// `MarchingCubes::table`).
+///
+/// On each face of the cell, the surface is a line segment from each edge
+/// where we enter the inside (walking counter-clockwise around the face) to
+/// the next edge where we leave it again. If a face has two inside corners
+/// diagonally opposite of each other, this separates them. As both cells
+/// sharing a face see the same corners, their segments always match.
+///
+/// Each edge crossing the surface is the start of exactly one segment (on
+/// one of its two faces) and the end of exactly one (on the other face), so
+/// the segments form closed loops around the inside corners. We triangulate
+/// each loop as a fan. The orientation of the segments makes all triangles
+/// counter-clockwise when seen from outside of the shape.
+fn triangulate(case: u8) -> Vec<[u8; 3]> {
+    let inside = |corner: u8| case & (1 << corner) != 0;
+    let edge_index = |a: u8, b: u8| {
+        EDGES.iter().position(|&e| e == (a, b) || e == (b, a)).unwrap() as u8
+    };
+
+    // The edge following each edge in its loop.
+    let mut next = [None; 12];
+    for face in &FACES {
+        let edge = |i: usize| (face[i % 4], face[(i + 1) % 4]);
+        for i in 0..4 {
+            let (a, b) = edge(i);
+            if inside(a) || !inside(b) {
+                continue;
+            }
+
+            let leaving = (i + 1..i + 4).map(edge).find(|&(c, d)| inside(c) && !inside(d));
+            let (c, d) = leaving.unwrap();
+            next[edge_index(a, b) as usize] = Some(edge_index(c, d));
+        }
+    }
+
+    let mut triangles = Vec::new();
+    let mut visited = [false; 12];
+    for start in 0..12 {
+        if visited[start] || next[start].is_none() {
+            continue;
+        }
+
+        let mut polygon = vec![start as u8];
+        visited[start] = true;
+        let mut edge = next[start].unwrap();
+        while edge as usize != start {
+            visited[edge as usize] = true;
+            polygon.push(edge);
+            edge = next[edge as usize].unwrap();
+        }
+
+        for i in 1..polygon.len() - 1 {
+            triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+
+    triangles
+}
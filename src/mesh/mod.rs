@@ -1,24 +1,154 @@
 use cgmath::{prelude::*, Point3, Vector3};
 use num_cpus;
-use std::{array::IntoIter, sync::mpsc::{channel, Receiver, Sender}};
+use serde::{Deserialize, Serialize};
+use std::{array::IntoIter, ops::Range, sync::mpsc::{channel, Receiver, Sender}};
 use std::sync::Arc;
 use threadpool::ThreadPool;
 
 use crate::{
     prelude::*,
     camera::Camera,
-    octree::{Octree, SpanExt},
+    octree::{Octree, Span, SpanExt},
     shape::Shape,
     util::iter,
     wgpu::DrawContext,
 };
 
 mod buffer;
+mod marching_cubes;
+mod qef;
+mod surface_nets;
 mod view;
 
-use self::buffer::{MeshBuffer, Timings};
+use self::buffer::{DistanceGrid, MeshBuffer, Mesher, Timings};
+use self::marching_cubes::MarchingCubes;
+use self::surface_nets::{DualContouring, SurfaceNets};
 use self::view::MeshView;
 
+/// Settings controlling the level of detail of the mesh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeshSettings {
+    /// Number of cells along each axis of the grid of each octree leaf.
+    pub resolution: u32,
+
+    /// The number of focus points used to determine which octree nodes are
+    /// to be split (drawn in higher resolution). In the end `focus_points²`
+    /// points are distributed over the near plane.
+    pub focus_points: u8,
+
+    /// The algorithm used to turn the sampled distance field into a mesh.
+    pub algorithm: MeshAlgorithm,
+
+    /// How exactly the points where grid edges cross the surface are
+    /// searched.
+    pub refinement: EdgeRefinement,
+
+    /// If set, the mesh is only generated inside this box. This is required
+    /// for shapes with an infinite bounding box (e.g. infinite repetition).
+    pub region: Option<Range<Point3<f32>>>,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 64,
+            focus_points: 5,
+            algorithm: MeshAlgorithm::SurfaceNets,
+            refinement: EdgeRefinement::Linear,
+            region: None,
+        }
+    }
+}
+
+/// The algorithms available to generate the mesh (see `Mesher`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshAlgorithm {
+    /// Fast, but rounds off sharp edges and corners.
+    SurfaceNets,
+
+    /// Preserves sharp features (e.g. of IFS fractals or boxes), but needs
+    /// additional shape queries (the normals at all edge crossings).
+    DualContouring,
+
+    /// The classic algorithm. Results in more and often thinner triangles
+    /// than surface nets.
+    MarchingCubes,
+}
+
+impl MeshAlgorithm {
+    /// Returns the algorithm after this one (to cycle through all of them).
+    pub fn next(self) -> Self {
+        match self {
+            MeshAlgorithm::SurfaceNets => MeshAlgorithm::DualContouring,
+            MeshAlgorithm::DualContouring => MeshAlgorithm::MarchingCubes,
+            MeshAlgorithm::MarchingCubes => MeshAlgorithm::SurfaceNets,
+        }
+    }
+
+    fn mesher(self, refinement: EdgeRefinement) -> Arc<dyn Mesher> {
+        match self {
+            MeshAlgorithm::SurfaceNets => Arc::new(SurfaceNets { refinement }),
+            MeshAlgorithm::DualContouring => Arc::new(DualContouring { refinement }),
+            MeshAlgorithm::MarchingCubes => Arc::new(MarchingCubes::new(refinement)),
+        }
+    }
+}
+
+/// How the points where grid edges cross the surface are searched. All
+/// meshers use these points to place their vertices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeRefinement {
+    /// Interpolates linearly between the distances at both corners. This
+    /// needs no additional shape queries, but is quite inaccurate for shapes
+    /// whose DE is far from linear (e.g. the mandelbulb).
+    Linear,
+
+    /// Halves the part of the edge containing the crossing `steps` times
+    /// before interpolating linearly.
+    Bisection { steps: u8 },
+
+    /// Sphere traces from the outside corner towards the inside one with at
+    /// most `steps` steps before interpolating linearly. Converges quickly
+    /// if the DE is close to the real distance and the edge crosses the
+    /// surface steeply, but slowly for edges almost parallel to it.
+    SphereTracing { steps: u8 },
+}
+
+impl MeshSettings {
+    /// Makes sure these settings can be used to generate a mesh.
+    pub fn validate(&self) -> Result<()> {
+        if !self.resolution.is_power_of_two() {
+            bail!("mesh resolution has to be a power of two, but is {}", self.resolution);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the region in which the mesh of `shape` is generated: its
+    /// bounding box, clamped to `region` if that is set. Fails if the result
+    /// is not finite or empty.
+    pub fn region_for(&self, shape: &dyn Shape) -> Result<Range<Point3<f32>>> {
+        let mut bb = shape.bounding_box();
+        if let Some(region) = &self.region {
+            for axis in 0..3 {
+                bb.start[axis] = bb.start[axis].max(region.start[axis]);
+                bb.end[axis] = bb.end[axis].min(region.end[axis]);
+            }
+        }
+
+        let finite = |p: Point3<f32>| p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+        if !finite(bb.start) || !finite(bb.end) {
+            bail!("the shape is unbounded, please set a finite `region` in the mesh settings");
+        }
+        if !(0..3).all(|axis| bb.start[axis] < bb.end[axis]) {
+            bail!("the mesh `region` doesn't overlap the bounding box of the shape");
+        }
+
+        Ok(bb)
+    }
+}
+
 /// Type to manage the graphical representation of the shape. It updates the
 /// internal data depending on the camera position and resolution.
 pub struct ShapeMesh {
@@ -28,12 +158,20 @@ pub struct ShapeMesh {
 
     /// The shape this mesh represents.
     shape: Arc<dyn Shape>,
+    settings: MeshSettings,
+
+    /// The implementation of `settings.algorithm`.
+    mesher: Arc<dyn Mesher>,
+
+    /// Incremented each time the shape or the algorithm changes (see
+    /// `set_shape`). Views generated for older versions are regenerated.
+    shape_version: u64,
 
     // The following fields are simply to manage the generation of the mesh on
     // multiple threads.
     thread_pool: ThreadPool,
-    new_meshes: Receiver<(Point3<f32>, (MeshView, Timings))>,
-    mesh_tx: Sender<(Point3<f32>, (MeshView, Timings))>,
+    new_meshes: Receiver<MeshJobResult>,
+    mesh_tx: Sender<MeshJobResult>,
     active_jobs: u64,
 
     // These are just for debugging/time measuring purposes
@@ -46,10 +184,12 @@ impl ShapeMesh {
         device: &wgpu::Device,
         out_format: wgpu::TextureFormat,
         shape: Arc<dyn Shape>,
+        settings: MeshSettings,
     ) -> Result<Self> {
         // Setup an empty tree and split the first two levels which results in
         // 8² = 64 children
-        let mut tree = Octree::spanning(shape.bounding_box());
+        let bb = settings.region_for(&*shape)?;
+        let mut tree = Octree::spanning(bb.start.cast().unwrap()..bb.end.cast().unwrap());
         let _ = tree.root_mut().split(None);
         for mut child in IntoIter::new(tree.root_mut().into_children().unwrap()) {
             child.split(None);
@@ -68,6 +208,9 @@ impl ShapeMesh {
             tree,
             pipeline,
             shape,
+            mesher: settings.algorithm.mesher(settings.refinement),
+            settings,
+            shape_version: 0,
             thread_pool: pool,
             new_meshes: rx,
             mesh_tx: tx,
@@ -77,27 +220,53 @@ impl ShapeMesh {
         })
     }
 
+    /// Replaces the shape, e.g. because it's animated. All existing views are
+    /// still drawn until the new ones are generated.
+    ///
+    /// The octree is not changed, so the new shape should be inside the
+    /// bounding box of the original one.
+    pub fn set_shape(&mut self, shape: Arc<dyn Shape>) {
+        self.shape = shape;
+        self.shape_version += 1;
+    }
+
+    /// Switches to another meshing algorithm. Like with `set_shape`, the
+    /// existing views are drawn until the new ones are generated.
+    pub fn set_algorithm(&mut self, algorithm: MeshAlgorithm) {
+        self.settings.algorithm = algorithm;
+        self.mesher = algorithm.mesher(self.settings.refinement);
+        self.shape_version += 1;
+    }
+
     /// Updates the mesh representing the shape. It increases resolution dynamically when
     /// camera is close to the objects surface.
     pub fn update(&mut self, device: Arc<wgpu::Device>, camera: &Camera) {
-        /// Constant that corresponds to the amount of focus points used to determine which octree
-        /// nodes are to be split (drawn in higher resolution). In the end FOCUS_POINTS² points
-        /// are distributed over the near plane.
-        const FOCUS_POINTS: u8 = 5;
-
         // Get focus points on the near plane. Through these points, distances from the camera to
         // nodes of the octree are calculated. When the distance is under a certain threshold, that
         // particular node is redrawn with higher resolution.
-        let focii = self.get_focii(camera, FOCUS_POINTS);
-        for focus in focii {
+        let focii = self.get_focii(camera, self.settings.focus_points);
+        for &focus in &focii {
             if let Some(mut leaf) = self.tree.leaf_around_mut(focus) {
-                if let Some(MeshStatus::Ready(_)) = leaf.leaf_data().unwrap() {
+                if let Some(MeshStatus::Ready { .. }) = leaf.leaf_data().unwrap() {
                     let dist = camera.position.distance(focus);
                     let span = leaf.span();
                     let threshold = 2.0 * (span.end.x - span.start.x).abs();
                     // If we are near enough to the surface, increase resolution.
                     if dist < threshold {
-                        leaf.split(None);
+                        // The children's grids are seeded with the parent's
+                        // samples (if they are still up to date).
+                        let parent = match leaf.split(None) {
+                            Some(MeshStatus::Ready { grid: Some(grid), shape_version, .. })
+                                if shape_version == self.shape_version => Some(grid),
+                            _ => None,
+                        };
+                        if let Some(grid) = parent {
+                            for child in leaf.into_children().unwrap().iter_mut() {
+                                *child.leaf_data_mut().unwrap() = Some(MeshStatus::Split {
+                                    parent_grid: grid.clone(),
+                                });
+                            }
+                        }
                     }
                 }
             }
@@ -107,17 +276,32 @@ impl ShapeMesh {
         let finished_jobs_before = self.finished_jobs;
 
         // Collect generated meshes and prepare them for rendering.
-        for (center, (view, timings)) in self.new_meshes.try_iter() {
+        for result in self.new_meshes.try_iter() {
             self.active_jobs -= 1;
             self.finished_jobs += 1;
-            self.batch_timings = self.batch_timings + timings;
+            self.batch_timings = self.batch_timings + result.timings;
 
             *self.tree
-                .leaf_around_mut(center)
+                .leaf_around_mut(result.center)
                 // we know that `center` is within the bound of the octree
                 .unwrap()
                 .leaf_data_mut()
-                .unwrap() = Some(MeshStatus::Ready(view));
+                .unwrap() = Some(MeshStatus::Ready {
+                    view: result.view,
+                    grid: Some(result.grid),
+                    shape_version: result.shape_version,
+                });
+        }
+
+        // Only leaves around the focus points are split, so we drop the grids
+        // of all other leaves. Each one holds `(resolution + 1)³` samples,
+        // which adds up quickly.
+        for elem in self.tree.iter_mut() {
+            if let Some((span, Some(MeshStatus::Ready { grid, .. }))) = elem.into_leaf() {
+                if grid.is_some() && !focii.iter().any(|&focus| span.contains(focus)) {
+                    *grid = None;
+                }
+            }
         }
 
 
@@ -125,36 +309,75 @@ impl ShapeMesh {
         // of space (see #9, #8)
 
 
-        // Here we simply start a mesh generation job for each empty leaf node.
-        let empty_leaves = self.tree.iter_mut()
-            .filter_map(|n| n.into_leaf())
-            .filter(|&(_, ref leaf_data)| leaf_data.is_none());
-        for (span, leaf_data) in empty_leaves {
-            const RESOLUTION: u32 = 64;
+        // Here we simply start a mesh generation job for each empty leaf node
+        // and each leaf whose view was generated for an old shape. Leaves
+        // which are still waiting for a job are skipped, so that there is at
+        // most one job per leaf, even if the shape changes every frame.
+        //
+        // The spans are collected first, as we have to look at the neighbors
+        // of each leaf to decide where skirts are needed.
+        let shape_version = self.shape_version;
+        let outdated_leaves = self.tree.iter()
+            .filter(|n| n.is_leaf())
+            .filter(|n| match n.leaf_data() {
+                None | Some(MeshStatus::Split { .. }) => true,
+                Some(MeshStatus::Ready { shape_version: v, .. }) => *v != shape_version,
+                Some(MeshStatus::Requested { .. }) => false,
+            })
+            .map(|n| n.span())
+            .collect::<Vec<_>>();
+        for span in outdated_leaves {
+            let skirts = self.skirt_depths(&span);
+            let leaf_data = self.tree
+                .leaf_around_mut(span.center())
+                .unwrap()
+                .into_leaf_data()
+                .unwrap();
+
+            // The parent's grid is only set when the leaf was just split, and
+            // the leaf is split only if the parent is up to date.
+            let parent_grid = match leaf_data {
+                Some(MeshStatus::Split { parent_grid }) => Some(parent_grid.clone()),
+                _ => None,
+            };
 
             // Prepare values to be moved into the closure.
+            let resolution = self.settings.resolution;
+            let mesher = self.mesher.clone();
             let tx = self.mesh_tx.clone();
             let shape = self.shape.clone();
             let device = device.clone();
 
             // Generate the raw buffers on another thread.
             self.thread_pool.execute(move || {
-                let (buf, timings) = MeshBuffer::generate_for_box(&span, &*shape, RESOLUTION);
-                let view = MeshView::new(&device, &buf.vertices, &buf.indices);
+                let (mut buf, timings) = MeshBuffer::generate_for_box(
+                    &span,
+                    &*shape,
+                    resolution,
+                    &*mesher,
+                    parent_grid.as_deref(),
+                );
+                buf.add_skirts(&span, skirts);
+                let view = MeshView::new(&device, buf.origin, &buf.vertices, &buf.indices);
 
                 // If the main thread hung up, it's fine: our thread will be
                 // killed soon, too.
-                let _ = tx.send((span.center(), (view, timings)));
+                let _ = tx.send(MeshJobResult {
+                    center: span.center(),
+                    view,
+                    grid: Arc::new(buf.grid),
+                    timings,
+                    shape_version,
+                });
             });
 
             self.active_jobs += 1;
 
             // If there has been an old view, we want to preserve it and
             // continue to render it until the new one is available. This
-            // doesn't make a lot of sense right now, but might be helpful
-            // later. Or it might not.
+            // avoids holes while the shape is changing.
             let old_view = match leaf_data.take() {
-                Some(MeshStatus::Ready(view)) => Some(view),
+                Some(MeshStatus::Ready { view, .. }) => Some(view),
                 _ => None,
             };
             *leaf_data = Some(MeshStatus::Requested { old_view });
@@ -165,7 +388,7 @@ impl ShapeMesh {
         }
 
         const PRINT_EVERY_FINISHED_JOBS: u64 = 64;
-        if self.finished_jobs % PRINT_EVERY_FINISHED_JOBS == 0
+        if self.finished_jobs.is_multiple_of(PRINT_EVERY_FINISHED_JOBS)
             && self.finished_jobs > 0
             && finished_jobs_before != self.finished_jobs {
             debug!(
@@ -177,6 +400,40 @@ impl ShapeMesh {
         }
     }
 
+    /// Returns the depths of the skirts needed on the faces of the given
+    /// leaf (see `MeshBuffer::add_skirts`).
+    ///
+    /// Only the finer of two neighboring leaves adds a skirt: it is always
+    /// meshed after the coarser one was created, as leaves are only ever
+    /// split. If the coarser neighbor is split later, the skirt stays, but
+    /// it's hidden inside of the shape. The skirt reaches one of the
+    /// neighbor's cells deep, as the vertices of both meshes are usually
+    /// closer than that to the surface.
+    fn skirt_depths(&self, span: &Span) -> [Option<f64>; 6] {
+        let resolution = self.settings.resolution as f64;
+        let center = span.center();
+
+        let mut depths = [None; 6];
+        for (face, depth) in depths.iter_mut().enumerate() {
+            // A point half a cell beyond the center of the face.
+            let axis = face / 2;
+            let size = span.end[axis] - span.start[axis];
+            let sign = if face % 2 == 0 { -1.0 } else { 1.0 };
+            let mut p = center;
+            p[axis] += sign * (0.5 * size + 0.5 * size / resolution);
+
+            // Outside of the octree, there is nothing to connect to.
+            if let Some(neighbor) = self.tree.leaf_around(p) {
+                let neighbor_size = neighbor.span().end[axis] - neighbor.span().start[axis];
+                if neighbor_size > 1.5 * size {
+                    *depth = Some(neighbor_size / resolution);
+                }
+            }
+        }
+
+        depths
+    }
+
     // Draws the whole shape by traversing the internal octree.
     pub(crate) fn draw(
         &self,
@@ -191,7 +448,7 @@ impl ShapeMesh {
         for (leaf_data, _span) in it {
             match leaf_data {
                 // If there is a view available, render it.
-                &MeshStatus::Ready(ref view) |
+                &MeshStatus::Ready { ref view, .. } |
                 &MeshStatus::Requested { old_view: Some(ref view) } => {
                     view.draw(draw_ctx, camera, &self.pipeline);
                 }
@@ -202,10 +459,13 @@ impl ShapeMesh {
 
     /// Returns points on the near plane distributed in a grid. These points are given
     /// in world coordinates. The number of points returned is focus_points².
-    pub fn get_focii(&self, camera: &Camera, focus_points: u8) -> Vec<Point3<f32>> {
-        const EPSILON: f32 = 0.000_001;
+    pub fn get_focii(&self, camera: &Camera, focus_points: u8) -> Vec<Point3<f64>> {
         const MAX_ITERS: u64 = 100;
 
+        // The ray marching stops when we are this close to the surface. This
+        // is relative to the near plane to support deep zooms.
+        let epsilon = 0.025 * f64::from(camera.projection.near_plane);
+
         let (top_left, bottom_right) = camera.near_plane_bb();
         let (frustum_width, frustum_height) = camera.projection.near_plane_dimension();
         let size_horizontal = frustum_width / focus_points as f32;
@@ -222,18 +482,21 @@ impl ShapeMesh {
                     0.0,
                 ) + center_diff;
 
+                // The view transformation is camera relative, so this is
+                // the direction from the camera to the point on the near
+                // plane.
                 Point3::from_homogeneous(
                     inv_view_trans * center.to_homogeneous()
-                )
+                ).to_vec()
             })
-            .filter_map(|p| {
+            .filter_map(|dir| {
                 let mut pos = camera.position;
-                let dir = (p - camera.position).normalize();
+                let dir = dir.cast::<f64>().unwrap().normalize();
 
                 for _ in 0..MAX_ITERS {
-                    let distance = self.shape.min_distance_from(pos);
+                    let distance = self.shape.min_distance_from_f64(pos);
                     pos += dir * distance;
-                    if distance < EPSILON {
+                    if distance < epsilon {
                         return Some(pos);
                     }
                 }
@@ -244,10 +507,36 @@ impl ShapeMesh {
 }
 
 enum MeshStatus {
+    /// The leaf was just created by splitting its parent, but no job was
+    /// started yet.
+    Split {
+        parent_grid: Arc<DistanceGrid>,
+    },
     Requested {
         old_view: Option<MeshView>,
     },
-    Ready(MeshView),
+    Ready {
+        view: MeshView,
+
+        /// The samples the view was generated from. They are used to seed
+        /// the children's grids when the leaf is split. To save memory, they
+        /// are only kept while the leaf is around a focus point.
+        grid: Option<Arc<DistanceGrid>>,
+
+        /// The version of the shape the view was generated for (see
+        /// `ShapeMesh::shape_version`).
+        shape_version: u64,
+    },
+}
+
+/// Sent by the threads generating the mesh to the main thread.
+struct MeshJobResult {
+    /// The center of the octree leaf.
+    center: Point3<f64>,
+    view: MeshView,
+    grid: Arc<DistanceGrid>,
+    timings: Timings,
+    shape_version: u64,
 }
 
 
@@ -258,9 +547,47 @@ pub struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
     distance_from_surface: f32,
+
+    /// The coloring data of the shape (see `ColorData`). If the shape
+    /// doesn't provide any, `iterations` is negative.
+    iterations: f32,
+    smooth_iterations: f32,
+
+    /// The plane traps in `xyz` and the origin trap in `w`.
+    orbit_trap: [f32; 4],
 }
 
 // `Vertex` is inhabited, allows any bitpattern, has no padding, all fields are
 // `Pod`, and is `repr(C)`.
 unsafe impl bytemuck::Pod for Vertex {}
 unsafe impl bytemuck::Zeroable for Vertex {}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use crate::shape::{Repeat, Sphere};
+    use super::MeshSettings;
+
+    #[test]
+    fn region() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let infinite = Repeat::new(sphere.clone(), [2.0, 0.0, 0.0].into(), [0, 0, 0]);
+        let mut settings = MeshSettings::default();
+
+        let bb = settings.region_for(&sphere).unwrap();
+        assert_eq!(bb, Point3::new(-1.0, -1.0, -1.0)..Point3::new(1.0, 1.0, 1.0));
+        assert!(settings.region_for(&infinite).is_err());
+
+        settings.region = Some(Point3::new(0.5, -3.0, -3.0)..Point3::new(3.0, 3.0, 0.5));
+        let bb = settings.region_for(&sphere).unwrap();
+        assert_eq!(bb, Point3::new(0.5, -1.0, -1.0)..Point3::new(1.0, 1.0, 0.5));
+        let bb = settings.region_for(&infinite).unwrap();
+        assert_eq!(bb, Point3::new(0.5, -1.0, -1.0)..Point3::new(3.0, 1.0, 0.5));
+
+        // No overlap with the bounding box
+        settings.region = Some(Point3::new(2.0, -3.0, -3.0)..Point3::new(3.0, 3.0, 3.0));
+        assert!(settings.region_for(&sphere).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use cgmath::{prelude::*, Matrix3, Point3, Vector3};
+
+
+/// Eigenvalues of `AᵀA` smaller than this times the largest one are treated
+/// as 0. This corresponds to dropping singular values of `A` smaller than a
+/// tenth of the largest one.
+const EIGENVALUE_THRESHOLD: f64 = 0.01;
+
+/// Number of Jacobi sweeps used to diagonalize `AᵀA`. For 3×3 matrices, the
+/// method converges very quickly.
+const JACOBI_SWEEPS: usize = 6;
+
+/// The quadratic error function used by dual contouring.
+///
+/// Each edge crossing of a cell defines a plane (through the crossing
+/// point, orthogonal to the surface normal there). The QEF is the sum of the
+/// squared distances of a point from all these planes. Its minimum lies on
+/// sharp features of the surface: for a corner, it's the intersection of
+/// the three planes.
+///
+/// If the planes don't determine the minimum uniquely (e.g. on a flat or
+/// only slightly curved part of the surface), the solution closest to the
+/// centroid of all crossing points ("mass point") is chosen. This is done
+/// with a truncated pseudo inverse, which also makes the solution robust
+/// against almost parallel planes.
+#[derive(Clone, Copy)]
+pub(super) struct Qef {
+    /// `AᵀA` where the rows of `A` are the normals.
+    ata: Matrix3<f64>,
+
+    /// `Aᵀb` where `b[i] = n[i] · p[i]`.
+    atb: Vector3<f64>,
+
+    /// Sum of all points (to calculate the mass point).
+    point_sum: Vector3<f64>,
+    count: u32,
+}
+
+impl Qef {
+    pub(super) fn new() -> Self {
+        Self {
+            ata: Matrix3::zero(),
+            atb: Vector3::zero(),
+            point_sum: Vector3::zero(),
+            count: 0,
+        }
+    }
+
+    /// Adds the plane through `p` with the (normalized) normal `n`.
+    pub(super) fn add(&mut self, p: Point3<f64>, n: Vector3<f64>) {
+        // `n nᵀ`, column by column
+        self.ata += Matrix3::from_cols(n * n.x, n * n.y, n * n.z);
+        self.atb += n * n.dot(p.to_vec());
+        self.point_sum += p.to_vec();
+        self.count += 1;
+    }
+
+    /// Returns the point minimizing the QEF. Must not be called without
+    /// adding at least one plane.
+    pub(super) fn solve(&self) -> Point3<f64> {
+        assert!(self.count > 0, "solving empty QEF");
+
+        // We solve for the offset from the mass point: `AᵀA x = Aᵀb - AᵀA c`.
+        let mass_point = Point3::from_vec(self.point_sum / self.count as f64);
+        let rhs = self.atb - self.ata * mass_point.to_vec();
+        let (eigenvalues, eigenvectors) = symmetric_eigen(self.ata);
+
+        let max = eigenvalues.x.abs().max(eigenvalues.y.abs()).max(eigenvalues.z.abs());
+        let mut offset = Vector3::zero();
+        for i in 0..3 {
+            if eigenvalues[i].abs() > EIGENVALUE_THRESHOLD * max {
+                let v = eigenvectors[i];
+                offset += v * (v.dot(rhs) / eigenvalues[i]);
+            }
+        }
+
+        mass_point + offset
+    }
+}
+
+/// Diagonalizes the symmetric matrix `m` with the Jacobi eigenvalue method.
+/// Returns the eigenvalues and the corresponding eigenvectors (as columns).
+fn symmetric_eigen(m: Matrix3<f64>) -> (Vector3<f64>, Matrix3<f64>) {
+    // Note that cgmath matrices are indexed `[column][row]`. As all matrices
+    // but `rotation` are symmetric, it doesn't matter most of the time.
+    let mut a = m;
+    let mut v = Matrix3::identity();
+
+    for _ in 0..JACOBI_SWEEPS {
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if a[q][p].abs() < 1e-12 {
+                continue;
+            }
+
+            // Rotate in the p-q-plane such that `a[q][p]` becomes 0.
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[q][p]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            let mut rotation = Matrix3::identity();
+            rotation[p][p] = c;
+            rotation[q][q] = c;
+            rotation[q][p] = s;
+            rotation[p][q] = -s;
+
+            a = rotation.transpose() * a * rotation;
+            v = v * rotation;
+        }
+    }
+
+    (Vector3::new(a[0][0], a[1][1], a[2][2]), v)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{prelude::*, Point3, Vector3};
+    use super::Qef;
+
+    fn assert_close(actual: Point3<f64>, expected: Point3<f64>) {
+        assert!(
+            actual.distance(expected) < 1e-9,
+            "expected {:?}, got {:?}",
+            expected,
+            actual,
+        );
+    }
+
+    #[test]
+    fn corner() {
+        // Three (not axis aligned) planes meeting in one point. Points on the
+        // planes are far away from the corner, so the mass point is, too.
+        let corner = Point3::new(0.3, -0.2, 0.5);
+        let normals = [
+            Vector3::new(1.0, 0.2, 0.1).normalize(),
+            Vector3::new(-0.1, 1.0, 0.3).normalize(),
+            Vector3::new(0.2, -0.3, 1.0).normalize(),
+        ];
+
+        let mut qef = Qef::new();
+        for (i, &n) in normals.iter().enumerate() {
+            // Some vector in the plane
+            let tangent = n.cross(normals[(i + 1) % 3]).normalize();
+            qef.add(corner + tangent * 0.4, n);
+            qef.add(corner - tangent * 0.2 + n.cross(tangent) * 0.3, n);
+        }
+
+        assert_close(qef.solve(), corner);
+    }
+
+    #[test]
+    fn underdetermined() {
+        // All points in the plane `z = 1`: the solution is the mass point.
+        let mut qef = Qef::new();
+        for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)] {
+            qef.add(Point3::new(x, y, 1.0), Vector3::unit_z());
+        }
+        assert_close(qef.solve(), Point3::new(0.5, 1.0 / 3.0, 1.0));
+
+        // Two planes meeting in an edge along the y axis at `x = z = 1`. The
+        // solution is the mass point projected onto the edge.
+        let mut qef = Qef::new();
+        qef.add(Point3::new(1.0, 0.2, 0.5), Vector3::unit_x());
+        qef.add(Point3::new(1.0, 0.6, 0.3), Vector3::unit_x());
+        qef.add(Point3::new(0.5, 0.4, 1.0), Vector3::unit_z());
+        assert_close(qef.solve(), Point3::new(1.0, 0.4, 1.0));
+    }
+}
@@ -0,0 +1,289 @@
+use std::time::Instant;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::{
+    shape::Shape,
+    octree::Span,
+    util::{iter::cube, grid::GridTable},
+};
+use super::{
+    EdgeRefinement,
+    buffer::{self, DistanceGrid, EdgeCrossings, MeshBuffer, Mesher, Timings, EDGES},
+    qef::Qef,
+};
+
+
+/// Implementation of the "Surface Nets" algorithm.
+///
+/// In particular, in this implementation the position of the vertex inside
+/// the 3D-cell is simply the centroid of all edge crossings. This rather
+/// easy version is described [in this article][1] ("naive surface nets").
+///
+/// The article will also help understand this algorithm. Compared to
+/// other algorithms for rendering iso surfaces, this one is relatively
+/// easy to implement while still working fairly nice. It rounds off sharp
+/// edges and corners, though (see `DualContouring`).
+///
+/// [1]: https://0fps.net/2012/07/12/smooth-voxel-terrain-part-2/
+pub struct SurfaceNets {
+    pub refinement: EdgeRefinement,
+}
+
+impl Mesher for SurfaceNets {
+    fn mesh(
+        &self,
+        span: &Span,
+        shape: &dyn Shape,
+        resolution: u32,
+        parent: Option<&DistanceGrid>,
+    ) -> (MeshBuffer, Timings) {
+        dual_mesh(span, shape, resolution, parent, self.refinement, false)
+    }
+}
+
+/// Implementation of the "Dual Contouring" algorithm.
+///
+/// It works exactly like `SurfaceNets`, but additionally calculates the
+/// surface normal at each edge crossing and places the vertex at the point
+/// closest to all tangent planes (see [the paper][1] and `Qef`). This
+/// preserves sharp features (see #2), but needs more queries to the shape.
+///
+/// [1]: https://www.cs.rice.edu/~jwarren/papers/dualcontour.pdf
+pub struct DualContouring {
+    pub refinement: EdgeRefinement,
+}
+
+impl Mesher for DualContouring {
+    fn mesh(
+        &self,
+        span: &Span,
+        shape: &dyn Shape,
+        resolution: u32,
+        parent: Option<&DistanceGrid>,
+    ) -> (MeshBuffer, Timings) {
+        dual_mesh(span, shape, resolution, parent, self.refinement, true)
+    }
+}
+
+/// Both algorithms generate one vertex per cell crossing the surface and
+/// connect them in the same way, they only differ in where the vertex is
+/// placed inside its cell.
+fn dual_mesh(
+    span: &Span,
+    shape: &dyn Shape,
+    resolution: u32,
+    parent: Option<&DistanceGrid>,
+    refinement: EdgeRefinement,
+    sharp_features: bool,
+) -> (MeshBuffer, Timings) {
+    // Vertices are only generated inside of the grid, so the meshes of two
+    // neighboring boxes wouldn't touch. To avoid holes in between them, we
+    // add one cell on each side. The cells keep their size, so that the grid
+    // stays aligned with the parent's grid.
+    let cells = resolution + 2;
+    let span = {
+        let overflow = (span.end - span.start) / resolution as f64;
+        span.start + -overflow .. span.end + overflow
+    };
+
+    let before_first = Instant::now();
+
+    // First Step:
+    // ===========
+    //
+    // We partition our box into regular cells. For each corner in between
+    // the cells we calculate and save the estimated minimal distance from
+    // the shape.
+    let grid = DistanceGrid::sample(&span, shape, cells, parent);
+    let dists = &grid.dists;
+
+    let before_second = Instant::now();
+
+
+    // Second Step:
+    // ============
+    //
+    // Next, we will iterate over all cells of the box (unlike before
+    // where we iterated over corners). For each cell crossing the shape's
+    // surface, we will generate one vertex. The `points` grid table holds
+    // the index of the vertex corresponding to the cell, or `None` if the
+    // cell does not cross the surface.
+    //
+    // The vertex is placed depending on the points where the cell's edges
+    // cross the surface. These are shared by neighboring cells, so we search
+    // all of them first.
+    let crossings = EdgeCrossings::find(&grid, shape, refinement);
+    let mut vertices = Vec::new();
+
+    // The world space distance between two corners/between the
+    // center points of two cells.
+    let step = grid.step;
+
+    let points = GridTable::fill_with(cells, |x, y, z| {
+        let corner = |c: u8| {
+            let (dx, dy, dz) = buffer::corner_offset(c);
+            (x + dx, y + dy, z + dz)
+        };
+
+        // First, check if the current cell is only partially inside the
+        // shape (if the cell intersects the shape's surface). If that's
+        // not the case, we won't generate a vertex for this cell.
+        let first = grid.is_outside(corner(0));
+        let no_shape_crossing = (1..8).all(|c| grid.is_outside(corner(c)) == first);
+
+        if no_shape_crossing {
+            // FIXME
+            // This is a bit hacky, but we will never access this number
+            return u32::MAX;
+        }
+
+        // World position of this cell's lower corner
+        let p0 = grid.corner((x, y, z));
+
+        // Get all edge crossings. These are points where the edges of the
+        // current cell intersect the surface. `EDGES` is ordered by axis and
+        // the first corner of each edge is the lower one.
+        let edge_crossings = EDGES.iter()
+            .enumerate()
+            .filter_map(|(i, &(from, _))| crossings.get(corner(from), i / 4));
+
+        let p = if sharp_features {
+            // The normals have to be sampled more locally than the vertex
+            // normals, otherwise sharp edges would be smoothed out again.
+            let qef = edge_crossings.fold(Qef::new(), |mut qef, p| {
+                qef.add(p, shape.normal_at(p, 0.1 * step.x));
+                qef
+            });
+
+            // The QEF's minimum can be outside of the cell if the normals are
+            // inaccurate or the cell contains more than one feature. Clamping
+            // it results in a valid mesh.
+            let p = qef.solve();
+            let end = p0 + step;
+            Point3::new(
+                p.x.max(p0.x).min(end.x),
+                p.y.max(p0.y).min(end.y),
+                p.z.max(p0.z).min(end.z),
+            )
+        } else {
+            // As described in the article above, we simply use the centroid
+            // of all edge crossings.
+            let (count, total_displacement) = edge_crossings.fold(
+                (0, Vector3::zero()),
+                |(count, sum), p| (count + 1, sum + p.to_vec()));
+            Point3::origin() + (total_displacement / count as f64)
+        };
+
+        vertices.push(buffer::vertex_at(shape, p, span.start, step));
+        vertices.len() as u32 - 1
+    });
+
+    let before_third = Instant::now();
+
+
+    // Third step:
+    // ===========
+    //
+    // We already have all vertices, now we need to generate the faces
+    // of our resulting mesh. For each edge crossing the surface of our
+    // shape, we will generate one face. This face's vertices are the
+    // vertices inside the four cells the edge is adjacent to.
+    //
+    let mut indices = Vec::new();
+    for (x, y, z) in cube(cells) {
+        // We iterate over all edges by iterating over all lower corners of
+        // all cells.
+        //
+        // About all those `points` lookups: if the edge is crossing the
+        // surface (which is checked in the if conditions below), then we
+        // generated a vertex for all of the adjacent cells (as they,
+        // by definition, also cross the surface). So the indices we access
+        // are always valid.
+
+        let base_sign = dists[(x, y, z)].is_sign_positive();
+
+        // Edge from the current corner pointing in +x direction
+        if y > 0 && z > 0 && base_sign != dists[(x + 1, y, z)].is_sign_positive()  {
+            let v0 = points[(x, y - 1, z - 1)];
+            let v1 = points[(x, y - 1, z    )];
+            let v2 = points[(x, y    , z - 1)];
+            let v3 = points[(x, y    , z    )];
+
+            indices.extend_from_slice(&
+                // distance negative, triangle cw
+                if dists[(x, y, z)] < 0.0 {
+                    [
+                        v0, v2, v1,
+                        v1, v2, v3,
+                    ]
+                } else {
+                    // ccw
+                    [
+                        v0, v1, v2,
+                        v1, v3, v2,
+                    ]
+                }
+            );
+        }
+
+        // Edge from the current corner pointing in +y direction
+        if x > 0 && z > 0 && base_sign != dists[(x, y + 1, z)].is_sign_positive()  {
+            let v0 = points[(x - 1, y, z - 1)];
+            let v1 = points[(x - 1, y, z    )];
+            let v2 = points[(x,     y, z - 1)];
+            let v3 = points[(x,     y, z    )];
+
+            indices.extend_from_slice(&
+                // distance negative, triangle cw
+                if dists[(x, y, z)] < 0.0 {
+                    [
+                        v0, v1, v2,
+                        v1, v3, v2,
+                    ]
+                } else {
+                    // ccw
+                    [
+                        v0, v2, v1,
+                        v1, v2, v3,
+                    ]
+                }
+            );
+        }
+
+        // Edge from the current corner pointing in +z direction
+        if x > 0 && y > 0 && base_sign != dists[(x, y, z + 1)].is_sign_positive()  {
+            let v0 = points[(x - 1, y - 1, z)];
+            let v1 = points[(x - 1, y    , z)];
+            let v2 = points[(x,     y - 1, z)];
+            let v3 = points[(x,     y    , z)];
+
+            indices.extend_from_slice(&
+                // distance negative, triangle cw
+                if dists[(x, y, z)] < 0.0 {
+                    [
+                        v0, v2, v1,
+                        v1, v2, v3,
+                    ]
+                } else {
+                    // ccw
+                    [
+                        v0, v1, v2,
+                        v1, v3, v2,
+                    ]
+                }
+            );
+        }
+    }
+
+    let after_third = Instant::now();
+    let timings = Timings {
+        first: before_second - before_first,
+        second: before_third - before_second,
+        third: after_third -  before_third,
+        vertices: vertices.len() as u32,
+        faces: indices.len() as u32 / 3,
+    };
+
+    (MeshBuffer { origin: span.start, vertices, indices, grid }, timings)
+}
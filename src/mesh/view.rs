@@ -1,6 +1,6 @@
 use std::mem;
 
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Point3};
 use wgpu::util::DeviceExt;
 
 use crate::{
@@ -12,6 +12,8 @@ use super::Vertex;
 
 
 pub struct MeshView {
+    /// The point all vertex positions are relative to.
+    origin: Point3<f64>,
     vbuf: wgpu::Buffer,
     ibuf: wgpu::Buffer,
     num_indices: u32,
@@ -20,7 +22,12 @@ pub struct MeshView {
 impl MeshView {
     /// Creates all required non-global resources to draw the mesh stored in
     /// the `MeshBuffer`.
-    pub fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u32]) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        origin: Point3<f64>,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Self {
         let vbuf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Shape mesh vertex buffer"),
             contents: bytemuck::cast_slice(&vertices),
@@ -34,6 +41,7 @@ impl MeshView {
         });
 
         Self {
+            origin,
             vbuf,
             ibuf,
             num_indices: indices.len() as u32,
@@ -46,7 +54,18 @@ impl MeshView {
         camera: &Camera,
         pipeline: &wgpu::RenderPipeline,
     ) {
-        let transform_mat = camera.proj_transform() * camera.view_transform();
+        // The view transformation is camera relative, so we have to move the
+        // mesh by its offset to the camera. This difference is calculated in
+        // `f64` and is small for all meshes close to the camera.
+        let offset = (self.origin - camera.position).cast::<f32>().unwrap();
+        let transform_mat = camera.proj_transform()
+            * camera.view_transform()
+            * Matrix4::from_translation(offset);
+        let origin = self.origin.cast::<f32>().unwrap();
+        let push_constants = PushConstants {
+            transform: transform_mat.to_arr(),
+            origin: [origin.x, origin.y, origin.z, 1.0],
+        };
 
         let mut encoder = draw_ctx.device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor { label: None }
@@ -79,7 +98,7 @@ impl MeshView {
             rpass.set_push_constants(
                 wgpu::ShaderStage::VERTEX,
                 0,
-                bytemuck::cast_slice(&transform_mat.to_arr()),
+                bytemuck::cast_slice(&[push_constants]),
             );
             rpass.pop_debug_group();
 
@@ -91,6 +110,19 @@ impl MeshView {
     }
 }
 
+/// The push constants of the vertex shader.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PushConstants {
+    transform: [[f32; 4]; 4],
+    origin: [f32; 4],
+}
+
+// `PushConstants` is inhabited, allows any bitpattern, has no padding, all
+// fields are `Pod`, and is `repr(C)`.
+unsafe impl bytemuck::Pod for PushConstants {}
+unsafe impl bytemuck::Zeroable for PushConstants {}
+
 pub(crate) fn create_pipeline(
     device: &wgpu::Device,
     out_format: wgpu::TextureFormat,
@@ -103,7 +135,7 @@ pub(crate) fn create_pipeline(
         bind_group_layouts: &[],
         push_constant_ranges: &[wgpu::PushConstantRange {
             stages: wgpu::ShaderStage::VERTEX,
-            range: 0..mem::size_of::<Matrix4<f32>>() as u32,
+            range: 0..mem::size_of::<PushConstants>() as u32,
         }],
     });
 
@@ -155,7 +187,22 @@ pub(crate) fn create_pipeline(
                     wgpu::VertexAttributeDescriptor {
                         format: wgpu::VertexFormat::Float,
                         offset: mem::size_of::<f32>() as u64 * 6,
-                        shader_location: 1,
+                        shader_location: 2,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float,
+                        offset: mem::size_of::<f32>() as u64 * 7,
+                        shader_location: 3,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float,
+                        offset: mem::size_of::<f32>() as u64 * 8,
+                        shader_location: 4,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: mem::size_of::<f32>() as u64 * 9,
+                        shader_location: 5,
                     },
                 ],
             }],
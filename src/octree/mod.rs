@@ -10,19 +10,19 @@ pub use self::iter::{Iter, IterElemMut, IterMut};
 // pub use self::debug_view::DebugView;
 
 /// A box in three dimensional space that is represented by one octree node
-pub type Span = Range<Point3<f32>>;
+pub type Span = Range<Point3<f64>>;
 
 pub trait SpanExt {
-    fn center(&self) -> Point3<f32>;
-    fn contains(&self, p: Point3<f32>) -> bool;
+    fn center(&self) -> Point3<f64>;
+    fn contains(&self, p: Point3<f64>) -> bool;
 }
 
 impl SpanExt for Span {
-    fn center(&self) -> Point3<f32> {
+    fn center(&self) -> Point3<f64> {
         self.start + (self.end - self.start) / 2.0
     }
 
-    fn contains(&self, p: Point3<f32>) -> bool {
+    fn contains(&self, p: Point3<f64>) -> bool {
         let s = self.start;
         let e = self.end;
 
@@ -37,7 +37,7 @@ impl SpanExt for Span {
 /// In this application it's used to store the representation of the octant in
 /// order to allow different resolutions in different parts of space.
 pub struct Octree<L, I> {
-    span: Range<Point3<f32>>,
+    span: Range<Point3<f64>>,
     root: Octnode<L, I>,
 }
 
@@ -71,8 +71,27 @@ impl<L, I> Octree<L, I> {
         }
     }
 
+    /// Returns the leaf node which contains the point `p` immutably.
+    pub fn leaf_around(&self, p: Point3<f64>) -> Option<NodeEntry<'_, L, I>> {
+        let mut node = self.root();
+        if !node.span().contains(p) {
+            return None;
+        }
+        loop {
+            let n = node.node;
+            match *n {
+                Octnode::Leaf(_) => return Some(node),
+                Octnode::SubTree { ref children, .. } => {
+                    let spans = create_spans(node.span());
+                    let i = (0..8).find(|&i| spans[i].contains(p)).unwrap();
+                    node = NodeEntry { node: &children[i], span: spans[i].clone() };
+                }
+            }
+        }
+    }
+
     /// Returns the leaf node which contains the point `p`.
-    pub fn leaf_around_mut(&mut self, p: Point3<f32>) -> Option<NodeEntryMut<L, I>> {
+    pub fn leaf_around_mut(&mut self, p: Point3<f64>) -> Option<NodeEntryMut<L, I>> {
         let mut node = self.root_mut();
         if !node.span().contains(p) {
             return None;
@@ -312,7 +331,7 @@ impl<'a, L, I> NodeEntryMut<'a, L, I> {
 
 /// Creates 8 equally sized children spans of a passed parent span. The spans are defined
 /// in a way that will be no gaps between them due to floating point precision errors.
-pub fn create_spans(parent_span: Range<Point3<f32>>) -> [Range<Point3<f32>>; 8] {
+pub fn create_spans(parent_span: Range<Point3<f64>>) -> [Range<Point3<f64>>; 8] {
     let start = parent_span.start;
     let end = parent_span.end;
     let center = start + (parent_span.end - start) / 2.0;
@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+use cgmath::{prelude::*, Point3, Rad, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    prelude::*,
+    camera::{Camera, Projection},
+    mesh::MeshSettings,
+    shape::{self, ExprAnimation, ParamValue, Shape},
+};
+
+
+/// Everything needed to reproduce a specific view of a shape. Scenes are
+/// stored as RON files.
+///
+/// ```text
+/// (
+///     shape: "mandelbulb(max_iters = 8)",
+///     params: {
+///         "bailout": Float(2.5),
+///     },
+///     camera: (
+///         position: (x: -3.0, y: 0.0, z: 0.0),
+///         direction: (x: 1.0, y: 0.0, z: 0.0),
+///         fov: 1.0,
+///         near_plane: 0.00004,
+///         far_plane: 10.0,
+///     ),
+///     mesh: (
+///         resolution: 64,
+///         focus_points: 5,
+///         algorithm: DualContouring,
+///         refinement: Bisection(steps: 6),
+///     ),
+/// )
+/// ```
+///
+/// All fields but `shape` are optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    /// The shape tree as shape expression (see `shape::parse_animated`).
+    pub shape: String,
+
+    /// Values for the parameters of the shape (see `Shape::params`), applied
+    /// after the shape is built. For animated shapes, they are applied to the
+    /// shape at each point in time, so they take precedence over animated
+    /// values.
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamValue>,
+
+    #[serde(default)]
+    pub camera: CameraSettings,
+
+    #[serde(default)]
+    pub mesh: MeshSettings,
+}
+
+impl Scene {
+    /// Loads a scene from the given RON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        let scene: Self = ron::de::from_str(&src)
+            .with_context(|| format!("failed to parse scene file '{}'", path.display()))?;
+        scene.mesh.validate()
+            .with_context(|| format!("invalid mesh settings in '{}'", path.display()))?;
+
+        Ok(scene)
+    }
+
+    /// Saves this scene as RON file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let src = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(path, src + "\n")
+            .with_context(|| format!("failed to write '{}'", path.display()))
+    }
+
+    /// Creates a scene with the default camera and mesh settings.
+    pub fn with_shape(shape: &str) -> Self {
+        Self {
+            shape: shape.to_string(),
+            params: BTreeMap::new(),
+            camera: CameraSettings::default(),
+            mesh: MeshSettings::default(),
+        }
+    }
+
+    /// Parses the shape expression, which might depend on time, and builds
+    /// the shape at time 0 (see `shape::parse_animated`).
+    pub fn parse_shape(&self) -> Result<(ExprAnimation, Arc<dyn Shape>)> {
+        shape::parse_animated(&self.shape).context("failed to parse shape expression")
+    }
+
+    /// Sets all parameters stored in this scene.
+    ///
+    /// If a parameter is fixed in `shape` (e.g. the power of the default
+    /// mandelbulb), but the stored value differs, `shape` is replaced by an
+    /// equivalent shape in which the parameter can be changed.
+    pub fn apply_params(&self, shape: &mut Arc<dyn Shape>) -> Result<()> {
+        for (name, &value) in &self.params {
+            if shape.param(name) != Some(value) {
+                if let Some(unfixed) = shape.unfix_param(name) {
+                    *shape = unfixed;
+                }
+            }
+
+            shape.set_param(name, value)
+                .with_context(|| format!("failed to set shape parameter '{}'", name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the current values of all parameters of `shape` in this scene.
+    pub fn store_params(&mut self, shape: &dyn Shape) {
+        self.params = shape.params().iter()
+            .filter_map(|p| shape.param(p.name).map(|v| (p.name.to_string(), v)))
+            .collect();
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::with_shape("mandelbulb()")
+    }
+}
+
+/// Position, direction and projection parameters of the camera. The aspect
+/// ratio is not stored, as it depends on the window size.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CameraSettings {
+    pub position: Point3<f64>,
+    pub direction: Vector3<f32>,
+
+    /// Field of view in the y direction in radians.
+    pub fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32,
+}
+
+impl CameraSettings {
+    /// Creates the camera for a window of the given size.
+    pub fn to_camera(self, window_size: (u32, u32)) -> Result<Camera> {
+        use std::f32::consts::FRAC_PI_2;
+
+        if self.direction.is_zero() {
+            bail!("camera direction must not be zero");
+        }
+        if !(self.fov > 0.0 && self.fov < FRAC_PI_2) {
+            bail!("camera field of view has to be between 0 and π/2, but is {}", self.fov);
+        }
+        if !(0.0 < self.near_plane && self.near_plane < self.far_plane) {
+            bail!("camera near plane has to be positive and smaller than the far plane");
+        }
+
+        let proj = Projection::new(
+            Rad(self.fov),
+            self.near_plane..self.far_plane,
+            window_size,
+        );
+        Ok(Camera::new(self.position, self.direction, proj))
+    }
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position,
+            direction: camera.direction(),
+            fov: camera.projection.fov.0,
+            near_plane: camera.projection.near_plane,
+            far_plane: camera.projection.far_plane,
+        }
+    }
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            position: Point3::new(-3.0, 0.0, 0.0),
+            direction: Vector3::unit_x(),
+            fov: 1.0,
+            near_plane: 0.000_04,
+            far_plane: 10.0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+    use cgmath::{Point3, Vector3};
+
+    use crate::{
+        mesh::{EdgeRefinement, MeshAlgorithm},
+        shape::{ParamValue, Shape},
+    };
+    use super::Scene;
+
+    /// Returns a path in the temporary directory which is unique to the test.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cantucci-{}-{}.ron", name, std::process::id()))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut scene = Scene::with_shape("union(sphere(), mandelbulb(power = 3))");
+        scene.params.insert("bailout".into(), ParamValue::Float(3.0));
+        scene.camera.position = Point3::new(1.0, -2.0, 0.5);
+        scene.camera.direction = Vector3::new(0.0, 0.6, 0.8);
+        scene.mesh.resolution = 32;
+        scene.mesh.algorithm = MeshAlgorithm::DualContouring;
+        scene.mesh.refinement = EdgeRefinement::Bisection { steps: 4 };
+        scene.mesh.region = Some(Point3::new(-1.0, -1.0, -1.0)..Point3::new(1.0, 2.0, 1.0));
+
+        let path = temp_path("save-and-load");
+        scene.save(&path).unwrap();
+        let loaded = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let ron = |s: &Scene| ron::ser::to_string(s).unwrap();
+        assert_eq!(ron(&loaded.unwrap()), ron(&scene));
+    }
+
+    #[test]
+    fn invalid_resolution() {
+        let mut scene = Scene::default();
+        scene.mesh.resolution = 48;
+
+        let path = temp_path("invalid-resolution");
+        scene.save(&path).unwrap();
+        let loaded = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = format!("{:#}", loaded.unwrap_err());
+        assert!(err.contains("power of two"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn override_fixed_power() {
+        let mut scene = Scene::default();
+        scene.params.insert("power".into(), ParamValue::Float(3.5));
+        scene.params.insert("max_iters".into(), ParamValue::Integer(4));
+
+        // The default shape is a `Mandelbulb<8>`, whose power is fixed.
+        let (_, mut shape) = scene.parse_shape().unwrap();
+        assert!(shape.set_param("power", ParamValue::Float(3.5)).is_err());
+
+        scene.apply_params(&mut shape).unwrap();
+        assert_eq!(shape.param("power"), Some(ParamValue::Float(3.5)));
+        assert_eq!(shape.param("max_iters"), Some(ParamValue::Integer(4)));
+        assert_eq!(shape.param("bailout"), Some(ParamValue::Float(2.5)));
+    }
+}
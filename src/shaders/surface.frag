@@ -3,20 +3,41 @@
 layout(location = 0) in float in_distance_from_surface;
 layout(location = 1) in vec3 in_position;
 layout(location = 2) in vec3 in_normal;
+layout(location = 3) in float in_iterations;
+layout(location = 4) in float in_smooth_iterations;
+layout(location = 5) in vec4 in_orbit_trap;
 
 layout(location = 0) out vec4 out_color;
 
 
 
-float col() {
-    return pow(length(in_position) * 0.85, 8.0) * 0.7;
+// Smooth color gradient (see https://iquilezles.org/www/articles/palettes/palettes.htm)
+vec3 palette(float t) {
+    return 0.5 + 0.5 * cos(6.28318 * (t + vec3(0.0, 0.1, 0.2)));
+}
+
+vec3 col() {
+    // Shapes without coloring data are colored by the distance from the
+    // origin.
+    if (in_iterations < 0.0) {
+        return vec3(pow(length(in_position) * 0.85, 8.0) * 0.7);
+    }
+
+    // The hue is determined by the iteration count, the orbit traps darken
+    // the parts of the surface whose orbit came close to the origin or one
+    // of the planes.
+    vec3 base = palette(in_smooth_iterations * 0.15);
+    float plane_trap = min(in_orbit_trap.x, min(in_orbit_trap.y, in_orbit_trap.z));
+    float trap = clamp(2.0 * in_orbit_trap.w, 0.0, 1.0) * clamp(4.0 * plane_trap + 0.5, 0.0, 1.0);
+
+    return base * (0.03 + 0.12 * trap);
 }
 
 void main() {
     vec3 light_dir = vec3(1, 0, 0);
     vec3 light_color = vec3(0.7, 0.6, 0.3);
     float light_strength = 10.0;
-    vec3 base_color = vec3(col());
+    vec3 base_color = col();
 
     // 1 means: fully lit, 0 to -1 means: not lit
     float angle = dot(in_normal, -light_dir);
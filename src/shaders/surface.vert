@@ -3,20 +3,31 @@
 layout(location = 0) in vec3 in_position;
 layout(location = 1) in vec3 in_normal;
 layout(location = 2) in float in_distance_from_surface;
+layout(location = 3) in float in_iterations;
+layout(location = 4) in float in_smooth_iterations;
+layout(location = 5) in vec4 in_orbit_trap;
 
 layout(location = 0) out float out_distance_from_surface;
 layout(location = 1) out vec3 out_position;
 layout(location = 2) out vec3 out_normal;
+layout(location = 3) out float out_iterations;
+layout(location = 4) out float out_smooth_iterations;
+layout(location = 5) out vec4 out_orbit_trap;
 
 layout(push_constant) uniform PushConsts {
   mat4 trans_matrix;
+  // Vertex positions are relative to this point (only `xyz` is used).
+  vec4 origin;
 } uniforms;
 
 
 void main() {
     out_distance_from_surface = in_distance_from_surface;
-    out_position = in_position;
+    out_position = uniforms.origin.xyz + in_position;
     out_normal = in_normal;
+    out_iterations = in_iterations;
+    out_smooth_iterations = in_smooth_iterations;
+    out_orbit_trap = in_orbit_trap;
 
     gl_Position = uniforms.trans_matrix * vec4(in_position, 1);
 }
@@ -1,6 +1,6 @@
 use test::{Bencher, black_box};
 
-use super::{BENCH_POINTS, Mandelbulb, Shape};
+use super::{BENCH_POINTS, DynMandelbulb, Mandelbox, Mandelbulb, Shape};
 
 
 // #[bench]
@@ -19,3 +19,32 @@ fn mandel_10points_i8_b5(b: &mut Bencher) {
             .sum::<f32>()
     });
 }
+
+#[bench]
+fn dyn_mandel_10points_p7_5_i8_b5(b: &mut Bencher) {
+    let m = DynMandelbulb::new(7.5, 8, 5.0);
+    b.iter(|| {
+        BENCH_POINTS.iter()
+            .map(|&p| m.min_distance_from(black_box(p.into())))
+            .sum::<f32>()
+    });
+}
+
+#[bench]
+fn mandelbox_10points_i12(b: &mut Bencher) {
+    let m = Mandelbox::classic(12);
+    b.iter(|| {
+        BENCH_POINTS.iter()
+            .map(|&p| m.min_distance_from(black_box(p.into())))
+            .sum::<f32>()
+    });
+}
+
+#[bench]
+fn mandel_10points_batch_i8_b5(b: &mut Bencher) {
+    let m = Mandelbulb::classic(8, 5.0);
+    let points = BENCH_POINTS.iter().map(|&p| p.into()).collect::<Vec<_>>();
+    b.iter(|| {
+        m.batch_min_distance_from(black_box(&points)).iter().sum::<f32>()
+    });
+}
@@ -0,0 +1,7 @@
+float {NAME}(vec3 point) {
+    float angle = -{RATE} * point.x;
+    vec2 xz = mat2(cos(angle), sin(angle), -sin(angle), cos(angle)) * point.xz;
+
+    float lipschitz = 1.0 + abs({RATE}) * max(length(point.xz), {RADIUS});
+    return {INNER}(vec3(xz.x, point.y, xz.y)) / lipschitz;
+}
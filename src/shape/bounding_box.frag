@@ -0,0 +1,4 @@
+float {NAME}(vec3 point) {
+    vec3 d = abs(point - {CENTER}) - {HALF_SIZE};
+    return length(max(d, 0.0)) + min(max(d.x, max(d.y, d.z)), 0.0);
+}
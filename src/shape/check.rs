@@ -0,0 +1,468 @@
+use std::fmt;
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::util::{grid::GridTable, iter};
+use super::Shape;
+
+
+/// Checks whether a shape fulfills the contract of the `Shape` trait. This
+/// is a diagnostic tool for tests: DE violations result in holes in the mesh
+/// and in artifacts when ray marching, which are hard to trace back otherwise.
+///
+/// As we don't know the real distance to the surface, we approximate it:
+/// the region around the bounding box is sampled on a grid and for each grid
+/// edge with a sign change of the DE, the exact surface point on that edge is
+/// searched via bisection. The distance to the closest of these surface
+/// points is an upper bound of the real distance (called the "reference"
+/// below). It's close to the real distance if the grid is fine enough.
+///
+/// Then, random points (uniformly in the region and close to the surface)
+/// are checked:
+///
+/// - The DE must be a lower bound: `|min_distance_from(p)|` may not be
+///   larger than the reference.
+/// - Stepping from `p` by `|min_distance_from(p)|` in any direction must not
+///   cross the surface, i.e. the sign of the DE may not change. This is what
+///   the ray marcher and the mesh generation rely on.
+/// - `max_distance_from(p)` must not be smaller than the lower bound and must
+///   not be (much) smaller than the reference.
+/// - `contains(p)` must be `true` exactly if the DE is negative.
+/// - All surface points must lie in the bounding box and the DE must be
+///   positive everywhere outside of it.
+pub fn check(shape: &dyn Shape, settings: &CheckSettings) -> Report {
+    let bb = shape.bounding_box();
+    let bb = bb.start.cast::<f64>().unwrap()..bb.end.cast::<f64>().unwrap();
+    let size = bb.end - bb.start;
+
+    // The sampled region is larger than the bounding box to find surface
+    // points outside of it.
+    let region_start = bb.start - size * settings.margin;
+    let region_size = size * (1.0 + 2.0 * settings.margin);
+    let cell = region_size / settings.resolution as f64;
+
+    // Values smaller than this are treated as 0 to account for rounding
+    // errors (mainly from the `f32` DE).
+    let epsilon = 1e-5 * region_size.magnitude();
+
+    let mut violations = Vec::new();
+    let mut violation = |kind, point, distance, reference| {
+        violations.push(Violation { kind, point, distance, reference });
+    };
+
+    // Find surface points on all grid edges crossing the surface.
+    let corner = |x: u32, y: u32, z: u32| {
+        region_start + Vector3::new(x as f64, y as f64, z as f64).mul_element_wise(cell)
+    };
+    let dists = GridTable::fill_with(settings.resolution + 1, |x, y, z| {
+        shape.min_distance_from_f64(corner(x, y, z))
+    });
+
+    let mut surface = Vec::new();
+    for (x, y, z) in iter::cube(settings.resolution + 1) {
+        let neighbors = [(x + 1, y, z), (x, y + 1, z), (x, y, z + 1)];
+        for &(nx, ny, nz) in &neighbors {
+            if nx > settings.resolution || ny > settings.resolution || nz > settings.resolution {
+                continue;
+            }
+
+            let d = dists[(x, y, z)];
+            if d.is_sign_negative() != dists[(nx, ny, nz)].is_sign_negative() {
+                surface.push(bisect(shape, corner(x, y, z), corner(nx, ny, nz), d));
+            }
+        }
+    }
+
+    for &p in &surface {
+        let outside = distance_outside(&bb, p);
+        if outside > epsilon {
+            violation(ViolationKind::BoundingBox, p, shape.min_distance_from_f64(p), outside);
+        }
+    }
+
+    // Check random points, half of them uniformly distributed in the region,
+    // the other half close to surface points (where violations matter most).
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for i in 0..settings.samples {
+        let p = if i % 2 == 0 || surface.is_empty() {
+            region_start + rng.vector().mul_element_wise(region_size)
+        } else {
+            let s = surface[rng.next() as usize % surface.len()];
+            s + (rng.vector() * 2.0 - Vector3::new(1.0, 1.0, 1.0)).mul_element_wise(cell) * 2.0
+        };
+
+        // The contract is about the `f32` methods.
+        let p32 = p.cast::<f32>().unwrap();
+        let p = p32.cast::<f64>().unwrap();
+        let (min, max) = shape.bounded_distance_from(p32);
+        let min = f64::from(min);
+
+        let reference = surface.iter()
+            .map(|s| s.distance(p))
+            .fold(f64::INFINITY, f64::min);
+
+        // See `CheckSettings::inside`.
+        let check_bound = settings.inside || min >= 0.0;
+
+        if check_bound && min.abs() > reference * (1.0 + settings.tolerance) + epsilon {
+            violation(ViolationKind::LowerBound, p, min, reference);
+        }
+
+        if check_bound && min.abs() > epsilon {
+            for _ in 0..settings.directions {
+                let q = p + rng.direction() * (min.abs() / (1.0 + settings.tolerance));
+                let dq = f64::from(shape.min_distance_from(q.cast().unwrap()));
+                if dq.abs() > epsilon && dq.is_sign_negative() != min.is_sign_negative() {
+                    violation(ViolationKind::Overstep, p, min, dq);
+                }
+            }
+        }
+
+        if let Some(max) = max {
+            // The reference is an upper bound of the real distance, but it's
+            // at most the diagonal of a cell larger than the real distance.
+            let max = f64::from(max).abs();
+            let lower = f64::max(min.abs(), reference - cell.magnitude());
+            if max + epsilon < lower {
+                violation(ViolationKind::UpperBound, p, max, lower);
+            }
+        }
+
+        if min.abs() > epsilon && shape.contains(p32) != (min < 0.0) {
+            violation(ViolationKind::Contains, p, min, reference);
+        }
+
+        let outside = distance_outside(&bb, p);
+        if outside > epsilon && min < 0.0 {
+            violation(ViolationKind::BoundingBox, p, min, outside);
+        }
+    }
+
+    Report {
+        surface_points: surface.len(),
+        samples: settings.samples,
+        violations,
+    }
+}
+
+/// Settings for `check()`.
+#[derive(Debug, Clone)]
+pub struct CheckSettings {
+    /// Number of grid cells along each axis used to find surface points.
+    /// Higher values result in a more precise reference distance.
+    pub resolution: u32,
+
+    /// The sampled region is the bounding box enlarged by this fraction of
+    /// its size on each side.
+    pub margin: f64,
+
+    /// The number of points at which the contract is checked.
+    pub samples: u32,
+
+    /// For each point, the number of random directions in which the step
+    /// along the DE is checked.
+    pub directions: u32,
+
+    /// How much the DE may exceed the reference distance (relative). Most
+    /// fractal DEs are only approximately lower bounds. The step along the DE
+    /// is shortened accordingly.
+    pub tolerance: f64,
+
+    /// Whether the magnitude of the DE is checked inside the shape, too (the
+    /// sign is always checked). For escape time fractals, the DE is only
+    /// meaningful outside of the shape. Luckily, the mesh generation and the
+    /// ray marcher only rely on the sign inside.
+    pub inside: bool,
+}
+
+impl Default for CheckSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 48,
+            margin: 0.25,
+            samples: 1000,
+            directions: 4,
+            tolerance: 0.0,
+            inside: true,
+        }
+    }
+}
+
+/// The result of `check()`.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The number of surface points found on the grid.
+    pub surface_points: usize,
+    pub samples: u32,
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    /// Panics with a description of the violations, if there are any.
+    pub fn assert_ok(&self) {
+        if !self.violations.is_empty() {
+            panic!("shape violates the DE contract:\n{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only show a few violations, there are usually lots of similar ones.
+        const MAX_SHOWN: usize = 10;
+
+        writeln!(
+            f,
+            "{} violations ({} samples, {} surface points)",
+            self.violations.len(),
+            self.samples,
+            self.surface_points,
+        )?;
+        for v in self.violations.iter().take(MAX_SHOWN) {
+            writeln!(f, "  {}", v)?;
+        }
+        if self.violations.len() > MAX_SHOWN {
+            writeln!(f, "  ...")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One violation of the contract found by `check()`.
+#[derive(Debug, Clone, Copy)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub point: Point3<f64>,
+
+    /// The value returned by the shape.
+    pub distance: f64,
+
+    /// The value it was compared to (depends on `kind`).
+    pub reference: f64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            ViolationKind::LowerBound => "DE larger than reference distance",
+            ViolationKind::Overstep => "stepping by DE crosses surface, DE there",
+            ViolationKind::UpperBound => "max distance smaller than lower bound",
+            ViolationKind::Contains => "`contains` inconsistent with DE, reference",
+            ViolationKind::BoundingBox => "surface outside bounding box, distance to box",
+        };
+        write!(
+            f,
+            "{:?} at ({:.6}, {:.6}, {:.6}): {} ({} vs {})",
+            self.kind,
+            self.point.x,
+            self.point.y,
+            self.point.z,
+            what,
+            self.distance,
+            self.reference,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    LowerBound,
+    Overstep,
+    UpperBound,
+    Contains,
+    BoundingBox,
+}
+
+/// Finds the surface point on the line from `a` to `b` (which have DEs of
+/// different signs). `da` is the DE at `a`.
+fn bisect(shape: &dyn Shape, mut a: Point3<f64>, mut b: Point3<f64>, da: f64) -> Point3<f64> {
+    const STEPS: u32 = 30;
+
+    let a_negative = da.is_sign_negative();
+    for _ in 0..STEPS {
+        let mid = a.midpoint(b);
+        if shape.min_distance_from_f64(mid).is_sign_negative() == a_negative {
+            a = mid;
+        } else {
+            b = mid;
+        }
+    }
+
+    a.midpoint(b)
+}
+
+/// Returns the distance of `p` from the box `bb` (0 if it's inside).
+fn distance_outside(bb: &std::ops::Range<Point3<f64>>, p: Point3<f64>) -> f64 {
+    let outside = |v: f64, min: f64, max: f64| f64::max(min - v, 0.0).max(v - max);
+    Vector3::new(
+        outside(p.x, bb.start.x, bb.end.x),
+        outside(p.y, bb.start.y, bb.end.y),
+        outside(p.z, bb.start.z, bb.end.z),
+    ).magnitude()
+}
+
+/// A tiny xorshift PRNG. We want reproducible results and don't need a good
+/// distribution.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a value in `[0, 1)`.
+    fn float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a vector with all components in `[0, 1)`.
+    fn vector(&mut self) -> Vector3<f64> {
+        Vector3::new(self.float(), self.float(), self.float())
+    }
+
+    /// Returns a random unit vector.
+    fn direction(&mut self) -> Vector3<f64> {
+        loop {
+            let v = self.vector() * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+            let len2 = v.magnitude2();
+            if len2 > 1e-6 && len2 <= 1.0 {
+                return v / len2.sqrt();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use cgmath::{prelude::*, Deg, Point3, Quaternion, Vector3};
+
+    use crate::shape::{
+        BENCH_POINTS, Difference, DynMandelbulb, Intersection, KaleidoscopicIfs, Mandelbox,
+        Mandelbulb, Shape, Sphere, Transformed, Union,
+    };
+    use super::{check, CheckSettings, ViolationKind};
+
+    /// Compares `shape.gradient_at()` with central differences of
+    /// `min_distance_from_f64()` at all `BENCH_POINTS`.
+    fn assert_gradient(shape: &dyn Shape) {
+        const DELTA: f64 = 1e-6;
+
+        for &p in &BENCH_POINTS {
+            let p = Point3::from(p).cast::<f64>().unwrap();
+            let diff = |v: Vector3<f64>| {
+                let d = shape.min_distance_from_f64(p + v * DELTA)
+                    - shape.min_distance_from_f64(p - v * DELTA);
+                d / (2.0 * DELTA)
+            };
+            let expected = Vector3::new(
+                diff(Vector3::unit_x()),
+                diff(Vector3::unit_y()),
+                diff(Vector3::unit_z()),
+            );
+
+            let actual = shape.gradient_at(p, DELTA);
+            let error = (actual - expected).magnitude();
+            assert!(
+                error <= 1e-4 * expected.magnitude().max(1.0),
+                "gradient at {:?} is {:?}, but central differences give {:?}",
+                p,
+                actual,
+                expected,
+            );
+        }
+    }
+
+    #[test]
+    fn sphere() {
+        let sphere = Sphere::new(Point3::new(0.3, -0.2, 0.1), 0.8);
+        check(&sphere, &CheckSettings::default()).assert_ok();
+    }
+
+    #[test]
+    fn mandelbulb() {
+        let settings = CheckSettings {
+            inside: false,
+            ..CheckSettings::default()
+        };
+
+        for &max_iters in &[4, 6, 10] {
+            check(&Mandelbulb::classic(max_iters, 2.5), &settings).assert_ok();
+        }
+    }
+
+    #[test]
+    fn dyn_mandelbulb_bounding_box() {
+        // Far away from the surface, the DE is only roughly a lower bound (which
+        // is fine, it's never used there). So we only check the bounding box.
+        let settings = CheckSettings {
+            inside: false,
+            ..CheckSettings::default()
+        };
+
+        for &power in &[2.0, 3.5, 8.0, -3.0] {
+            for &bailout in &[0.8, 2.5] {
+                let report = check(&DynMandelbulb::new(power, 6, bailout), &settings);
+                assert!(
+                    report.violations.iter().all(|v| v.kind != ViolationKind::BoundingBox),
+                    "power {}, bailout {}: {}",
+                    power,
+                    bailout,
+                    report,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kifs() {
+        for &max_iters in &[1, 3, 5] {
+            let shapes = [
+                KaleidoscopicIfs::menger_sponge(max_iters),
+                KaleidoscopicIfs::sierpinski_tetrahedron(max_iters),
+                KaleidoscopicIfs::sierpinski_octahedron(max_iters),
+            ];
+            for shape in &shapes {
+                check(shape, &CheckSettings::default()).assert_ok();
+            }
+        }
+    }
+
+    #[test]
+    fn mandelbox() {
+        let settings = CheckSettings {
+            inside: false,
+            ..CheckSettings::default()
+        };
+
+        // With less iterations than that, the DE oversteps close to the surface
+        // in some places.
+        for &max_iters in &[10, 12, 16] {
+            check(&Mandelbox::classic(max_iters), &settings).assert_ok();
+        }
+        check(&Mandelbox::new(10, -1.5, 1.0, 0.5, 1.0), &settings).assert_ok();
+    }
+
+    #[test]
+    fn gradients() {
+        assert_gradient(&Sphere::new(Point3::new(0.3, -0.2, 0.1), 0.8));
+        assert_gradient(&Mandelbulb::classic(6, 2.5));
+        assert_gradient(&DynMandelbulb::new(3.5, 6, 2.5));
+
+        let bulb: Arc<dyn Shape> = Arc::new(Mandelbulb::classic(6, 2.5));
+        let sphere: Arc<dyn Shape> = Arc::new(Sphere::new(Point3::new(0.5, 0.0, 0.0), 0.6));
+        for &k in &[0.0, 0.2] {
+            assert_gradient(&Union::smooth(bulb.clone(), sphere.clone(), k));
+            assert_gradient(&Intersection::smooth(bulb.clone(), sphere.clone(), k));
+            assert_gradient(&Difference::smooth(bulb.clone(), sphere.clone(), k));
+        }
+
+        let axis = Vector3::new(1.0, 2.0, -1.0).normalize();
+        let rotation = Quaternion::from_axis_angle(axis, Deg(30.0));
+        assert_gradient(&Transformed::new(bulb, Vector3::new(0.1, -0.2, 0.05), rotation, 1.3));
+    }
+}
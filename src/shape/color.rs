@@ -0,0 +1,65 @@
+use cgmath::Vector3;
+
+
+/// Additional data about a point which can be used to color the surface of a
+/// shape. See `Shape::colored_distance_from`.
+///
+/// For escape time fractals, this describes the orbit of the point, i.e. the
+/// sequence of values the fractal iteration produces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorData {
+    /// The number of iterations after which the orbit escaped (exceeded the
+    /// bailout). If it didn't escape, this is the maximum number of
+    /// iterations.
+    pub iterations: u32,
+
+    /// Continuous version of `iterations`, which doesn't result in visible
+    /// bands when used for coloring.
+    pub smooth_iterations: f32,
+
+    /// The minimum distances of the orbit to the yz-, xz- and xy-plane (in
+    /// that order).
+    pub trap_planes: Vector3<f32>,
+
+    /// The minimum distance of the orbit to the origin.
+    pub trap_origin: f32,
+}
+
+impl ColorData {
+    /// Creates the data of an orbit which hasn't escaped after `max_iters`
+    /// iterations (yet). Afterwards, `trap()` has to be called for each value
+    /// of the orbit and `escape()` once the orbit escaped.
+    pub fn orbit(max_iters: u64) -> Self {
+        Self {
+            iterations: max_iters as u32,
+            smooth_iterations: max_iters as f32,
+            trap_planes: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            trap_origin: f32::INFINITY,
+        }
+    }
+
+    /// Updates the orbit traps with the next value `z` of the orbit.
+    pub fn trap(&mut self, [x, y, z]: [f64; 3]) {
+        let min = |trap: &mut f32, d: f64| *trap = trap.min(d.abs() as f32);
+
+        min(&mut self.trap_planes.x, x);
+        min(&mut self.trap_planes.y, y);
+        min(&mut self.trap_planes.z, z);
+        min(&mut self.trap_origin, (x * x + y * y + z * z).sqrt());
+    }
+
+    /// Marks the orbit as escaped in iteration `iteration` with the radius
+    /// `r`. `power` is the exponent of the iteration formula.
+    pub fn escape(&mut self, iteration: u64, r: f64, bailout: f64, power: f64) {
+        self.iterations = iteration as u32;
+
+        // The usual renormalization. It only works for powers greater than 1,
+        // otherwise we just use the integer value.
+        self.smooth_iterations = if power > 1.0 && r > 1.0 && bailout > 1.0 {
+            let correction = (r.ln() / bailout.ln()).ln() / power.ln();
+            (iteration as f64 + 1.0 - correction).max(0.0) as f32
+        } else {
+            iteration as f32
+        };
+    }
+}
@@ -0,0 +1,6 @@
+float {NAME}(vec3 point) {
+    float a = {A}(point);
+    float b = {B}(point);
+
+    return {COMBINE};
+}
@@ -0,0 +1,330 @@
+use std::{ops::Range, sync::Arc};
+use cgmath::{BaseFloat, Point3, Vector3};
+
+use super::{ColorData, Shape, glsl::{Code, Float, GlslBuilder, Ident}};
+
+
+/// The union of two shapes: all points that are in `a` or in `b`.
+#[derive(Clone)]
+pub struct Union {
+    a: Arc<dyn Shape>,
+    b: Arc<dyn Shape>,
+    k: f32,
+}
+
+impl Union {
+    pub fn new(a: Arc<dyn Shape>, b: Arc<dyn Shape>) -> Self {
+        Self::smooth(a, b, 0.0)
+    }
+
+    /// Like `new()`, but both shapes are blended in a region of size `k`
+    /// around the seam, using the polynomial smooth minimum described
+    /// [here][1]. With `k == 0`, this is the normal (sharp) union.
+    ///
+    /// [1]: https://iquilezles.org/www/articles/smin/smin.htm
+    pub fn smooth(a: Arc<dyn Shape>, b: Arc<dyn Shape>, k: f32) -> Self {
+        assert!(k >= 0.0);
+        Self { a, b, k }
+    }
+}
+
+impl Shape for Union {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        smin(self.a.min_distance_from(p), self.b.min_distance_from(p), self.k)
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        smin(self.a.min_distance_from_f64(p), self.b.min_distance_from_f64(p), self.k.into())
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        // The color is taken from the shape which is closer.
+        let (a, color_a) = self.a.colored_distance_from(p);
+        let (b, color_b) = self.b.colored_distance_from(p);
+        (smin(a, b, self.k.into()), if a < b { color_a } else { color_b })
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        smin_gradient(
+            (self.a.min_distance_from_f64(p), self.a.gradient_at(p, delta)),
+            (self.b.min_distance_from_f64(p), self.b.gradient_at(p, delta)),
+            self.k.into(),
+        )
+    }
+
+    fn max_distance_from(&self, p: Point3<f32>) -> Option<f32> {
+        // The smoothed union is always a superset of the sharp union, so the
+        // bound would still be correct. But it wouldn't converge to the real
+        // distance when approaching the surface.
+        if self.k != 0.0 {
+            return None;
+        }
+
+        let a = self.a.max_distance_from(p)?;
+        let b = self.b.max_distance_from(p)?;
+        Some(a.min(b))
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // The smooth minimum is at most `k / 4` smaller than the real minimum,
+        // so the surface can bulge out by that much.
+        let bb = bb_union(self.a.bounding_box(), self.b.bounding_box());
+        let off = Vector3::new(self.k, self.k, self.k) / 4.0;
+        bb.start + -off .. bb.end + off
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let combine = if self.k == 0.0 {
+            "min(a, b)".to_string()
+        } else {
+            format!("min(a, b) - {}", smooth_correction_glsl("a - b", self.k))
+        };
+
+        combined_shader(glsl, "union", &*self.a, &*self.b, &combine)
+    }
+
+    impl_batch_methods!();
+}
+
+
+/// The intersection of two shapes: all points that are in `a` and in `b`.
+#[derive(Clone)]
+pub struct Intersection {
+    a: Arc<dyn Shape>,
+    b: Arc<dyn Shape>,
+    k: f32,
+}
+
+impl Intersection {
+    pub fn new(a: Arc<dyn Shape>, b: Arc<dyn Shape>) -> Self {
+        Self::smooth(a, b, 0.0)
+    }
+
+    /// Like `new()`, but with a smooth seam of size `k` (see
+    /// `Union::smooth()`).
+    pub fn smooth(a: Arc<dyn Shape>, b: Arc<dyn Shape>, k: f32) -> Self {
+        assert!(k >= 0.0);
+        Self { a, b, k }
+    }
+}
+
+impl Shape for Intersection {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        smax(self.a.min_distance_from(p), self.b.min_distance_from(p), self.k)
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        smax(self.a.min_distance_from_f64(p), self.b.min_distance_from_f64(p), self.k.into())
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        // The color is taken from the shape whose surface forms the surface
+        // of the intersection.
+        let (a, color_a) = self.a.colored_distance_from(p);
+        let (b, color_b) = self.b.colored_distance_from(p);
+        (smax(a, b, self.k.into()), if a > b { color_a } else { color_b })
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        smax_gradient(
+            (self.a.min_distance_from_f64(p), self.a.gradient_at(p, delta)),
+            (self.b.min_distance_from_f64(p), self.b.gradient_at(p, delta)),
+            self.k.into(),
+        )
+    }
+
+    // We can't give an upper bound: a point can be very close to the
+    // surfaces of both shapes while being far away from their intersection.
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // Smoothing only removes points from the intersection, so we don't
+        // need to enlarge the box here.
+        bb_intersection(self.a.bounding_box(), self.b.bounding_box())
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let combine = if self.k == 0.0 {
+            "max(a, b)".to_string()
+        } else {
+            format!("max(a, b) + {}", smooth_correction_glsl("a - b", self.k))
+        };
+
+        combined_shader(glsl, "intersection", &*self.a, &*self.b, &combine)
+    }
+
+    impl_batch_methods!();
+}
+
+
+/// The difference of two shapes: all points that are in `a` but not in `b`.
+#[derive(Clone)]
+pub struct Difference {
+    a: Arc<dyn Shape>,
+    b: Arc<dyn Shape>,
+    k: f32,
+}
+
+impl Difference {
+    pub fn new(a: Arc<dyn Shape>, b: Arc<dyn Shape>) -> Self {
+        Self::smooth(a, b, 0.0)
+    }
+
+    /// Like `new()`, but with a smooth seam of size `k` (see
+    /// `Union::smooth()`).
+    pub fn smooth(a: Arc<dyn Shape>, b: Arc<dyn Shape>, k: f32) -> Self {
+        assert!(k >= 0.0);
+        Self { a, b, k }
+    }
+}
+
+impl Shape for Difference {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        smax(self.a.min_distance_from(p), -self.b.min_distance_from(p), self.k)
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        smax(self.a.min_distance_from_f64(p), -self.b.min_distance_from_f64(p), self.k.into())
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        // Same as for the intersection, but with the inverted `b`.
+        let (a, color_a) = self.a.colored_distance_from(p);
+        let (b, color_b) = self.b.colored_distance_from(p);
+        (smax(a, -b, self.k.into()), if a > -b { color_a } else { color_b })
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        smax_gradient(
+            (self.a.min_distance_from_f64(p), self.a.gradient_at(p, delta)),
+            (-self.b.min_distance_from_f64(p), -self.b.gradient_at(p, delta)),
+            self.k.into(),
+        )
+    }
+
+    // Same as for the intersection, there is no upper bound.
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        self.a.bounding_box()
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let combine = if self.k == 0.0 {
+            "max(a, -b)".to_string()
+        } else {
+            format!("max(a, -b) + {}", smooth_correction_glsl("a + b", self.k))
+        };
+
+        combined_shader(glsl, "difference", &*self.a, &*self.b, &combine)
+    }
+
+    impl_batch_methods!();
+}
+
+
+/// Polynomial smooth minimum. For `k == 0` this is the normal minimum.
+fn smin<F: BaseFloat>(a: F, b: F, k: F) -> F {
+    if k.is_zero() {
+        a.min(b)
+    } else {
+        let h = (k - (a - b).abs()).max(F::zero());
+        a.min(b) - h * h / (F::from(4.0).unwrap() * k)
+    }
+}
+
+/// Polynomial smooth maximum. For `k == 0` this is the normal maximum.
+fn smax<F: BaseFloat>(a: F, b: F, k: F) -> F {
+    -smin(-a, -b, k)
+}
+
+/// Gradient of `smin(a, b, k)`, given the values and gradients of `a` and
+/// `b`.
+fn smin_gradient(
+    (a, grad_a): (f64, Vector3<f64>),
+    (b, grad_b): (f64, Vector3<f64>),
+    k: f64,
+) -> Vector3<f64> {
+    let grad_min = if a < b { grad_a } else { grad_b };
+    let h = (k - (a - b).abs()).max(0.0);
+    if h == 0.0 {
+        grad_min
+    } else {
+        // Derivative of the correction term `-h² / 4k`
+        grad_min + (grad_a - grad_b) * (h / (2.0 * k) * (a - b).signum())
+    }
+}
+
+/// Gradient of `smax(a, b, k)`, see `smin_gradient()`.
+fn smax_gradient(
+    (a, grad_a): (f64, Vector3<f64>),
+    (b, grad_b): (f64, Vector3<f64>),
+    k: f64,
+) -> Vector3<f64> {
+    -smin_gradient((-a, -grad_a), (-b, -grad_b), k)
+}
+
+/// Returns the GLSL expression for the correction term of the smooth
+/// minimum/maximum, where `diff` is the difference of both operands.
+fn smooth_correction_glsl(diff: &str, k: f32) -> String {
+    format!(
+        "pow(max({k} - abs({diff}), 0.0), 2.0) / (4.0 * {k})",
+        k = Float(k),
+        diff = diff,
+    )
+}
+
+/// Emits the GLSL function of a shape combining the two given shapes.
+/// `combine` is a GLSL expression combining the children's distances `a` and
+/// `b`.
+fn combined_shader(
+    glsl: &mut GlslBuilder,
+    name: &str,
+    a: &dyn Shape,
+    b: &dyn Shape,
+    combine: &str,
+) -> Ident {
+    let a = glsl.shape(a);
+    let b = glsl.shape(b);
+
+    glsl.function(name, include_str!("csg.frag"), &[
+        ("A", &a),
+        ("B", &b),
+        ("COMBINE", &Code(combine.to_string())),
+    ])
+}
+
+fn bb_union(a: Range<Point3<f32>>, b: Range<Point3<f32>>) -> Range<Point3<f32>> {
+    let start = Point3::new(
+        a.start.x.min(b.start.x),
+        a.start.y.min(b.start.y),
+        a.start.z.min(b.start.z),
+    );
+    let end = Point3::new(
+        a.end.x.max(b.end.x),
+        a.end.y.max(b.end.y),
+        a.end.z.max(b.end.z),
+    );
+
+    start .. end
+}
+
+fn bb_intersection(a: Range<Point3<f32>>, b: Range<Point3<f32>>) -> Range<Point3<f32>> {
+    let start = Point3::new(
+        a.start.x.max(b.start.x),
+        a.start.y.max(b.start.y),
+        a.start.z.max(b.start.z),
+    );
+    let end = Point3::new(
+        a.end.x.min(b.end.x),
+        a.end.y.min(b.end.y),
+        a.end.z.min(b.end.z),
+    );
+
+    // If the boxes don't overlap, the intersection is empty. But we can't
+    // return an empty box, so we simply return one of the original boxes.
+    // The shape won't have any surface in there anyway.
+    if start.x < end.x && start.y < end.y && start.z < end.z {
+        start .. end
+    } else {
+        a
+    }
+}
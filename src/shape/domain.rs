@@ -0,0 +1,506 @@
+//! Shapes which modify the space their inner shape is evaluated in ("domain
+//! operations"): mirroring, repetition, twisting and bending.
+
+use std::ops::Range;
+use cgmath::{prelude::*, BaseFloat, Point2, Point3, Vector2, Vector3};
+
+use super::{ColorData, Shape, glsl::{GlslBuilder, Ident}};
+
+
+/// Mirrors the part of the inner shape on the positive side of some of the
+/// coordinate planes onto the negative side. The part on the negative side
+/// is discarded.
+#[derive(Clone)]
+pub struct Mirror<S> {
+    shape: S,
+    axes: [bool; 3],
+}
+
+impl<S: Shape> Mirror<S> {
+    /// `axes` says which coordinates are mirrored, e.g. `[true, false,
+    /// false]` mirrors at the plane `x = 0`.
+    pub fn new(shape: S, axes: [bool; 3]) -> Self {
+        Self { shape, axes }
+    }
+
+    fn to_inner<F: BaseFloat>(&self, p: Point3<F>) -> Point3<F> {
+        let m = |v: F, mirrored: bool| if mirrored { v.abs() } else { v };
+        Point3::new(m(p.x, self.axes[0]), m(p.y, self.axes[1]), m(p.z, self.axes[2]))
+    }
+}
+
+impl<S: Shape> Shape for Mirror<S> {
+    // Mirroring doesn't change distances. Points on the positive side are
+    // at least as far away from the mirrored part as from the original part,
+    // so the inner DE is still a lower bound.
+    //
+    // There is no upper bound: the closest surface point might be in the
+    // discarded part.
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.shape.min_distance_from(self.to_inner(p))
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.shape.min_distance_from_f64(self.to_inner(p))
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        self.shape.colored_distance_from(self.to_inner(p))
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        let inner = self.shape.gradient_at(self.to_inner(p), delta);
+        let m = |g: f64, v: f64, mirrored: bool| if mirrored && v < 0.0 { -g } else { g };
+        Vector3::new(
+            m(inner.x, p.x, self.axes[0]),
+            m(inner.y, p.y, self.axes[1]),
+            m(inner.z, p.z, self.axes[2]),
+        )
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let Range { mut start, mut end } = self.shape.bounding_box();
+        for axis in 0..3 {
+            if self.axes[axis] {
+                let extent = end[axis].max(0.0);
+                start[axis] = -extent;
+                end[axis] = extent;
+            }
+        }
+
+        start..end
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let inner = glsl.shape(&self.shape);
+        let mask = Vector3::new(self.axes[0], self.axes[1], self.axes[2])
+            .map(|m| if m { 1.0 } else { 0.0 });
+
+        glsl.function("mirror", include_str!("mirror.frag"), &[
+            ("INNER", &inner),
+            ("MASK", &mask),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+
+/// Repeats the inner shape along some axes, either infinitely or a given
+/// number of times. The copies are centered around the origin.
+///
+/// The DE is the minimum of the inner DE over all copies which could be
+/// closer than the copy in whose cell the point is. This is correct even if
+/// the inner shape is not centered or doesn't fit into one cell, but of
+/// course it's faster if it does.
+#[derive(Clone)]
+pub struct Repeat<S> {
+    shape: S,
+
+    /// The distance between two copies along each axis. Axes with the period
+    /// 0 are not repeated.
+    period: Vector3<f32>,
+
+    /// The copies are at `c * period` for all `c` in `[-limit, limit]` which
+    /// differ from `shift` by an integer. `limit` is infinite for infinite
+    /// repetition and 0 for axes which are not repeated.
+    limit: Vector3<f32>,
+    shift: Vector3<f32>,
+
+    /// How many neighboring cells on each side have to be checked.
+    reach: [i32; 3],
+
+    /// The inner shape's bounding box.
+    inner_bb: Range<Point3<f32>>,
+}
+
+impl<S: Shape> Repeat<S> {
+    /// Creates copies every `period[axis]` along all axes with a positive
+    /// period. `count[axis]` is the number of copies along that axis, 0 means
+    /// infinitely many.
+    ///
+    /// Panics if a period is negative or the inner shape's bounding box is
+    /// not finite along a repeated axis.
+    pub fn new(shape: S, period: Vector3<f32>, count: [u32; 3]) -> Self {
+        let inner_bb = shape.bounding_box();
+        let mut limit = Vector3::zero();
+        let mut shift = Vector3::zero();
+        let mut reach = [0; 3];
+
+        for axis in 0..3 {
+            let p = period[axis];
+            assert!(p >= 0.0 && p.is_finite(), "invalid period {}", p);
+            if p == 0.0 {
+                continue;
+            }
+
+            let (start, end) = (inner_bb.start[axis], inner_bb.end[axis]);
+            assert!(
+                start.is_finite() && end.is_finite(),
+                "can't repeat a shape with an infinite bounding box",
+            );
+
+            if count[axis] == 0 {
+                limit[axis] = f32::INFINITY;
+            } else {
+                limit[axis] = (count[axis] - 1) as f32 / 2.0;
+                shift[axis] = limit[axis].fract();
+            }
+
+            // Along this axis, the point is at most `p / 2` away from the
+            // center of its cell (or outside of all cells) and each point of
+            // the inner shape at most `radius` from the center of its copy.
+            // So a point of the copy `k` cells away can only be closer than
+            // the same point of the own copy if `(k - 1) * p < 2 * radius`.
+            let radius = start.abs().max(end.abs());
+            reach[axis] = (2.0 * radius / p).ceil() as i32;
+        }
+
+        Self { shape, period, limit, shift, reach, inner_bb }
+    }
+
+    /// Returns the smallest DE (calculated by `de`) of all copies and the
+    /// point in the inner shape's space for which it was calculated.
+    fn closest_copy<F: BaseFloat>(
+        &self,
+        p: Point3<F>,
+        de: impl Fn(Point3<F>) -> F,
+    ) -> (F, Point3<F>) {
+        let f = |v: f32| F::from(v).unwrap();
+        let bb_start = self.inner_bb.start.cast::<F>().unwrap();
+        let bb_end = self.inner_bb.end.cast::<F>().unwrap();
+
+        // The cell containing `p`, i.e. the copy whose center is closest.
+        let mut base = [F::zero(); 3];
+        for axis in 0..3 {
+            if self.period[axis] != 0.0 {
+                let (period, shift) = (f(self.period[axis]), f(self.shift[axis]));
+                let limit = f(self.limit[axis]);
+                let cell = (p[axis] / period - shift).round() + shift;
+                base[axis] = cell.max(-limit).min(limit);
+            }
+        }
+
+        let inner_point = |cell: [F; 3]| {
+            let offset = Vector3::new(cell[0], cell[1], cell[2])
+                .mul_element_wise(self.period.cast::<F>().unwrap());
+            p - offset
+        };
+
+        let mut best_q = inner_point(base);
+        let mut best = de(best_q);
+
+        let [rx, ry, rz] = self.reach;
+        for dx in -rx..=rx {
+            for dy in -ry..=ry {
+                for dz in -rz..=rz {
+                    if (dx, dy, dz) == (0, 0, 0) {
+                        continue;
+                    }
+
+                    let mut cell = base;
+                    let mut valid = true;
+                    for (axis, &d) in [dx, dy, dz].iter().enumerate() {
+                        cell[axis] += F::from(d).unwrap();
+                        valid &= cell[axis].abs() <= f(self.limit[axis]);
+                    }
+                    if !valid {
+                        continue;
+                    }
+
+                    // The copy is inside its bounding box, so it can't be
+                    // closer than that.
+                    let q = inner_point(cell);
+                    if box_distance(q, bb_start, bb_end) >= best {
+                        continue;
+                    }
+
+                    let d = de(q);
+                    if d < best {
+                        best = d;
+                        best_q = q;
+                    }
+                }
+            }
+        }
+
+        (best, best_q)
+    }
+}
+
+impl<S: Shape> Shape for Repeat<S> {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.closest_copy(p, |q| self.shape.min_distance_from(q)).0
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.closest_copy(p, |q| self.shape.min_distance_from_f64(q)).0
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let (_, q) = self.closest_copy(p, |q| self.shape.min_distance_from_f64(q));
+        self.shape.colored_distance_from(q)
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        let (_, q) = self.closest_copy(p, |q| self.shape.min_distance_from_f64(q));
+        self.shape.gradient_at(q, delta)
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // `limit` is infinite for infinite repetition, resulting in an
+        // infinite box. Axes which are not repeated have a limit of 0.
+        let extent = self.limit.mul_element_wise(self.period);
+        self.inner_bb.start - extent..self.inner_bb.end + extent
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let inner = glsl.shape(&self.shape);
+
+        // To avoid divisions by zero, we use a period of 1 for axes which
+        // are not repeated. Their limit is 0, so it doesn't matter.
+        let period = self.period.map(|p| if p == 0.0 { 1.0 } else { p });
+
+        glsl.function("repeat", include_str!("repeat.frag"), &[
+            ("INNER", &inner),
+            ("PERIOD", &period),
+            ("SHIFT", &self.shift),
+            ("LIMIT", &self.limit),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+/// The distance from `p` to the box from `start` to `end` (0 inside).
+fn box_distance<F: BaseFloat>(p: Point3<F>, start: Point3<F>, end: Point3<F>) -> F {
+    let outside = |v: F, start: F, end: F| (start - v).max(F::zero()).max(v - end);
+    Vector3::new(
+        outside(p.x, start.x, end.x),
+        outside(p.y, start.y, end.y),
+        outside(p.z, start.z, end.z),
+    ).magnitude()
+}
+
+
+/// Twists the inner shape around the z axis: the shape is rotated by an
+/// angle proportional to `z`.
+#[derive(Clone)]
+pub struct Twist<S> {
+    shape: S,
+
+    /// The rotation angle per unit length along the z axis in radians.
+    rate: f32,
+
+    /// The largest distance of the inner shape from the z axis.
+    radius: f32,
+}
+
+impl<S: Shape> Twist<S> {
+    /// `rate` is the angle (in radians) by which the shape is rotated per
+    /// unit length along the z axis.
+    pub fn new(shape: S, rate: f32) -> Self {
+        let radius = max_axis_distance(&shape.bounding_box(), 0, 1);
+        Self { shape, rate, radius }
+    }
+
+    /// Returns the point in the inner shape's space and the factor by which
+    /// the inner DE has to be divided.
+    ///
+    /// The twist stretches space, the more the further away from the axis.
+    /// With `t = rate * r`, its Jacobian has the largest singular value
+    /// `t / 2 + sqrt(1 + t² / 4)`. On the straight line from `p` to the
+    /// closest surface point, `r` is at most `max(r_p, radius)`.
+    fn to_inner<F: BaseFloat>(&self, p: Point3<F>) -> (Point3<F>, F) {
+        let rate = F::from(self.rate).unwrap();
+        let xy = rotate(Point2::new(p.x, p.y), -rate * p.z);
+
+        let r = Vector2::new(p.x, p.y).magnitude().max(F::from(self.radius).unwrap());
+        let t = (rate * r).abs();
+        let two = F::from(2.0).unwrap();
+        let lipschitz = t / two + (F::one() + t * t / (two * two)).sqrt();
+
+        (Point3::new(xy.x, xy.y, p.z), lipschitz)
+    }
+}
+
+impl<S: Shape> Shape for Twist<S> {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let (q, lipschitz) = self.to_inner(p);
+        self.shape.min_distance_from(q) / lipschitz
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        let (q, lipschitz) = self.to_inner(p);
+        self.shape.min_distance_from_f64(q) / lipschitz
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let (q, lipschitz) = self.to_inner(p);
+        let (d, color) = self.shape.colored_distance_from(q);
+        (d / lipschitz, color)
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // Rotating around the z axis doesn't change the distance from it.
+        let bb = self.shape.bounding_box();
+        let r = self.radius;
+        Point3::new(-r, -r, bb.start.z)..Point3::new(r, r, bb.end.z)
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let inner = glsl.shape(&self.shape);
+        glsl.function("twist", include_str!("twist.frag"), &[
+            ("INNER", &inner),
+            ("RATE", &self.rate),
+            ("RADIUS", &self.radius),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+
+/// Bends the inner shape around the y axis: the shape is rotated in the
+/// x-z-plane by an angle proportional to `x`. A bar along the x axis becomes
+/// an arc.
+#[derive(Clone)]
+pub struct Bend<S> {
+    shape: S,
+
+    /// The rotation angle per unit length along the x axis in radians.
+    rate: f32,
+
+    /// The largest distance of the inner shape from the y axis.
+    radius: f32,
+}
+
+impl<S: Shape> Bend<S> {
+    /// `rate` is the angle (in radians) by which the shape is rotated per
+    /// unit length along the x axis.
+    pub fn new(shape: S, rate: f32) -> Self {
+        let radius = max_axis_distance(&shape.bounding_box(), 0, 2);
+        Self { shape, rate, radius }
+    }
+
+    /// Like `Twist::to_inner`. As the rotation angle depends on `x`, which
+    /// is rotated itself, the Jacobian is not as nice. We use the simple
+    /// bound `1 + rate * r` for its largest singular value.
+    fn to_inner<F: BaseFloat>(&self, p: Point3<F>) -> (Point3<F>, F) {
+        let rate = F::from(self.rate).unwrap();
+        let xz = rotate(Point2::new(p.x, p.z), -rate * p.x);
+
+        let r = Vector2::new(p.x, p.z).magnitude().max(F::from(self.radius).unwrap());
+        let lipschitz = F::one() + (rate * r).abs();
+
+        (Point3::new(xz.x, p.y, xz.y), lipschitz)
+    }
+}
+
+impl<S: Shape> Shape for Bend<S> {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let (q, lipschitz) = self.to_inner(p);
+        self.shape.min_distance_from(q) / lipschitz
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        let (q, lipschitz) = self.to_inner(p);
+        self.shape.min_distance_from_f64(q) / lipschitz
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let (q, lipschitz) = self.to_inner(p);
+        let (d, color) = self.shape.colored_distance_from(q);
+        (d / lipschitz, color)
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // Rotating in the x-z-plane doesn't change the distance from the y
+        // axis.
+        let bb = self.shape.bounding_box();
+        let r = self.radius;
+        Point3::new(-r, bb.start.y, -r)..Point3::new(r, bb.end.y, r)
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let inner = glsl.shape(&self.shape);
+        glsl.function("bend", include_str!("bend.frag"), &[
+            ("INNER", &inner),
+            ("RATE", &self.rate),
+            ("RADIUS", &self.radius),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+/// Rotates `p` around the origin by `angle` (in radians).
+fn rotate<F: BaseFloat>(p: Point2<F>, angle: F) -> Point2<F> {
+    let (sin, cos) = angle.sin_cos();
+    Point2::new(cos * p.x - sin * p.y, sin * p.x + cos * p.y)
+}
+
+/// Returns the largest distance of all points in `bb` from the axis
+/// orthogonal to the two given axes.
+fn max_axis_distance(bb: &Range<Point3<f32>>, a: usize, b: usize) -> f32 {
+    let max_abs = |axis: usize| bb.start[axis].abs().max(bb.end[axis].abs());
+    Vector2::new(max_abs(a), max_abs(b)).magnitude()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use cgmath::{Point3, Vector3};
+
+    use crate::shape::{
+        check::{check, CheckSettings},
+        Shape, Sphere, Transformed, Union,
+    };
+    use super::{Bend, Mirror, Repeat, Twist};
+
+    /// Two spheres of different size, not centered at the origin.
+    fn blob() -> Arc<dyn Shape> {
+        Arc::new(Union::new(
+            Arc::new(Sphere::new(Point3::new(0.3, 0.1, -0.2), 0.4)),
+            Arc::new(Sphere::new(Point3::new(-0.2, -0.3, 0.3), 0.25)),
+        ))
+    }
+
+    #[test]
+    fn mirror() {
+        check(&Mirror::new(blob(), [true, false, true]), &CheckSettings::default()).assert_ok();
+    }
+
+    #[test]
+    fn repeat() {
+        // Copies overlapping each other and copies not touching.
+        for &period in &[0.6, 1.5] {
+            let repeated = Repeat::new(blob(), Vector3::new(period, 0.0, period), [3, 0, 2]);
+            check(&repeated, &CheckSettings::default()).assert_ok();
+        }
+
+        // Infinite repetition is the same as finite repetition, as long as
+        // there are enough copies.
+        let period = Vector3::new(1.2, 1.2, 0.0);
+        let infinite = Repeat::new(blob(), period, [0, 0, 0]);
+        let finite = Repeat::new(blob(), period, [7, 7, 0]);
+        assert!(infinite.bounding_box().end.x.is_infinite());
+        for &p in &[Point3::new(0.1, 0.2, 0.3), Point3::new(2.5, -1.9, 0.0)] {
+            assert_eq!(infinite.min_distance_from(p), finite.min_distance_from(p));
+        }
+    }
+
+    #[test]
+    fn twist_and_bend() {
+        let bar = Arc::new(Union::new(
+            blob(),
+            Arc::new(Transformed::translated(blob(), Vector3::new(0.8, 0.0, 0.6))),
+        ));
+
+        let settings = CheckSettings::default();
+        check(&Twist::new(bar.clone(), 2.0), &settings).assert_ok();
+        check(&Bend::new(bar, 0.8), &settings).assert_ok();
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use cgmath::{prelude::*, Point3, Vector3};
+
+
+/// The number types distance estimators can be written for, so that the same
+/// code can be evaluated with `f64` and with `Dual` (to get the gradient).
+pub(crate) trait Real:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn constant(v: f64) -> Self;
+
+    /// The plain value, without any derivatives.
+    fn value(self) -> f64;
+
+    fn sqrt(self) -> Self;
+    fn ln(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan2(self, x: Self) -> Self;
+
+    /// Calculates `self^e`, but uses the faster `powi` for integer exponents.
+    fn pow(self, e: f64) -> Self;
+}
+
+macro_rules! impl_real_for_float {
+    ($ty:ident) => {
+        impl Real for $ty {
+            fn constant(v: f64) -> Self { v as $ty }
+            fn value(self) -> f64 { self.into() }
+            fn sqrt(self) -> Self { $ty::sqrt(self) }
+            fn ln(self) -> Self { $ty::ln(self) }
+            fn sin(self) -> Self { $ty::sin(self) }
+            fn cos(self) -> Self { $ty::cos(self) }
+            fn acos(self) -> Self { $ty::acos(self) }
+            fn atan2(self, x: Self) -> Self { $ty::atan2(self, x) }
+
+            fn pow(self, e: f64) -> Self {
+                if e.fract() == 0.0 {
+                    self.powi(e as i32)
+                } else {
+                    self.powf(e as $ty)
+                }
+            }
+        }
+    }
+}
+
+impl_real_for_float!(f32);
+impl_real_for_float!(f64);
+
+/// A dual number: a value together with its gradient with respect to the
+/// sample point. Evaluating a distance estimator with dual numbers yields its
+/// exact gradient (forward mode automatic differentiation).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Dual {
+    pub value: f64,
+    pub grad: Vector3<f64>,
+}
+
+impl Dual {
+    /// Returns the components of `p` as dual numbers, i.e. each one with the
+    /// respective unit vector as gradient.
+    pub fn point(p: Point3<f64>) -> [Self; 3] {
+        [
+            Dual { value: p.x, grad: Vector3::unit_x() },
+            Dual { value: p.y, grad: Vector3::unit_y() },
+            Dual { value: p.z, grad: Vector3::unit_z() },
+        ]
+    }
+
+    /// Applies a function with the given value and derivative (both at
+    /// `self.value`) using the chain rule.
+    fn chain(self, value: f64, derivative: f64) -> Self {
+        Dual { value, grad: self.grad * derivative }
+    }
+}
+
+impl Add for Dual {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Dual { value: self.value + rhs.value, grad: self.grad + rhs.grad }
+    }
+}
+
+impl Sub for Dual {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Dual { value: self.value - rhs.value, grad: self.grad - rhs.grad }
+    }
+}
+
+impl Mul for Dual {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Dual {
+            value: self.value * rhs.value,
+            grad: self.grad * rhs.value + rhs.grad * self.value,
+        }
+    }
+}
+
+impl Div for Dual {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Dual {
+            value: self.value / rhs.value,
+            grad: (self.grad * rhs.value - rhs.grad * self.value) / (rhs.value * rhs.value),
+        }
+    }
+}
+
+impl Neg for Dual {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Dual { value: -self.value, grad: -self.grad }
+    }
+}
+
+impl Real for Dual {
+    fn constant(v: f64) -> Self {
+        Dual { value: v, grad: Vector3::zero() }
+    }
+
+    fn value(self) -> f64 {
+        self.value
+    }
+
+    fn sqrt(self) -> Self {
+        let s = self.value.sqrt();
+        self.chain(s, 0.5 / s)
+    }
+
+    fn ln(self) -> Self {
+        self.chain(self.value.ln(), 1.0 / self.value)
+    }
+
+    fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+
+    fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+
+    fn acos(self) -> Self {
+        self.chain(self.value.acos(), -1.0 / (1.0 - self.value * self.value).sqrt())
+    }
+
+    fn atan2(self, x: Self) -> Self {
+        let y = self;
+        let denom = x.value * x.value + y.value * y.value;
+        Dual {
+            value: y.value.atan2(x.value),
+            grad: (y.grad * x.value - x.grad * y.value) / denom,
+        }
+    }
+
+    fn pow(self, e: f64) -> Self {
+        let value = Real::pow(self.value, e);
+        self.chain(value, e * Real::pow(self.value, e - 1.0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{prelude::*, Point3, Vector3};
+
+    use super::{Dual, Real};
+
+    /// Uses all operations of `Real`.
+    fn f<R: Real>([x, y, z]: [R; 3]) -> R {
+        let r = (x * x + y * y + z * z).sqrt();
+        let theta = (z / r).acos();
+        let phi = y.atan2(x);
+        let a = (theta * R::constant(3.0)).sin() * phi.cos() - r.ln();
+        let b = r.pow(3.0) + r.pow(2.5) / (R::constant(1.0) + x * x);
+        -(a * b)
+    }
+
+    #[test]
+    fn matches_central_differences() {
+        const DELTA: f64 = 1e-6;
+        let points = [
+            Point3::new(0.3, -0.7, 0.2),
+            Point3::new(-1.2, 0.4, 0.9),
+            Point3::new(0.05, 0.6, -0.45),
+        ];
+
+        for &p in &points {
+            let diff = |v: Vector3<f64>| {
+                let a = f((p + v * DELTA).into());
+                let b = f((p - v * DELTA).into());
+                (a - b) / (2.0 * DELTA)
+            };
+            let expected = Vector3::new(
+                diff(Vector3::unit_x()),
+                diff(Vector3::unit_y()),
+                diff(Vector3::unit_z()),
+            );
+
+            let dual: Dual = f(Dual::point(p));
+            assert!((dual.value - f(p.into())).abs() < 1e-12);
+            assert!(
+                (dual.grad - expected).magnitude() < 1e-6 * expected.magnitude().max(1.0),
+                "gradient at {:?} is {:?}, but central differences give {:?}",
+                p,
+                dual.grad,
+                expected,
+            );
+        }
+    }
+}
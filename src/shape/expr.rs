@@ -0,0 +1,1035 @@
+use std::{collections::HashMap, f32::consts::PI, fmt, path::Path, sync::Arc};
+use cgmath::{prelude::*, Deg, Point3, Quaternion, Rad, Vector3};
+
+use crate::prelude::*;
+use super::{
+    AnimatedShape, Bend, DynMandelbulb, Difference, Intersection, Juliabulb, KaleidoscopicIfs,
+    Interpolation, Mandelbox, Mandelbulb, MeshShape, Mirror, QuaternionJulia, Repeat,
+    SampledShape, Shape, Sphere, Transformed, Twist, Union,
+};
+
+
+/// Parses the given shape expression, which may depend on time.
+///
+/// An expression is a single function call, arguments are either numbers,
+/// strings (in double quotes, without escapes) or other function calls.
+/// Arguments can be passed by position or by name (but positional arguments
+/// have to come first). Example:
+///
+/// ```text
+/// smooth_union(mandelbulb(power=8), translate(sphere(0.5), 1, 0, 0), k=0.1)
+/// union(mesh("models/bunny.obj"), scale(menger(), 0.3))
+/// repeat(twist(mirror(translate(sphere(0.3), 0.4, 0, 0)), angle=90), 2, 2, 0)
+/// ```
+///
+/// The expression is turned into a tree of the normal shape types, so the
+/// resulting shape's distance estimator and GLSL code are generated from the
+/// very same tree. See `build()` for the list of available functions.
+///
+/// Numbers can also be given by functions of time (see `eval_number()`),
+/// e.g. `mandelbulb(power=oscillate(6, 10, period=5))`. The returned
+/// animation builds the shape at any point in time. The shape at time 0 is
+/// built anyway to check the expression, so it is returned as well.
+pub fn parse_animated(src: &str) -> Result<(ExprAnimation, Arc<dyn Shape>)> {
+    ExprAnimation::new(Parser::new(src)?.parse()?)
+}
+
+/// A shape expression which depends on time. See `parse_animated`.
+pub struct ExprAnimation {
+    /// Boxed, so that its address is stable (see `static_shapes`).
+    expr: Box<Expr>,
+
+    /// Shapes built from subexpressions which don't depend on time, keyed by
+    /// the address of the subexpression. The tree is never modified, so the
+    /// addresses are stable. Building some shapes is expensive (e.g. loading
+    /// meshes), so we don't want to do it for each frame.
+    static_shapes: HashMap<usize, Arc<dyn Shape>>,
+}
+
+impl ExprAnimation {
+    fn new(expr: Expr) -> Result<(Self, Arc<dyn Shape>)> {
+        fn collect(expr: &Expr, out: &mut HashMap<usize, Arc<dyn Shape>>) -> Result<()> {
+            if let Expr::Call { args, .. } = expr {
+                for arg in args {
+                    match &arg.value {
+                        e @ Expr::Call { .. } if !e.is_animated() => {
+                            out.insert(e as *const _ as usize, build(e, &Context::at(0.0))?);
+                        }
+                        e => collect(e, out)?,
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        // The root shape itself is never cached, so that each call to `at`
+        // returns a new shape whose parameters can be changed.
+        let expr = Box::new(expr);
+        let mut static_shapes = HashMap::new();
+        if expr.is_animated() {
+            collect(&expr, &mut static_shapes)?;
+        }
+
+        // This also makes sure that the expression is valid at all.
+        let out = Self { expr, static_shapes };
+        let shape = out.at(0.0)?;
+        Ok((out, shape))
+    }
+}
+
+impl AnimatedShape for ExprAnimation {
+    fn at(&self, t: f32) -> Result<Arc<dyn Shape>> {
+        let ctx = Context {
+            time: t,
+            static_shapes: Some(&self.static_shapes),
+        };
+        build(&self.expr, &ctx).with_context(|| format!("invalid shape at time {}s", t))
+    }
+
+    fn is_animated(&self) -> bool {
+        self.expr.is_animated()
+    }
+}
+
+/// The syntax tree of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    String(String),
+    Call {
+        name: String,
+        args: Vec<Arg>,
+    },
+}
+
+/// An argument of a function call. `name` is `None` for positional
+/// arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arg {
+    pub name: Option<String>,
+    pub value: Expr,
+}
+
+impl Expr {
+    /// Returns whether the value of this expression depends on time, i.e. if
+    /// it contains calls to number functions (see `eval_number()`).
+    pub fn is_animated(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::String(_) => false,
+            Expr::Call { name, args } => {
+                NUMBER_FUNCTIONS.contains(&name.as_str())
+                    || args.iter().any(|arg| arg.value.is_animated())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::String(s) => write!(f, "\"{}\"", s),
+            Expr::Call { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    if let Some(name) = &arg.name {
+                        write!(f, "{}=", name)?;
+                    }
+                    write!(f, "{}", arg.value)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+
+// ===========================================================================
+// ===== Lexing and parsing
+// ===========================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f32),
+    String(String),
+    ParenOpen,
+    ParenClose,
+    Comma,
+    Equals,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "identifier '{}'", s),
+            Token::Number(n) => write!(f, "number '{}'", n),
+            Token::String(s) => write!(f, "string \"{}\"", s),
+            Token::ParenOpen => write!(f, "'('"),
+            Token::ParenClose => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Equals => write!(f, "'='"),
+        }
+    }
+}
+
+/// Splits the input into tokens. Each token is stored with its byte offset
+/// for error messages.
+fn lex(src: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => Token::ParenOpen,
+            ')' => Token::ParenClose,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '"' => {
+                chars.next();
+                let end = loop {
+                    match chars.next() {
+                        Some((i, '"')) => break i,
+                        Some(_) => {}
+                        None => bail!("unterminated string starting at position {}", pos),
+                    }
+                };
+
+                tokens.push((pos, Token::String(src[pos + 1..end].to_string())));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = pos;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+
+                tokens.push((pos, Token::Ident(src[pos..end].to_string())));
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut end = pos;
+                let mut prev = None;
+                while let Some(&(i, c)) = chars.peek() {
+                    // Signs are only allowed at the start and after the
+                    // exponent character.
+                    let is_sign = (c == '-' || c == '+')
+                        && (i == pos || prev == Some('e') || prev == Some('E'));
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || is_sign) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    prev = Some(c);
+                    chars.next();
+                }
+
+                let s = &src[pos..end];
+                let n: f32 = s.parse()
+                    .map_err(|_| anyhow!("invalid number '{}' at position {}", s, pos))?;
+                if !n.is_finite() {
+                    bail!("number '{}' at position {} is too large", s, pos);
+                }
+                tokens.push((pos, Token::Number(n)));
+                continue;
+            }
+            other => bail!("unexpected character '{}' at position {}", other, pos),
+        };
+
+        chars.next();
+        tokens.push((pos, token));
+    }
+
+    Ok(tokens)
+}
+
+/// A simple recursive descent parser.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+
+    /// Length of the input, used for "unexpected end" errors.
+    len: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: lex(src)?,
+            pos: 0,
+            len: src.len(),
+        })
+    }
+
+    fn parse(mut self) -> Result<Expr> {
+        let expr = self.expr()?;
+        if let Some((pos, token)) = self.tokens.get(self.pos) {
+            bail!("expected end of input, found {} at position {}", token, pos);
+        }
+
+        Ok(expr)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token)> {
+        let out = self.tokens.get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end of input at position {}", self.len))?;
+        self.pos += 1;
+        Ok(out)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let (pos, token) = self.next()?;
+        if token != expected {
+            bail!("expected {}, found {} at position {}", expected, token, pos);
+        }
+
+        Ok(())
+    }
+
+    /// expr := number | string | ident '(' args? ')'
+    fn expr(&mut self) -> Result<Expr> {
+        match self.next()? {
+            (_, Token::Number(n)) => Ok(Expr::Number(n)),
+            (_, Token::String(s)) => Ok(Expr::String(s)),
+            (_, Token::Ident(name)) => {
+                self.expect(Token::ParenOpen)?;
+                let args = self.args()?;
+                self.expect(Token::ParenClose)?;
+                Ok(Expr::Call { name, args })
+            }
+            (pos, token) => bail!("expected expression, found {} at position {}", token, pos),
+        }
+    }
+
+    /// args := arg (',' arg)*
+    /// arg  := (ident '=')? expr
+    fn args(&mut self) -> Result<Vec<Arg>> {
+        let mut args: Vec<Arg> = Vec::new();
+        if self.peek() == Some(&Token::ParenClose) {
+            return Ok(args);
+        }
+
+        loop {
+            let start = self.tokens.get(self.pos).map(|(pos, _)| *pos).unwrap_or(self.len);
+
+            // To find out if this is a named argument, we have to look at the
+            // token after the identifier.
+            let is_named = matches!(self.peek(), Some(Token::Ident(_)))
+                && self.tokens.get(self.pos + 1).map(|(_, t)| t) == Some(&Token::Equals);
+            let name = if is_named {
+                match self.next()? {
+                    (_, Token::Ident(name)) => {
+                        self.expect(Token::Equals)?;
+                        Some(name)
+                    }
+                    _ => unreachable!(),
+                }
+            } else {
+                if args.iter().any(|a| a.name.is_some()) {
+                    bail!("positional argument after named argument at position {}", start);
+                }
+                None
+            };
+
+            let value = self.expr()?;
+            args.push(Arg { name, value });
+
+            if self.peek() == Some(&Token::Comma) {
+                self.next()?;
+            } else {
+                return Ok(args);
+            }
+        }
+    }
+}
+
+
+// ===========================================================================
+// ===== Building the shape
+// ===========================================================================
+
+/// Everything `build()` needs apart from the expression.
+struct Context<'a> {
+    /// The time in seconds at which number functions are evaluated.
+    time: f32,
+
+    /// See `ExprAnimation::static_shapes`.
+    static_shapes: Option<&'a HashMap<usize, Arc<dyn Shape>>>,
+}
+
+impl Context<'static> {
+    fn at(time: f32) -> Self {
+        Self {
+            time,
+            static_shapes: None,
+        }
+    }
+}
+
+/// The names of all functions which return numbers (see `eval_number()`).
+const NUMBER_FUNCTIONS: &[&str] = &["oscillate", "linear"];
+
+/// Builds the shape described by the given expression.
+fn build(expr: &Expr, ctx: &Context<'_>) -> Result<Arc<dyn Shape>> {
+    let key = expr as *const _ as usize;
+    if let Some(shape) = ctx.static_shapes.and_then(|shapes| shapes.get(&key)) {
+        return Ok(shape.clone());
+    }
+
+    let (name, args) = match expr {
+        Expr::Call { name, args } => (name.as_str(), args),
+        Expr::Number(n) => bail!("expected shape, found number '{}'", n),
+        Expr::String(s) => bail!("expected shape, found string \"{}\"", s),
+    };
+
+    let mut args = Args::new(name, args, ctx);
+    let shape: Arc<dyn Shape> = match name {
+        // ----- Primitives and fractals -------------------------------------
+        "sphere" => {
+            let radius = args.number("radius", Some(1.0))?;
+            if radius <= 0.0 {
+                bail!("radius of 'sphere' has to be positive, but is {}", radius);
+            }
+            Arc::new(Sphere::new(Point3::origin(), radius))
+        }
+        "mandelbulb" => {
+            let power = args.number("power", Some(8.0))?;
+            let max_iters = args.integer("max_iters", Some(6))?;
+            let bailout = args.number("bailout", Some(2.5))?;
+            check_bailout(name, bailout, 1.0)?;
+
+            if !DynMandelbulb::is_valid_power(power) {
+                bail!("the mandelbulb power must not be 0 or 1");
+            }
+
+            // Use the faster implementation if possible
+            if power == 8.0 {
+                Arc::new(Mandelbulb::classic(max_iters, bailout))
+            } else {
+                Arc::new(DynMandelbulb::new(power, max_iters, bailout))
+            }
+        }
+        "juliabulb" => {
+            let c = args.vector(["cx", "cy", "cz"], None)?;
+            let max_iters = args.integer("max_iters", Some(8))?;
+            let bailout = args.number("bailout", Some(4.0))?;
+            check_bailout(name, bailout, Juliabulb::<8>::min_bailout(c))?;
+            Arc::new(Juliabulb::classic(c, max_iters, bailout))
+        }
+        "julia" => {
+            let cs = args.number("cs", None)?;
+            let c = args.vector(["cx", "cy", "cz"], None)?;
+            let slice = args.number("slice", Some(0.0))?;
+            let max_iters = args.integer("max_iters", Some(12))?;
+            let bailout = args.number("bailout", Some(4.0))?;
+            let c = Quaternion::from_sv(cs, c);
+            check_bailout(name, bailout, QuaternionJulia::min_bailout(c))?;
+            Arc::new(QuaternionJulia::new(c, slice, max_iters, bailout))
+        }
+        "mandelbox" => {
+            let scale = args.number("scale", Some(2.0))?;
+            let fold_limit = args.number("fold_limit", Some(1.0))?;
+            let min_radius = args.number("min_radius", Some(0.5))?;
+            let fixed_radius = args.number("fixed_radius", Some(1.0))?;
+            let max_iters = args.integer("max_iters", Some(12))?;
+            if scale.abs() <= 1.0 {
+                bail!("the absolute value of the mandelbox scale has to be greater than 1");
+            }
+            if fold_limit <= 0.0 {
+                bail!("the mandelbox fold limit has to be positive");
+            }
+            if min_radius <= 0.0 || min_radius > fixed_radius {
+                bail!("the mandelbox radii have to satisfy 0 < min_radius <= fixed_radius");
+            }
+            Arc::new(Mandelbox::new(max_iters, scale, fold_limit, min_radius, fixed_radius))
+        }
+        "menger" => {
+            Arc::new(KaleidoscopicIfs::menger_sponge(args.integer("max_iters", Some(4))?))
+        }
+        "sierpinski_tetrahedron" => {
+            let max_iters = args.integer("max_iters", Some(8))?;
+            Arc::new(KaleidoscopicIfs::sierpinski_tetrahedron(max_iters))
+        }
+        "sierpinski_octahedron" => {
+            let max_iters = args.integer("max_iters", Some(8))?;
+            Arc::new(KaleidoscopicIfs::sierpinski_octahedron(max_iters))
+        }
+        "mesh" => Arc::new(MeshShape::load(args.string("path", None)?)?),
+        "volume" => {
+            let path = args.string("path", None)?;
+            let interpolation = interpolation(name, args.string("interpolation", Some("cubic"))?)?;
+            Arc::new(SampledShape::load(path, interpolation)?)
+        }
+
+        // ----- Combinations ------------------------------------------------
+        "union" | "smooth_union" => {
+            let (a, b) = (args.shape("a")?, args.shape("b")?);
+            let k = args.number("k", if name == "union" { Some(0.0) } else { None })?;
+            check_smoothness(name, k)?;
+            if k == 0.0 {
+                Arc::new(Union::new(a, b))
+            } else {
+                Arc::new(Union::smooth(a, b, k))
+            }
+        }
+        "intersection" | "smooth_intersection" => {
+            let (a, b) = (args.shape("a")?, args.shape("b")?);
+            let k = args.number("k", if name == "intersection" { Some(0.0) } else { None })?;
+            check_smoothness(name, k)?;
+            if k == 0.0 {
+                Arc::new(Intersection::new(a, b))
+            } else {
+                Arc::new(Intersection::smooth(a, b, k))
+            }
+        }
+        "difference" | "smooth_difference" => {
+            let (a, b) = (args.shape("a")?, args.shape("b")?);
+            let k = args.number("k", if name == "difference" { Some(0.0) } else { None })?;
+            check_smoothness(name, k)?;
+            if k == 0.0 {
+                Arc::new(Difference::new(a, b))
+            } else {
+                Arc::new(Difference::smooth(a, b, k))
+            }
+        }
+
+        // ----- Transformations ---------------------------------------------
+        "translate" => {
+            let shape = args.shape("shape")?;
+            let v = args.vector(["x", "y", "z"], Some(0.0))?;
+            Arc::new(Transformed::translated(shape, v))
+        }
+        "rotate" => {
+            let shape = args.shape("shape")?;
+            let angle = args.number("angle", None)?;
+            let axis = args.vector(["x", "y", "z"], None)?;
+            if axis.magnitude2() == 0.0 {
+                bail!("rotation axis of 'rotate' must not be zero");
+            }
+
+            let rotation = Quaternion::from_axis_angle(axis.normalize(), Deg(angle));
+            Arc::new(Transformed::rotated(shape, rotation))
+        }
+        "scale" => {
+            let shape = args.shape("shape")?;
+            let factor = args.number("factor", None)?;
+            if factor <= 0.0 {
+                bail!("scale factor has to be positive, but is {}", factor);
+            }
+            Arc::new(Transformed::scaled(shape, factor))
+        }
+
+        // ----- Domain operations -------------------------------------------
+        "mirror" => {
+            let shape = args.shape("shape")?;
+            let axes = args.string("axes", Some("x"))?;
+            let mask = [axes.contains('x'), axes.contains('y'), axes.contains('z')];
+            if axes.is_empty() || axes.chars().any(|c| !"xyz".contains(c)) {
+                bail!("axes of 'mirror' have to be some of x, y and z, found \"{}\"", axes);
+            }
+            Arc::new(Mirror::new(shape, mask))
+        }
+        "repeat" => {
+            let shape = args.shape("shape")?;
+            let period = args.vector(["x", "y", "z"], Some(0.0))?;
+            let count = args.vector(["nx", "ny", "nz"], Some(0.0))?;
+            let bb = shape.bounding_box();
+            for axis in 0..3 {
+                if period[axis] < 0.0 {
+                    bail!("periods of 'repeat' must not be negative");
+                }
+                if count[axis] < 0.0 || count[axis].fract() != 0.0 {
+                    bail!("counts of 'repeat' have to be non-negative integers (0 = infinite)");
+                }
+                let bounded = bb.start[axis].is_finite() && bb.end[axis].is_finite();
+                if period[axis] > 0.0 && !bounded {
+                    bail!("'repeat' requires the shape to be bounded along repeated axes");
+                }
+            }
+            if period.magnitude2() == 0.0 {
+                bail!("at least one period of 'repeat' has to be positive");
+            }
+
+            let count = [count.x as u32, count.y as u32, count.z as u32];
+            Arc::new(Repeat::new(shape, period, count))
+        }
+        "twist" => {
+            let shape = args.shape("shape")?;
+            let angle = args.number("angle", None)?;
+            check_bounded(name, &*shape, [0, 1])?;
+            Arc::new(Twist::new(shape, Rad::from(Deg(angle)).0))
+        }
+        "bend" => {
+            let shape = args.shape("shape")?;
+            let angle = args.number("angle", None)?;
+            check_bounded(name, &*shape, [0, 2])?;
+            Arc::new(Bend::new(shape, Rad::from(Deg(angle)).0))
+        }
+
+        // ----- Sampling ----------------------------------------------------
+        "sampled" => {
+            // The shape is only built if the samples are not cached.
+            let shape_expr = args.expr("shape")?;
+            let resolution = args.integer("resolution", Some(64))?;
+            let interpolation = interpolation(name, args.string("interpolation", Some("cubic"))?)?;
+            if !(8..=1024).contains(&resolution) {
+                bail!("resolution of 'sampled' has to be between 8 and 1024");
+            }
+            let resolution = resolution as u32;
+            let cache = args.string("cache", Some(""))?;
+
+            let sample = || -> Result<SampledShape> {
+                let shape = build(shape_expr, ctx).context("in argument 'shape' of 'sampled'")?;
+                let bb = shape.bounding_box();
+                if !(0..3).all(|axis| bb.start[axis].is_finite() && bb.end[axis].is_finite()) {
+                    bail!("'sampled' requires a bounded shape");
+                }
+                Ok(SampledShape::sample(&*shape, resolution, interpolation))
+            };
+
+            if cache.is_empty() {
+                Arc::new(sample()?)
+            } else {
+                if shape_expr.is_animated() {
+                    bail!("'sampled' can't cache the samples of a shape that depends on time");
+                }
+                Arc::new(load_or_sample(cache, shape_expr, resolution, interpolation, sample)?)
+            }
+        }
+
+        other => bail!("unknown shape '{}'", other),
+    };
+
+    args.finish()?;
+    Ok(shape)
+}
+
+/// Loads the samples for `sampled(...)` from the cache file at `path`, if it
+/// was written for the same shape expression and resolution. Otherwise the
+/// samples are created by `sample` and written to `path`.
+///
+/// Only the expression is compared, so changes in files it references (e.g.
+/// in `mesh(...)`) are not detected.
+fn load_or_sample(
+    path: &str,
+    shape_expr: &Expr,
+    resolution: u32,
+    interpolation: Interpolation,
+    sample: impl FnOnce() -> Result<SampledShape>,
+) -> Result<SampledShape> {
+    // A stable hash (FNV-1a) of the expression, as `DefaultHasher` may change
+    // between Rust versions.
+    let key = shape_expr.to_string().bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    if Path::new(path).exists() {
+        // Invalid files are an error instead of being overwritten, as the
+        // path might be wrong.
+        let cached = SampledShape::load(path, interpolation)?;
+        if cached.key() == key && cached.resolution() == resolution {
+            info!("Loaded samples of 'sampled' from '{}'", path);
+            return Ok(cached);
+        }
+        info!("Samples in '{}' belong to a different shape, sampling again", path);
+    }
+
+    let sampled = sample()?.with_key(key);
+    sampled.save(path)?;
+    info!("Saved samples of 'sampled' to '{}'", path);
+    Ok(sampled)
+}
+
+/// Evaluates a call of a number function. All of them depend on time:
+///
+/// - `oscillate(min, max, period=1, phase=0)`: oscillates smoothly (like a
+///   cosine) between `min` and `max` with the given period in seconds. It
+///   starts at `min`, unless `phase` (in periods) is given.
+/// - `linear(start, speed)`: starts at `start` and changes by `speed` per
+///   second.
+fn eval_number(name: &str, args: &[Arg], ctx: &Context<'_>) -> Result<f32> {
+    let mut args = Args::new(name, args, ctx);
+    let value = match name {
+        "oscillate" => {
+            let min = args.number("min", None)?;
+            let max = args.number("max", None)?;
+            let period = args.number("period", Some(1.0))?;
+            let phase = args.number("phase", Some(0.0))?;
+            if period <= 0.0 {
+                bail!("period of 'oscillate' has to be positive, but is {}", period);
+            }
+
+            let angle = 2.0 * PI * (ctx.time / period + phase);
+            min + (max - min) * (1.0 - angle.cos()) / 2.0
+        }
+        "linear" => {
+            let start = args.number("start", None)?;
+            let speed = args.number("speed", None)?;
+            start + speed * ctx.time
+        }
+        other => bail!("unknown number function '{}'", other),
+    };
+
+    args.finish()?;
+    Ok(value)
+}
+
+fn interpolation(fn_name: &str, name: &str) -> Result<Interpolation> {
+    match name {
+        "linear" => Ok(Interpolation::Trilinear),
+        "cubic" => Ok(Interpolation::Tricubic),
+        other => bail!(
+            "interpolation of '{}' has to be \"linear\" or \"cubic\", found \"{}\"",
+            fn_name,
+            other,
+        ),
+    }
+}
+
+fn check_bailout(fn_name: &str, bailout: f32, min: f32) -> Result<()> {
+    if bailout < min {
+        bail!("bailout of '{}' has to be at least {}, but is {}", fn_name, min, bailout);
+    }
+
+    Ok(())
+}
+
+fn check_smoothness(fn_name: &str, k: f32) -> Result<()> {
+    if k < 0.0 {
+        bail!("smoothness 'k' of '{}' must not be negative, but is {}", fn_name, k);
+    }
+
+    Ok(())
+}
+
+/// Makes sure the bounding box of `shape` is finite along the given axes.
+/// Twist and bend need this: the further away from their axis, the more they
+/// stretch space.
+fn check_bounded(fn_name: &str, shape: &dyn Shape, axes: [usize; 2]) -> Result<()> {
+    let bb = shape.bounding_box();
+    if !axes.iter().all(|&axis| bb.start[axis].is_finite() && bb.end[axis].is_finite()) {
+        let names = ["x", "y", "z"];
+        bail!(
+            "'{}' requires the shape to be bounded along the {} and {} axes",
+            fn_name,
+            names[axes[0]],
+            names[axes[1]],
+        );
+    }
+
+    Ok(())
+}
+
+/// Helper to bind the arguments of a call to the parameters of a shape.
+///
+/// The parameters have to be requested in the order of their declaration,
+/// such that positional arguments can be matched.
+struct Args<'a> {
+    fn_name: &'a str,
+    args: &'a [Arg],
+    ctx: &'a Context<'a>,
+    used: Vec<bool>,
+
+    /// The names of all parameters requested so far.
+    params: Vec<String>,
+}
+
+impl<'a> Args<'a> {
+    fn new(fn_name: &'a str, args: &'a [Arg], ctx: &'a Context<'a>) -> Self {
+        Self {
+            fn_name,
+            args,
+            ctx,
+            used: vec![false; args.len()],
+            params: Vec::new(),
+        }
+    }
+
+    /// Returns the argument for the next parameter, called `name`.
+    fn next(&mut self, name: &str) -> Option<&'a Expr> {
+        let idx = self.params.len();
+        self.params.push(name.to_string());
+
+        // Positional arguments always come first, so if there is a
+        // positional argument at this index, it belongs to this parameter.
+        let found = match self.args.get(idx) {
+            Some(Arg { name: None, .. }) => Some(idx),
+            _ => self.args.iter().position(|a| a.name.as_deref() == Some(name)),
+        };
+
+        found.map(|i| {
+            self.used[i] = true;
+            &self.args[i].value
+        })
+    }
+
+    fn number(&mut self, name: &str, default: Option<f32>) -> Result<f32> {
+        match (self.next(name), default) {
+            (Some(Expr::Number(n)), _) => Ok(*n),
+            (Some(Expr::Call { name: f, args }), _) if NUMBER_FUNCTIONS.contains(&f.as_str()) => {
+                eval_number(f, args, self.ctx)
+                    .with_context(|| format!("in argument '{}' of '{}'", name, self.fn_name))
+            }
+            (Some(other), _) => {
+                bail!(
+                    "argument '{}' of '{}' has to be a number, found '{}'",
+                    name,
+                    self.fn_name,
+                    other,
+                )
+            }
+            (None, Some(default)) => Ok(default),
+            (None, None) => bail!("missing argument '{}' of '{}'", name, self.fn_name),
+        }
+    }
+
+    fn integer(&mut self, name: &str, default: Option<u64>) -> Result<u64> {
+        let n = self.number(name, default.map(|d| d as f32))?;
+        if n < 1.0 || n.fract() != 0.0 {
+            bail!(
+                "argument '{}' of '{}' has to be a positive integer, found '{}'",
+                name,
+                self.fn_name,
+                n,
+            );
+        }
+
+        Ok(n as u64)
+    }
+
+    fn vector(&mut self, names: [&str; 3], default: Option<f32>) -> Result<Vector3<f32>> {
+        Ok(Vector3::new(
+            self.number(names[0], default)?,
+            self.number(names[1], default)?,
+            self.number(names[2], default)?,
+        ))
+    }
+
+    fn string(&mut self, name: &str, default: Option<&'a str>) -> Result<&'a str> {
+        match (self.next(name), default) {
+            (Some(Expr::String(s)), _) => Ok(s),
+            (Some(other), _) => {
+                bail!(
+                    "argument '{}' of '{}' has to be a string, found '{}'",
+                    name,
+                    self.fn_name,
+                    other,
+                )
+            }
+            (None, Some(default)) => Ok(default),
+            (None, None) => bail!("missing argument '{}' of '{}'", name, self.fn_name),
+        }
+    }
+
+    fn shape(&mut self, name: &str) -> Result<Arc<dyn Shape>> {
+        let expr = self.expr(name)?;
+        build(expr, self.ctx)
+            .with_context(|| format!("in argument '{}' of '{}'", name, self.fn_name))
+    }
+
+    /// Returns the argument without evaluating it.
+    fn expr(&mut self, name: &str) -> Result<&'a Expr> {
+        match self.next(name) {
+            Some(expr) => Ok(expr),
+            None => bail!("missing argument '{}' of '{}'", name, self.fn_name),
+        }
+    }
+
+    /// Makes sure all arguments were used.
+    fn finish(self) -> Result<()> {
+        if let Some(i) = self.used.iter().position(|used| !used) {
+            match &self.args[i].name {
+                // The parameter was already bound to another argument.
+                Some(name) if self.params.contains(name) => {
+                    bail!("argument '{}' of '{}' is given more than once", name, self.fn_name);
+                }
+                Some(name) => bail!("unknown argument '{}' of '{}'", name, self.fn_name),
+                None => bail!("too many arguments for '{}'", self.fn_name),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use cgmath::{prelude::*, Point3};
+
+    use crate::{prelude::*, shape::{AnimatedShape, Interpolation, SampledShape, Shape}};
+    use super::{parse_animated, Parser};
+
+    /// Builds the shape at time 0.
+    fn parse(src: &str) -> Result<Arc<dyn Shape>> {
+        Ok(parse_animated(src)?.1)
+    }
+
+    #[test]
+    fn parsing() {
+        let src = "union(sphere(), translate(sphere(radius=0.5), 1, -2e-1, z=+3))";
+        let expr = Parser::new(src).unwrap().parse().unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "union(sphere(), translate(sphere(radius=0.5), 1, -0.2, z=3))",
+        );
+        assert_eq!(
+            Parser::new(r#"volume(path="a b.vol")"#).unwrap().parse().unwrap().to_string(),
+            r#"volume(path="a b.vol")"#,
+        );
+
+        let invalid = [
+            "",
+            "sphere",
+            "sphere(",
+            "sphere(1,)",
+            "sphere(radius=)",
+            "sphere(radius 1)",
+            "sphere(radius=1, 2)",
+            "sphere(1.2.3)",
+            "sphere(1e99)",
+            "sphere(#)",
+            "sphere() sphere()",
+            r#"mesh("open)"#,
+        ];
+        for src in &invalid {
+            assert!(Parser::new(src).and_then(|p| p.parse()).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn building() {
+        let shape = parse("translate(sphere(2), 1, z=0)").unwrap();
+        assert_eq!(shape.min_distance_from(Point3::origin()), -1.0);
+        assert!(parse("difference(mandelbox(), scale(menger(3), 2))").is_ok());
+        assert!(parse("smooth_union(sphere(), mandelbulb(power=3), k=0.1)").is_ok());
+        assert!(parse("twist(repeat(sphere(0.3), z=1), 45)").is_ok());
+
+        let error = |src: &str| format!("{:#}", parse(src).err().expect(src));
+        let errors = [
+            ("cube()", "unknown shape 'cube'"),
+            ("1", "expected shape, found number '1'"),
+            ("sphere(radus=2)", "unknown argument 'radus' of 'sphere'"),
+            ("sphere(radius=1, radius=2)", "argument 'radius' of 'sphere' is given more than once"),
+            ("sphere(1, radius=2)", "argument 'radius' of 'sphere' is given more than once"),
+            ("sphere(1, 2)", "too many arguments for 'sphere'"),
+            ("sphere(\"1\")", "argument 'radius' of 'sphere' has to be a number"),
+            ("sphere(-1)", "radius of 'sphere' has to be positive"),
+            ("union(sphere())", "missing argument 'b' of 'union'"),
+            ("union(sphere(), sphere(0))", "in argument 'b' of 'union'"),
+            ("smooth_union(sphere(), sphere(), k=-1)", "'k' of 'smooth_union' must not be"),
+            ("mandelbulb(max_iters=2.5)", "has to be a positive integer"),
+            ("mandelbulb(bailout=0.5)", "bailout of 'mandelbulb' has to be at least 1"),
+            ("julia(0, 0, 0, 0, bailout=0.5)", "bailout of 'julia' has to be at least"),
+            ("scale(sphere(), 0)", "scale factor has to be positive"),
+            ("rotate(sphere(), 90, 0, 0, 0)", "rotation axis of 'rotate' must not be zero"),
+            ("mirror(sphere(), axes=\"w\")", "axes of 'mirror' have to be"),
+            ("twist(repeat(sphere(0.3), 1), 45)", "'twist' requires the shape to be bounded"),
+            ("bend(repeat(sphere(0.3), z=1), 45)", "bounded along the x and z axes"),
+        ];
+        for &(src, expected) in &errors {
+            let error = error(src);
+            assert!(error.contains(expected), "{}: {}", src, error);
+        }
+    }
+
+    #[test]
+    fn number_functions() {
+        let radius = |src: &str, t: f32| {
+            -parse_animated(src).unwrap().0.at(t).unwrap().min_distance_from(Point3::origin())
+        };
+
+        let src = "sphere(oscillate(1, 2, period=4))";
+        assert_eq!(radius(src, 0.0), 1.0);
+        assert_eq!(radius(src, 1.0), 1.5);
+        assert_eq!(radius(src, 2.0), 2.0);
+        assert_eq!(radius("sphere(oscillate(1, 2, phase=0.5))", 0.0), 2.0);
+        assert_eq!(radius("sphere(linear(1, speed=0.5))", 3.0), 2.5);
+        assert_eq!(radius("sphere(oscillate(linear(1, 1), 4, period=2))", 2.0), 3.0);
+
+        assert!(parse("sphere(oscillate(1, 2, period=0))").is_err());
+        assert!(parse("sphere(linear(1))").is_err());
+        assert!(parse("oscillate(1, 2)").is_err());
+    }
+
+    #[test]
+    fn mandelbulb_power() {
+        assert!(parse("mandelbulb(power=2)").is_ok());
+        assert!(parse("mandelbulb(power=-3.5)").is_ok());
+        assert!(parse("mandelbulb(power=0)").is_err());
+        assert!(parse("mandelbulb(power=1)").is_err());
+    }
+
+    #[test]
+    fn sampled_cache() {
+        let path = std::env::temp_dir()
+            .join(format!("cantucci-sampled-cache-{}.sdf", std::process::id()));
+        let src = |radius, resolution| format!(
+            r#"sampled(sphere({}), {}, cache="{}")"#,
+            radius,
+            resolution,
+            path.display(),
+        );
+        let cached = || SampledShape::load(&path, Interpolation::Tricubic).unwrap();
+        let p = Point3::new(0.6, 0.0, 0.0);
+
+        let sampled = parse(&src(0.5, 16)).unwrap();
+        assert!(path.exists());
+        let key = cached().key();
+        let loaded = parse(&src(0.5, 16)).unwrap();
+        assert_eq!(loaded.min_distance_from(p), sampled.min_distance_from(p));
+
+        // A different shape or resolution is sampled again and replaces the
+        // cached samples.
+        let other = parse(&src(0.7, 16)).unwrap();
+        assert!((other.min_distance_from(p) + 0.1).abs() < 1e-2);
+        assert_ne!(cached().key(), key);
+        parse(&src(0.7, 20)).unwrap();
+        assert_eq!(cached().resolution(), 20);
+
+        // Files which aren't sampled shapes are not overwritten.
+        std::fs::write(&path, "foo").unwrap();
+        let err = parse(&src(0.7, 20));
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.is_err());
+        assert_eq!(contents, b"foo");
+    }
+
+    #[test]
+    fn static_subshapes_are_cached() {
+        let (static_anim, _) = parse_animated("union(sphere(1), sphere(2))").unwrap();
+        assert!(!static_anim.is_animated());
+        assert!(static_anim.static_shapes.is_empty());
+
+        let (anim, shape) = parse_animated(
+            "union(scale(sphere(1), 2), translate(sphere(0.5), linear(0, 1), 0, 0))",
+        ).unwrap();
+        assert!(anim.is_animated());
+
+        // `scale(...)` and `sphere(0.5)`
+        assert_eq!(anim.static_shapes.len(), 2);
+
+        let p = Point3::new(4.0, 0.0, 0.0);
+        assert_eq!(shape.min_distance_from(p), 2.0);
+        assert_eq!(anim.at(0.0).unwrap().min_distance_from(p), 2.0);
+        assert_eq!(anim.at(3.0).unwrap().min_distance_from(p), 0.5);
+    }
+}
@@ -0,0 +1,497 @@
+use std::{collections::HashSet, fmt};
+use cgmath::{Matrix3, Quaternion, Vector3};
+
+use super::Shape;
+
+
+/// The name of the function defined by `de_shader` for the whole shape. It
+/// has the signature `float shape_de(vec3 point)`.
+pub const DE_FUNCTION: &str = "shape_de";
+
+/// Generates the GLSL code of the distance estimator of the given shape.
+///
+/// If the shape uses uniforms, they are declared in one uniform block at
+/// binding 0 of the descriptor set `uniform_set`. Its content is returned by
+/// `DeShader::uniform_data`.
+#[allow(dead_code)]  // TODO
+pub fn de_shader(shape: &dyn Shape, uniform_set: u32) -> DeShader {
+    let mut glsl = GlslBuilder::new();
+    let de = glsl.shape(shape);
+    glsl.finish(de, uniform_set)
+}
+
+/// The GLSL code of a distance estimator, generated by `de_shader`.
+#[derive(Debug, Clone)]
+pub struct DeShader {
+    source: String,
+    uniforms: Vec<Uniform>,
+}
+
+#[allow(dead_code)]  // TODO
+impl DeShader {
+    /// The GLSL code which can be included into a shader. It defines the
+    /// function `DE_FUNCTION` and everything it needs.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the content of the uniform block in `std140` layout. Empty if
+    /// the shape doesn't use any uniforms.
+    pub fn uniform_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for uniform in &self.uniforms {
+            let (align, bytes) = match uniform.value {
+                UniformValue::Float(v) => (4, v.to_ne_bytes().to_vec()),
+                UniformValue::Int(v) => (4, v.to_ne_bytes().to_vec()),
+                UniformValue::Vec3(v) => {
+                    (16, bytemuck::cast_slice(&[v.x, v.y, v.z]).to_vec())
+                }
+                UniformValue::Vec4(v) => {
+                    (16, bytemuck::cast_slice(&[v.s, v.v.x, v.v.y, v.v.z]).to_vec())
+                }
+            };
+
+            data.resize(round_up(data.len(), align), 0);
+            data.extend(bytes);
+        }
+
+        // The size of a block is rounded up to the alignment of `vec4`.
+        data.resize(round_up(data.len(), 16), 0);
+        data
+    }
+}
+
+#[allow(dead_code)]  // TODO
+fn round_up(x: usize, align: usize) -> usize {
+    x.div_ceil(align) * align
+}
+
+/// A uniform declared via `GlslBuilder::uniform`.
+#[derive(Debug, Clone)]
+struct Uniform {
+    name: Ident,
+    value: UniformValue,
+}
+
+/// The type and current value of a uniform.
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]  // TODO
+pub enum UniformValue {
+    Float(f32),
+    Int(i32),
+    Vec3(Vector3<f32>),
+
+    /// Quaternions are stored as `(real, i, j, k)`.
+    Vec4(Quaternion<f32>),
+}
+
+impl UniformValue {
+    fn glsl_type(&self) -> &'static str {
+        match self {
+            UniformValue::Float(_) => "float",
+            UniformValue::Int(_) => "int",
+            UniformValue::Vec3(_) => "vec3",
+            UniformValue::Vec4(_) => "vec4",
+        }
+    }
+}
+
+impl From<f32> for UniformValue {
+    fn from(v: f32) -> Self {
+        UniformValue::Float(v)
+    }
+}
+
+impl From<i32> for UniformValue {
+    fn from(v: i32) -> Self {
+        UniformValue::Int(v)
+    }
+}
+
+impl From<Vector3<f32>> for UniformValue {
+    fn from(v: Vector3<f32>) -> Self {
+        UniformValue::Vec3(v)
+    }
+}
+
+impl From<Quaternion<f32>> for UniformValue {
+    fn from(v: Quaternion<f32>) -> Self {
+        UniformValue::Vec4(v)
+    }
+}
+
+/// Collects the GLSL functions and uniforms of a shape (and all its child
+/// shapes) and makes sure all their names are unique.
+///
+/// Shapes emit their code in `Shape::build_de_shader`, which is usually
+/// called via `GlslBuilder::shape`.
+pub struct GlslBuilder {
+    idents: HashSet<String>,
+    functions: Vec<String>,
+    uniforms: Vec<Uniform>,
+}
+
+impl GlslBuilder {
+    fn new() -> Self {
+        let mut idents = HashSet::new();
+        idents.insert(DE_FUNCTION.to_string());
+
+        Self {
+            idents,
+            functions: Vec::new(),
+            uniforms: Vec::new(),
+        }
+    }
+
+    /// Emits the distance estimator of `shape` and returns the name of the
+    /// function (with the signature `float NAME(vec3 point)`).
+    pub fn shape(&mut self, shape: &dyn Shape) -> Ident {
+        shape.build_de_shader(self)
+    }
+
+    /// Emits a function and returns its name, which is derived from `name`.
+    ///
+    /// The function is defined by `template`: every `{KEY}` placeholder
+    /// (upper case letters, digits and `_`) is replaced by the GLSL
+    /// representation of the value with that key in `args`. The name of the
+    /// function is available as `{NAME}`. All other braces are kept as they
+    /// are.
+    ///
+    /// Panics if a placeholder has no value or if a value is not used, as
+    /// both are bugs in the calling shape.
+    pub fn function(
+        &mut self,
+        name: &str,
+        template: &str,
+        args: &[(&str, &dyn ToGlsl)],
+    ) -> Ident {
+        let ident = self.fresh_ident(&format!("de_{}", name));
+
+        let mut args = args.to_vec();
+        args.push(("NAME", &ident));
+        self.functions.push(expand(template, &args));
+
+        ident
+    }
+
+    /// Declares a uniform with the given initial value and returns its name,
+    /// which is derived from `name`.
+    ///
+    /// Uniforms can be changed without recompiling the shader. They should be
+    /// used for values that are likely to change often (e.g. in animations).
+    pub fn uniform(&mut self, name: &str, value: impl Into<UniformValue>) -> Ident {
+        let ident = self.fresh_ident(&format!("u_{}", name));
+        self.uniforms.push(Uniform {
+            name: ident.clone(),
+            value: value.into(),
+        });
+
+        ident
+    }
+
+    /// Returns an identifier starting with `base` which wasn't returned
+    /// before.
+    fn fresh_ident(&mut self, base: &str) -> Ident {
+        assert!(
+            base.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "invalid GLSL identifier '{}'",
+            base,
+        );
+
+        let mut name = base.to_string();
+        let mut i = 1;
+        while self.idents.contains(&name) {
+            name = format!("{}_{}", base, i);
+            i += 1;
+        }
+
+        self.idents.insert(name.clone());
+        Ident(name)
+    }
+
+    fn finish(self, de: Ident, uniform_set: u32) -> DeShader {
+        let mut source = String::new();
+
+        if !self.uniforms.is_empty() {
+            source += &format!(
+                "layout(set = {}, binding = 0) uniform ShapeParams {{\n",
+                uniform_set,
+            );
+            for uniform in &self.uniforms {
+                source += &format!("    {} {};\n", uniform.value.glsl_type(), uniform.name);
+            }
+            source += "};\n\n";
+        }
+
+        for function in &self.functions {
+            source += function;
+            source += "\n";
+        }
+
+        source += &format!(
+            "float {}(vec3 point) {{\n    return {}(point);\n}}\n",
+            DE_FUNCTION,
+            de,
+        );
+
+        DeShader {
+            source,
+            uniforms: self.uniforms,
+        }
+    }
+}
+
+/// Replaces all `{KEY}` placeholders in `template`. See
+/// `GlslBuilder::function`.
+fn expand(template: &str, args: &[(&str, &dyn ToGlsl)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut used = vec![false; args.len()];
+
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let key_len = after
+            .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(after.len());
+        if key_len > 0 && after[key_len..].starts_with('}') {
+            let key = &after[..key_len];
+            let idx = args.iter()
+                .position(|&(k, _)| k == key)
+                .unwrap_or_else(|| panic!("no value for '{{{}}}' in GLSL template", key));
+
+            out.push_str(&args[idx].1.to_glsl());
+            used[idx] = true;
+            rest = &after[key_len + 1..];
+        } else {
+            // Just a normal brace
+            out.push('{');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+
+    if let Some(idx) = used.iter().position(|used| !used) {
+        panic!("value for '{}' not used in GLSL template", args[idx].0);
+    }
+
+    out
+}
+
+/// The name of a GLSL function or uniform.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ident(String);
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Formats an `f32` as valid GLSL float literal.
+///
+/// Unlike `f32::to_string`, this always emits a decimal point or exponent
+/// (`1.0` instead of `1`). Negative numbers are put in parenthesis, so that
+/// the literal can be inserted into any expression. Infinity and NaN don't
+/// have literals in GLSL, so they are created from their bit patterns.
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f32);
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = self.0;
+        if v.is_nan() {
+            write!(f, "uintBitsToFloat({:#010x}u)", f32::NAN.to_bits())
+        } else if v.is_infinite() {
+            write!(f, "uintBitsToFloat({:#010x}u)", v.to_bits())
+        } else if v.is_sign_negative() {
+            // `Debug` always prints a decimal point or an exponent, both
+            // valid in GLSL.
+            write!(f, "(-{:?})", -v)
+        } else {
+            write!(f, "{:?}", v)
+        }
+    }
+}
+
+/// A piece of raw GLSL code, e.g. an expression built by a shape.
+#[derive(Debug, Clone)]
+pub struct Code(pub String);
+
+/// Values that can be inserted into GLSL templates.
+pub trait ToGlsl {
+    fn to_glsl(&self) -> String;
+}
+
+impl ToGlsl for f32 {
+    fn to_glsl(&self) -> String {
+        Float(*self).to_string()
+    }
+}
+
+/// Integers are emitted as `int` literals. Values not fitting into an `int`
+/// are clamped.
+impl ToGlsl for u64 {
+    fn to_glsl(&self) -> String {
+        (*self).min(i32::MAX as u64).to_string()
+    }
+}
+
+impl ToGlsl for Vector3<f32> {
+    fn to_glsl(&self) -> String {
+        format!("vec3({}, {}, {})", Float(self.x), Float(self.y), Float(self.z))
+    }
+}
+
+/// Quaternions are emitted as `vec4` in the order `(real, i, j, k)`.
+impl ToGlsl for Quaternion<f32> {
+    fn to_glsl(&self) -> String {
+        format!(
+            "vec4({}, {}, {}, {})",
+            Float(self.s),
+            Float(self.v.x),
+            Float(self.v.y),
+            Float(self.v.z),
+        )
+    }
+}
+
+impl ToGlsl for Matrix3<f32> {
+    fn to_glsl(&self) -> String {
+        // GLSL matrices are specified column by column, just like cgmath
+        // stores them.
+        let values = [self.x, self.y, self.z].iter()
+            .flat_map(|c| vec![c.x, c.y, c.z])
+            .map(|v| Float(v).to_string())
+            .collect::<Vec<_>>();
+
+        format!("mat3({})", values.join(", "))
+    }
+}
+
+impl ToGlsl for Ident {
+    fn to_glsl(&self) -> String {
+        self.0.clone()
+    }
+}
+
+impl ToGlsl for Code {
+    fn to_glsl(&self) -> String {
+        self.0.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use cgmath::{prelude::*, Deg, Point3, Quaternion, Vector3};
+    use shaderc::{Compiler, ShaderKind};
+
+    use crate::shape::*;
+    use crate::shape::kifs::{FoldPlane, IfsBase};
+    use super::*;
+
+    /// Compiles a fragment shader using the DE of `shape` with shaderc.
+    fn compile(shape: &dyn Shape) {
+        let de = de_shader(shape, 1);
+        let src = format!(
+            "#version 450\n\
+             layout(location = 0) in vec3 pos;\n\
+             layout(location = 0) out vec4 color;\n\
+             {}\n\
+             void main() {{\n    color = vec4(vec3({}(pos)), 1.0);\n}}\n",
+            de.source(),
+            DE_FUNCTION,
+        );
+
+        let mut compiler = Compiler::new().unwrap();
+        let kind = ShaderKind::Fragment;
+        if let Err(e) = compiler.compile_into_spirv(&src, kind, "de.frag", "main", None) {
+            panic!("invalid GLSL:\n{}\n\n{}", src, e);
+        }
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(Float(1.0).to_string(), "1.0");
+        assert_eq!(Float(0.25).to_string(), "0.25");
+        assert_eq!(Float(-3.0).to_string(), "(-3.0)");
+        assert_eq!(Float(-0.0).to_string(), "(-0.0)");
+        assert_eq!(Float(1e20).to_string(), "1e20");
+        assert_eq!(Float(1.5e-7).to_string(), "1.5e-7");
+        assert_eq!(Float(f32::INFINITY).to_string(), "uintBitsToFloat(0x7f800000u)");
+        assert_eq!(Float(f32::NEG_INFINITY).to_string(), "uintBitsToFloat(0xff800000u)");
+        assert_eq!(Float(f32::NAN).to_string(), "uintBitsToFloat(0x7fc00000u)");
+    }
+
+    #[test]
+    fn unique_names() {
+        let mut glsl = GlslBuilder::new();
+        let a = glsl.function("foo", "float {NAME}(vec3 point) { return 1.0; }", &[]);
+        let b = glsl.function("foo", "float {NAME}(vec3 point) { return 2.0; }", &[]);
+        let c = glsl.uniform("foo", 3.0);
+        assert_eq!(a.to_string(), "de_foo");
+        assert_eq!(b.to_string(), "de_foo_1");
+        assert_eq!(c.to_string(), "u_foo");
+    }
+
+    #[test]
+    fn uniform_layout() {
+        let mut glsl = GlslBuilder::new();
+        glsl.uniform("a", 1.0);
+        glsl.uniform("b", Vector3::new(2.0, 3.0, 4.0));
+        glsl.uniform("c", 5);
+        let de = glsl.function("x", "float {NAME}(vec3 point) { return 0.0; }", &[]);
+        let data = glsl.finish(de, 0).uniform_data();
+
+        let floats: &[f32] = bytemuck::cast_slice(&data);
+        assert_eq!(floats.len(), 8);
+        assert_eq!(floats[..1], [1.0]);
+        assert_eq!(floats[4..7], [2.0, 3.0, 4.0]);
+        assert_eq!(bytemuck::cast_slice::<_, i32>(&data)[7], 5);
+    }
+
+    #[test]
+    fn all_shapes_compile() {
+        let mandelbulb: Arc<dyn Shape> = Arc::new(Mandelbulb::classic(6, 2.5));
+        let sphere: Arc<dyn Shape> = Arc::new(Sphere::new(Point3::new(-1.0, 0.0, 2.0), 1.0));
+        let shapes: Vec<Arc<dyn Shape>> = vec![
+            sphere.clone(),
+            mandelbulb.clone(),
+            Arc::new(Mandelbulb::<3>::new(5, 4.0)),
+            Arc::new(DynMandelbulb::new(7.5, 6, 2.5)),
+            Arc::new(Juliabulb::<8>::classic(Vector3::new(0.3, -0.1, 0.2), 8, 4.0)),
+            Arc::new(QuaternionJulia::new(Quaternion::new(-0.2, 0.6, 0.2, 0.2), 0.0, 12, 4.0)),
+            Arc::new(Mandelbox::classic(12)),
+            Arc::new(Mandelbox::new(10, -1.5, 1.0, 0.5, 1.0)),
+            Arc::new(KaleidoscopicIfs::menger_sponge(4)),
+            Arc::new(KaleidoscopicIfs::sierpinski_tetrahedron(8)),
+            Arc::new(KaleidoscopicIfs::new(
+                vec![FoldPlane::new(Vector3::new(1.0, 1.0, 0.0), -0.5)],
+                2.0,
+                Vector3::unit_x(),
+                IfsBase::Cube(0.5),
+                5,
+            )),
+            Arc::new(Transformed::new(
+                mandelbulb.clone(),
+                Vector3::new(1.0, -2.0, 0.5),
+                Quaternion::from_axis_angle(Vector3::unit_y(), Deg(30.0)),
+                0.5,
+            )),
+            Arc::new(Union::new(mandelbulb.clone(), mandelbulb.clone())),
+            Arc::new(Intersection::smooth(mandelbulb.clone(), sphere.clone(), 0.1)),
+            Arc::new(Difference::smooth(
+                Arc::new(Union::new(sphere.clone(), Arc::new(DynMandelbulb::new(3.0, 4, 2.0)))),
+                Arc::new(Union::new(sphere.clone(), Arc::new(DynMandelbulb::new(5.0, 4, 2.0)))),
+                0.25,
+            )),
+        ];
+
+        for shape in &shapes {
+            compile(&**shape);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+float {NAME}(vec3 point) {
+    // Quaternions are stored as (real, i, j, k)
+    vec4 z = vec4(point, {SLICE});
+    vec4 c = {C};
+    float dz2 = 1.0;
+    float z2 = dot(z, z);
+
+    for (int i = 0; i < {MAX_ITERS}; i++) {
+        if (z2 > {BAILOUT} * {BAILOUT}) {
+            break;
+        }
+
+        dz2 *= 4.0 * z2;
+
+        // quaternion squaring
+        z = vec4(z.x * z.x - dot(z.yzw, z.yzw), 2.0 * z.x * z.yzw) + c;
+        z2 = dot(z, z);
+    }
+
+    return 0.25 * sqrt(z2 / dz2) * log(z2);
+}
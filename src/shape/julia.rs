@@ -0,0 +1,90 @@
+use std::ops::Range;
+use cgmath::{prelude::*, Point3, Quaternion};
+
+use super::{Shape, glsl::{GlslBuilder, Ident}};
+
+/// A three dimensional slice through the four dimensional Julia set of the
+/// quaternion iteration `z ↦ z² + c`, described [here][1].
+///
+/// The point (x, y, z) is mapped to the quaternion `x + yi + zj + wk`, where
+/// `w` is the fixed `slice` parameter.
+///
+/// [1]: https://iquilezles.org/www/articles/juliasets3d/juliasets3d.htm
+#[derive(Clone)]
+pub struct QuaternionJulia {
+    c: Quaternion<f32>,
+    slice: f32,
+    max_iters: u64,
+    bailout: f32,
+}
+
+impl QuaternionJulia {
+    pub fn new(c: Quaternion<f32>, slice: f32, max_iters: u64, bailout: f32) -> Self {
+        assert!(max_iters >= 1);
+
+        assert!(
+            bailout >= Self::min_bailout(c),
+            "bailout has to be at least {}",
+            Self::min_bailout(c),
+        );
+
+        QuaternionJulia {
+            c,
+            slice,
+            max_iters,
+            bailout,
+        }
+    }
+
+    /// Every point further away from the origin than this will diverge, so
+    /// this is the smallest valid bailout value.
+    ///
+    /// If |z| > r, then |z² + c| ≥ |z|² - |c| > |z| as long as r² - r - |c|
+    /// ≥ 0. This is the positive root of that polynomial.
+    pub fn min_bailout(c: Quaternion<f32>) -> f32 {
+        (1.0 + (1.0 + 4.0 * c.magnitude()).sqrt()) / 2.0
+    }
+}
+
+impl Shape for QuaternionJulia {
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let r = Self::min_bailout(self.c);
+        Point3::new(-r, -r, -r) .. Point3::new(r, r, r)
+    }
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let mut z = Quaternion::new(p.x, p.y, p.z, self.slice);
+
+        // We only need the magnitude of the derivative, so we track its square
+        // (|dz|²) instead of the whole quaternion.
+        let mut dz2 = 1.0;
+        let mut z2 = z.magnitude2();
+
+        for _ in 0..self.max_iters {
+            if z2 > self.bailout * self.bailout {
+                break;
+            }
+
+            // z' = 2 * z * dz  =>  |z'|² = 4 * |z|² * |dz|²
+            dz2 *= 4.0 * z2;
+            z = z * z + self.c;
+            z2 = z.magnitude2();
+        }
+
+        // Same as for the mandelbulb: 0.5 * |z| * ln(|z|) / |dz|. Points that
+        // don't diverge usually end up with |z| < 1 which makes the result
+        // negative.
+        0.25 * (z2 / dz2).sqrt() * z2.ln()
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        glsl.function("quaternion_julia", include_str!("julia.frag"), &[
+            ("C", &self.c),
+            ("SLICE", &self.slice),
+            ("BAILOUT", &self.bailout),
+            ("MAX_ITERS", &self.max_iters),
+        ])
+    }
+
+    impl_batch_methods!();
+}
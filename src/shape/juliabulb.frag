@@ -0,0 +1,33 @@
+float {NAME}(vec3 point) {
+    vec3 z = point;
+    vec3 c = {C};
+    float dr = 1.0;
+    float r = length(z);
+
+    for (int i = 0; i < {MAX_ITERS}; i++) {
+        if (r > {BAILOUT}) {
+            break;
+        }
+
+        // convert to polar coordinates
+        float theta = acos(z.z / r);
+        float phi = atan(z.y, z.x);
+        dr = pow(r, {POWER} - 1.0) * {POWER} * dr;
+
+        // scale and rotate the point
+        float zr = pow(r, {POWER});
+        theta = theta * {POWER};
+        phi = phi * {POWER};
+
+        // convert back to cartesian coordinates
+        z = zr * vec3(
+            sin(theta) * cos(phi),
+            sin(phi) * sin(theta),
+            cos(theta)
+        );
+        z = z + c;
+        r = length(z);
+    }
+
+    return 0.5 * log(r) * r / dr;
+}
@@ -0,0 +1,13 @@
+float {NAME}(vec3 point) {
+    vec3 z = point;
+    float factor = 1.0;
+    float d;
+
+    for (int i = 0; i < {MAX_ITERS}; i++) {
+        {FOLDS}
+        z = z * {SCALE} - {OFFSET} * ({SCALE} - 1.0);
+        factor /= {SCALE};
+    }
+
+    return ({BASE}) * factor;
+}
@@ -0,0 +1,272 @@
+use std::ops::Range;
+use cgmath::{prelude::*, Point3, Vector3};
+
+use super::{Shape, glsl::{Code, Float, GlslBuilder, Ident, ToGlsl}};
+
+
+/// A plane used by `KaleidoscopicIfs` to fold space.
+#[derive(Debug, Clone, Copy)]
+pub struct FoldPlane {
+    normal: Vector3<f32>,
+    distance: f32,
+}
+
+impl FoldPlane {
+    /// Creates the plane `dot(normal, p) == distance`. Points on the side the
+    /// normal points away from are reflected to the other side. The normal
+    /// doesn't need to have unit length.
+    pub fn new(normal: Vector3<f32>, distance: f32) -> Self {
+        let len = normal.magnitude();
+        assert!(len > 0.0);
+
+        Self {
+            normal: normal / len,
+            distance: distance / len,
+        }
+    }
+
+    /// Reflects `p` to the positive side of the plane (if it isn't already
+    /// there).
+    #[inline(always)]
+    fn fold(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let d = p.dot(self.normal) - self.distance;
+        if d < 0.0 {
+            p - self.normal * (2.0 * d)
+        } else {
+            p
+        }
+    }
+}
+
+/// The shape that is placed at each leaf of the iterated function system.
+#[derive(Debug, Clone, Copy)]
+pub enum IfsBase {
+    /// An axis aligned cube around the origin with the given half side
+    /// length.
+    Cube(f32),
+
+    /// The regular tetrahedron with the corners (1, 1, 1), (-1, -1, 1),
+    /// (-1, 1, -1) and (1, -1, -1).
+    Tetrahedron,
+
+    /// The regular octahedron with its corners at distance 1 from the origin
+    /// on all axes.
+    Octahedron,
+}
+
+impl IfsBase {
+    /// Returns the signed distance from `p` to this shape. Exact for cubes, a
+    /// lower bound for the other variants.
+    fn distance_from(&self, p: Vector3<f32>) -> f32 {
+        match *self {
+            IfsBase::Cube(half_size) => {
+                let q = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs())
+                    - Vector3::new(half_size, half_size, half_size);
+                let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                outside.magnitude() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            IfsBase::Tetrahedron => {
+                let m = (-p.x - p.y - p.z)
+                    .max(p.x + p.y - p.z)
+                    .max(p.x - p.y + p.z)
+                    .max(-p.x + p.y + p.z);
+                (m - 1.0) / 3f32.sqrt()
+            }
+            IfsBase::Octahedron => {
+                (p.x.abs() + p.y.abs() + p.z.abs() - 1.0) / 3f32.sqrt()
+            }
+        }
+    }
+
+    /// The radius of a sphere around the origin containing this shape.
+    fn radius(&self) -> f32 {
+        match *self {
+            IfsBase::Cube(half_size) => half_size * 3f32.sqrt(),
+            IfsBase::Tetrahedron => 3f32.sqrt(),
+            IfsBase::Octahedron => 1.0,
+        }
+    }
+
+    /// Returns the GLSL expression of `distance_from` for the point `z`.
+    fn glsl(&self) -> String {
+        match *self {
+            IfsBase::Cube(half_size) => format!(
+                "length(max(abs(z) - vec3({h}), 0.0)) \
+                    + min(max(abs(z).x, max(abs(z).y, abs(z).z)) - {h}, 0.0)",
+                h = Float(half_size),
+            ),
+            IfsBase::Tetrahedron => "(max(\
+                    max(-z.x - z.y - z.z, z.x + z.y - z.z), \
+                    max(z.x - z.y + z.z, -z.x + z.y + z.z)\
+                ) - 1.0) / sqrt(3.0)".into(),
+            IfsBase::Octahedron => "(abs(z.x) + abs(z.y) + abs(z.z) - 1.0) / sqrt(3.0)".into(),
+        }
+    }
+}
+
+/// A kaleidoscopic iterated function system as described [here][1].
+///
+/// In each iteration, the point is first folded at all `folds` (in order),
+/// then scaled by `scale` around `offset`. After `max_iters` iterations, the
+/// distance to `base` is calculated and scaled back.
+///
+/// Folds don't increase the distance between two points and the scaling is
+/// undone by the factor, so every point of the fractal is mapped into the
+/// base shape and the result is a lower bound of the distance to the
+/// `max_iters`-th approximation of the fractal. It's not exact though: the
+/// folds don't always map a point to its closest sub-piece (e.g. for the
+/// Menger sponge).
+///
+/// [1]: http://www.fractalforums.com/sierpinski-gasket/kaleidoscopic-(escape-time-ifs)/
+#[derive(Clone)]
+pub struct KaleidoscopicIfs {
+    folds: Vec<FoldPlane>,
+    scale: f32,
+    offset: Vector3<f32>,
+    base: IfsBase,
+    max_iters: u64,
+
+    /// Half the side length of the bounding box (which is centered around the
+    /// origin).
+    bounds: f32,
+}
+
+impl KaleidoscopicIfs {
+    pub fn new(
+        folds: Vec<FoldPlane>,
+        scale: f32,
+        offset: Vector3<f32>,
+        base: IfsBase,
+        max_iters: u64,
+    ) -> Self {
+        assert!(scale > 1.0);
+
+        // We need to find a radius r such that every point with |p| > r is
+        // outside of the shape. Each fold can move a point at most twice the
+        // plane's distance closer to the origin. So for |z| > r we get:
+        //
+        //     |z'| >= scale * (|z| - folds) - |offset| * (scale - 1)
+        //
+        // This is bigger than |z| for the `r` calculated below. Once |z| is
+        // bigger than the base's radius, the point is outside.
+        let folds_dist: f32 = folds.iter().map(|f| 2.0 * f.distance.abs()).sum();
+        let escape = scale * folds_dist / (scale - 1.0) + offset.magnitude();
+        let bounds = escape.max(base.radius());
+
+        Self {
+            folds,
+            scale,
+            offset,
+            base,
+            max_iters,
+            bounds,
+        }
+    }
+
+    /// The Menger sponge, fitting into the cube [-1, 1]³.
+    pub fn menger_sponge(max_iters: u64) -> Self {
+        let folds = vec![
+            // Mirror everything into the positive octant...
+            FoldPlane::new(Vector3::unit_x(), 0.0),
+            FoldPlane::new(Vector3::unit_y(), 0.0),
+            FoldPlane::new(Vector3::unit_z(), 0.0),
+
+            // ... and sort the coordinates such that x >= y >= z.
+            FoldPlane::new(Vector3::new(1.0, -1.0, 0.0), 0.0),
+            FoldPlane::new(Vector3::new(1.0, 0.0, -1.0), 0.0),
+            FoldPlane::new(Vector3::new(0.0, 1.0, -1.0), 0.0),
+
+            // Points with large z belong to the middle row of sub-cubes.
+            FoldPlane::new(-Vector3::unit_z(), -1.0 / 3.0),
+        ];
+
+        // The sponge is a subset of its base cube.
+        Self::new(folds, 3.0, Vector3::new(1.0, 1.0, 0.0), IfsBase::Cube(1.0), max_iters)
+            .with_bounds(1.0)
+    }
+
+    /// The Sierpinski tetrahedron with the corners of `IfsBase::Tetrahedron`.
+    pub fn sierpinski_tetrahedron(max_iters: u64) -> Self {
+        let folds = vec![
+            FoldPlane::new(Vector3::new(1.0, 1.0, 0.0), 0.0),
+            FoldPlane::new(Vector3::new(1.0, 0.0, 1.0), 0.0),
+            FoldPlane::new(Vector3::new(0.0, 1.0, 1.0), 0.0),
+        ];
+
+        // The fractal is a subset of its base tetrahedron.
+        Self::new(folds, 2.0, Vector3::new(1.0, 1.0, 1.0), IfsBase::Tetrahedron, max_iters)
+            .with_bounds(1.0)
+    }
+
+    /// The Sierpinski octahedron with the corners of `IfsBase::Octahedron`.
+    pub fn sierpinski_octahedron(max_iters: u64) -> Self {
+        let folds = vec![
+            FoldPlane::new(Vector3::unit_x(), 0.0),
+            FoldPlane::new(Vector3::unit_y(), 0.0),
+            FoldPlane::new(Vector3::unit_z(), 0.0),
+            FoldPlane::new(Vector3::new(1.0, -1.0, 0.0), 0.0),
+            FoldPlane::new(Vector3::new(1.0, 0.0, -1.0), 0.0),
+        ];
+
+        // The fractal is a subset of its base octahedron.
+        Self::new(folds, 2.0, Vector3::unit_x(), IfsBase::Octahedron, max_iters)
+            .with_bounds(1.0)
+    }
+
+    /// Overwrites the conservative bounds calculated in `new` with tighter
+    /// ones (for presets where we know better).
+    fn with_bounds(self, bounds: f32) -> Self {
+        Self { bounds, ..self }
+    }
+
+    /// Returns the signed distance to the base shape after all iterations,
+    /// already scaled back to world space.
+    fn distance_from(&self, p: Point3<f32>) -> f32 {
+        let mut z = p.to_vec();
+        let mut factor = 1.0;
+
+        for _ in 0..self.max_iters {
+            for fold in &self.folds {
+                z = fold.fold(z);
+            }
+
+            z = z * self.scale - self.offset * (self.scale - 1.0);
+            factor /= self.scale;
+        }
+
+        self.base.distance_from(z) * factor
+    }
+}
+
+impl Shape for KaleidoscopicIfs {
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let b = self.bounds;
+        Point3::new(-b, -b, -b) .. Point3::new(b, b, b)
+    }
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.distance_from(p)
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let folds = self.folds.iter()
+            .map(|f| format!(
+                "d = dot(z, {normal}) - {};\n        \
+                    if (d < 0.0) z -= 2.0 * d * {normal};\n",
+                Float(f.distance),
+                normal = f.normal.to_glsl(),
+            ))
+            .collect::<Vec<_>>()
+            .join("        ");
+
+        glsl.function("kifs", include_str!("kifs.frag"), &[
+            ("MAX_ITERS", &self.max_iters),
+            ("FOLDS", &Code(folds)),
+            ("SCALE", &self.scale),
+            ("OFFSET", &self.offset),
+            ("BASE", &Code(self.base.glsl())),
+        ])
+    }
+
+    impl_batch_methods!();
+}
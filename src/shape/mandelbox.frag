@@ -0,0 +1,34 @@
+float {NAME}(vec3 point) {
+    vec3 z = point;
+    float dr = 1.0;
+
+    for (int i = 0; i < {MAX_ITERS}; i++) {
+        // box fold
+        z = clamp(z, -{FOLD_LIMIT}, {FOLD_LIMIT}) * 2.0 - z;
+
+        // sphere fold
+        float r2 = dot(z, z);
+        float factor = 1.0;
+        if (r2 < {MIN_RADIUS2}) {
+            factor = {FIXED_RADIUS2} / {MIN_RADIUS2};
+        } else if (r2 < {FIXED_RADIUS2}) {
+            factor = {FIXED_RADIUS2} / r2;
+        }
+        z = z * factor;
+        dr = dr * factor;
+
+        // scale and translate
+        z = z * {SCALE} + point;
+        dr = dr * abs({SCALE}) + 1.0;
+
+        if (dot(z, z) > {ESCAPE_RADIUS2}) {
+            break;
+        }
+    }
+
+    float de = (length(z) - {OFFSET}) / dr - {MIN_DR};
+
+    // cut off at the cube containing the real mandelbox
+    vec3 a = abs(point);
+    return max(de, max(max(a.x, a.y), a.z) - {HALF_SIZE});
+}
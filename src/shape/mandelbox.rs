@@ -0,0 +1,151 @@
+use std::ops::Range;
+use cgmath::{prelude::*, Point3, Vector3};
+
+use super::{Shape, glsl::{GlslBuilder, Ident}};
+
+/// Once the orbit point is this far away from the origin, we know it will
+/// diverge and can stop iterating. Stopping early does not change the result
+/// of the distance estimator (much), but avoids overflowing to `inf`.
+const ESCAPE_RADIUS: f32 = 1024.0;
+
+/// The 3D "Mandelbox" fractal discovered by Tom Lowe, described [here][1].
+///
+/// Each iteration consists of a "box fold" (reflecting each component at the
+/// planes ±`fold_limit`), a "sphere fold" (inverting points in the sphere with
+/// radius `fixed_radius`, but with a linear scaling inside of `min_radius`),
+/// a scaling by `scale` and adding the original point. The distance
+/// estimator is the one by "Rrrola" described [here][2].
+///
+/// [1]: https://sites.google.com/site/mandelbox/what-is-a-mandelbox
+/// [2]: http://www.fractalforums.com/3d-fractal-generation/a-mandelbox-distance-estimate-formula/
+#[derive(Clone)]
+pub struct Mandelbox {
+    max_iters: u64,
+    scale: f32,
+    fold_limit: f32,
+    min_radius: f32,
+    fixed_radius: f32,
+}
+
+impl Mandelbox {
+    pub fn new(
+        max_iters: u64,
+        scale: f32,
+        fold_limit: f32,
+        min_radius: f32,
+        fixed_radius: f32,
+    ) -> Self {
+        assert!(max_iters >= 1);
+        assert!(scale.abs() > 1.0, "mandelbox is unbounded for |scale| <= 1");
+        assert!(fold_limit > 0.0);
+        assert!(min_radius > 0.0 && min_radius <= fixed_radius);
+
+        Mandelbox {
+            max_iters,
+            scale,
+            fold_limit,
+            min_radius,
+            fixed_radius,
+        }
+    }
+
+    /// The mandelbox with the parameters from the original description:
+    /// scale 2, fold limit 1, minimum radius 0.5 and fixed radius 1.
+    #[cfg(test)]
+    pub fn classic(max_iters: u64) -> Self {
+        Self::new(max_iters, 2.0, 1.0, 0.5, 1.0)
+    }
+
+    /// Half the side length of the cube (centered at the origin) containing
+    /// the mandelbox.
+    fn half_size(&self) -> f32 {
+        // For the standard fold limit of 1, the mandelbox with |scale| > 1 is
+        // known to fit inside a cube with the half side length
+        // 2 * (|s| + 1) / (|s| - 1). For negative scales the real bounds are
+        // a lot tighter, but this value is still correct. The box fold scales
+        // linearly with the fold limit, so we do too.
+        let s = self.scale.abs();
+        2.0 * self.fold_limit * (s + 1.0) / (s - 1.0)
+    }
+}
+
+impl Shape for Mandelbox {
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let half = self.half_size();
+        Point3::new(-half, -half, -half) .. Point3::new(half, half, half)
+    }
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let min_radius2 = self.min_radius * self.min_radius;
+        let fixed_radius2 = self.fixed_radius * self.fixed_radius;
+
+        let c = p.to_vec();
+        let mut z = c;
+        let mut dr = 1.0;
+
+        for _ in 0..self.max_iters {
+            // Box fold: reflect each component at the planes ±fold_limit.
+            z = Vector3::new(
+                box_fold(z.x, self.fold_limit),
+                box_fold(z.y, self.fold_limit),
+                box_fold(z.z, self.fold_limit),
+            );
+
+            // Sphere fold: inside of `min_radius` we scale linearly, between
+            // `min_radius` and `fixed_radius` we invert the point.
+            let r2 = z.magnitude2();
+            let factor = if r2 < min_radius2 {
+                fixed_radius2 / min_radius2
+            } else if r2 < fixed_radius2 {
+                fixed_radius2 / r2
+            } else {
+                1.0
+            };
+            z *= factor;
+            dr *= factor;
+
+            z = z * self.scale + c;
+            dr = dr * self.scale.abs() + 1.0;
+
+            if z.magnitude2() > ESCAPE_RADIUS * ESCAPE_RADIUS {
+                break;
+            }
+        }
+
+        // This distance estimator (by "Rrrola", see the type's documentation)
+        // is negative inside the shape. The two constant offsets are chosen
+        // such that the surface sits where points start to escape.
+        let offset = (self.scale - 1.0).abs();
+        let min_dr = self.scale.abs().powi(1 - self.max_iters as i32);
+        let de = (z.magnitude() - offset) / dr - min_dr;
+
+        // With few iterations, the surface of the DE reaches outside of the
+        // cube containing the real mandelbox. We cut it off there, using the
+        // distance to the cube in the maximum norm (a lower bound of the
+        // euclidean distance).
+        let cube = p.x.abs().max(p.y.abs()).max(p.z.abs()) - self.half_size();
+        de.max(cube)
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        glsl.function("mandelbox", include_str!("mandelbox.frag"), &[
+            ("MAX_ITERS", &self.max_iters),
+            ("SCALE", &self.scale),
+            ("FOLD_LIMIT", &self.fold_limit),
+            ("MIN_RADIUS2", &(self.min_radius * self.min_radius)),
+            ("FIXED_RADIUS2", &(self.fixed_radius * self.fixed_radius)),
+            ("ESCAPE_RADIUS2", &(ESCAPE_RADIUS * ESCAPE_RADIUS)),
+            ("OFFSET", &(self.scale - 1.0).abs()),
+            ("MIN_DR", &self.scale.abs().powi(1 - self.max_iters as i32)),
+            ("HALF_SIZE", &self.half_size()),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+/// Reflects `x` at `limit` or `-limit` if it lies outside of that range.
+#[inline(always)]
+fn box_fold(x: f32, limit: f32) -> f32 {
+    x.max(-limit).min(limit) * 2.0 - x
+}
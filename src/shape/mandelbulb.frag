@@ -1,4 +1,4 @@
-float shape_de(vec3 point) {
+float {NAME}(vec3 point) {
     vec3 z = point;
     float dr = 1.0;
     float r = 0.0;
@@ -12,7 +12,7 @@ float shape_de(vec3 point) {
         // convert to polar coordinates
         float theta = acos(z.z / r);
         float phi = atan(z.y, z.x);
-        dr = pow(r, {POWER} - 1.0) * {POWER} * dr + 1.0;
+        dr = pow(r, {POWER} - 1.0) * abs({POWER}) * dr + 1.0;
 
         // scale and rotate the point
         float zr = pow(r, {POWER});
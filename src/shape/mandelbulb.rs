@@ -1,10 +1,21 @@
-use std::{
-    arch::x86_64::*,
-    ops::{Add, Mul, Range, Sub},
+use std::{ops::{Range, RangeInclusive}, sync::Arc};
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::prelude::*;
+use super::{
+    ColorData,
+    Shape,
+    dual::{Dual, Real},
+    vec3::{NativeVec3, Vec3},
+    glsl::{GlslBuilder, Ident, ToGlsl},
+    param::{self, ParamInfo, ParamType, ParamValue},
 };
-use cgmath::Point3;
 
-use super::Shape;
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "sse4.1"
+))]
+use super::vec3::SseVec3;
 
 /// Represents the 3D version of the classical mandelbulb described [here][1].
 ///
@@ -57,48 +68,404 @@ impl<const P: u8> Shape for Mandelbulb<P> {
     }
 
     fn min_distance_from(&self, p: Point3<f32>) -> f32 {
-        let p = Vec3::new(p.x, p.y, p.z);
-        let mut z = p;
+        estimate_distance::<NativeVec3>(p, self.max_iters, self.bailout, P as f32, rotate::<_, P>)
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.colored_distance_from(p).0
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let (d, color) = estimate_distance_f64(p.into(), self.max_iters, self.bailout, P.into());
+        (d, Some(color))
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, _delta: f64) -> Vector3<f64> {
+        estimate_distance_f64(Dual::point(p), self.max_iters, self.bailout, P.into()).0.grad
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        build_de_shader(glsl, self.max_iters, self.bailout, &(P as f32))
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        // The power is part of the type and thus can't be changed.
+        params(P as f32..=P as f32)
+    }
+
+    fn param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "power" => Some((P as f32).into()),
+            _ => common_param(name, self.max_iters, self.bailout),
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        param::find(&self.params(), name)?.check(value)?;
+        match name {
+            "power" => {}
+            _ => set_common_param(name, value, &mut self.max_iters, &mut self.bailout),
+        }
+
+        Ok(())
+    }
+
+    fn unfix_param(&self, name: &str) -> Option<Arc<dyn Shape>> {
+        match name {
+            "power" => Some(Arc::new(DynMandelbulb::new(P as f32, self.max_iters, self.bailout))),
+            _ => None,
+        }
+    }
+
+    fn batch_min_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32> {
+        if P == 8 {
+            batch_estimate_distance_p8(points, self.max_iters, self.bailout)
+        } else {
+            points.iter().map(|&p| self.min_distance_from(p)).collect()
+        }
+    }
+
+    fn has_fast_batch(&self) -> bool {
+        P == 8
+    }
+
+    impl_batch_methods!(without_min);
+}
+
+/// Like `Mandelbulb`, but the power is chosen at runtime and doesn't need to
+/// be an integer. Fractional and negative powers are allowed.
+///
+/// This is useful to continuously change the power (e.g. for animations).
+/// For the power 8, the same fast path as for `Mandelbulb<8>` is used; all
+/// other powers use the (slower) trigonometric formula.
+#[derive(Clone)]
+pub struct DynMandelbulb {
+    max_iters: u64,
+    bailout: f32,
+    power: f32,
+}
+
+impl DynMandelbulb {
+    pub fn new(power: f32, max_iters: u64, bailout: f32) -> Self {
+        assert!(max_iters >= 1);
+        assert!(Self::is_valid_power(power), "invalid mandelbulb power {}", power);
+
+        DynMandelbulb {
+            max_iters,
+            bailout,
+            power,
+        }
+    }
+
+    /// Returns `false` for the powers 0 and 1 (and non-finite ones): there,
+    /// the iteration doesn't create a fractal and the DE is meaningless.
+    pub fn is_valid_power(power: f32) -> bool {
+        power.is_finite() && power != 0.0 && power != 1.0
+    }
+}
+
+impl Shape for DynMandelbulb {
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // Points further away from the origin than the bailout stop iterating
+        // right away, so their DE is `0.5 * ln(r) * r`, which is positive for
+        // `r > 1`. This holds for all powers, so the box doesn't change when
+        // the power is animated. For powers >= 2, the real shape is a lot
+        // smaller (all points with `r > 2` escape).
+        let half = self.bailout.max(1.0);
+        Point3::new(-half, -half, -half) .. Point3::new(half, half, half)
+    }
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let power = self.power;
+        estimate_distance(p, self.max_iters, self.bailout, power, |z: NativeVec3| {
+            rotate_dyn(z, power)
+        })
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.colored_distance_from(p).0
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let power = self.power.into();
+        let (d, color) = estimate_distance_f64(p.into(), self.max_iters, self.bailout, power);
+        (d, Some(color))
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, _delta: f64) -> Vector3<f64> {
+        let power = self.power.into();
+        estimate_distance_f64(Dual::point(p), self.max_iters, self.bailout, power).0.grad
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        // The power is passed as uniform, so that it can be changed without
+        // recompiling the shader.
+        let power = glsl.uniform("mandelbulb_power", self.power);
+        build_de_shader(glsl, self.max_iters, self.bailout, &power)
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        params(f32::MIN..=f32::MAX)
+    }
+
+    fn param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "power" => Some(self.power.into()),
+            _ => common_param(name, self.max_iters, self.bailout),
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        param::find(&self.params(), name)?.check(value)?;
+        match name {
+            "power" if !Self::is_valid_power(value.as_float()) => {
+                bail!("the mandelbulb power must not be 0 or 1");
+            }
+            "power" => self.power = value.as_float(),
+            _ => set_common_param(name, value, &mut self.max_iters, &mut self.bailout),
+        }
+
+        Ok(())
+    }
+
+    fn batch_min_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32> {
+        if self.power == 8.0 {
+            batch_estimate_distance_p8(points, self.max_iters, self.bailout)
+        } else {
+            points.iter().map(|&p| self.min_distance_from(p)).collect()
+        }
+    }
+
+    fn has_fast_batch(&self) -> bool {
+        self.power == 8.0
+    }
+
+    impl_batch_methods!(without_min);
+}
+
+/// The Julia set counterpart of the mandelbulb: the same triplex iteration is
+/// used, but instead of the sample point, the fixed constant `c` is added in
+/// each step. The sample point is only used as starting value.
+#[derive(Clone)]
+pub struct Juliabulb<const P: u8> {
+    c: Vector3<f32>,
+    max_iters: u64,
+    bailout: f32,
+}
+
+impl<const P: u8> Juliabulb<P> {
+    pub fn new(c: Vector3<f32>, max_iters: u64, bailout: f32) -> Self {
+        assert!(P >= 2);
+        assert!(max_iters >= 1);
+
+        assert!(
+            bailout >= Self::min_bailout(c),
+            "bailout has to be at least {}",
+            Self::min_bailout(c),
+        );
+
+        Juliabulb {
+            c,
+            max_iters,
+            bailout,
+        }
+    }
+
+    /// Every point further away from the origin than this will diverge, so
+    /// this is the smallest valid bailout value.
+    ///
+    /// The triplex power satisfies |z^P| = |z|^P, so |z^P + c| ≥ |z|^P - |c|.
+    /// With r = 1 + |c| and |z| > r it follows that |z|^P - |c| > |z| for
+    /// all P ≥ 2.
+    pub fn min_bailout(c: Vector3<f32>) -> f32 {
+        1.0 + c.magnitude()
+    }
+}
+
+impl Juliabulb<8> {
+    pub fn classic(c: Vector3<f32>, max_iters: u64, bailout: f32) -> Self {
+        Self::new(c, max_iters, bailout)
+    }
+}
+
+impl<const P: u8> Shape for Juliabulb<P> {
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let r = Self::min_bailout(self.c);
+        Point3::new(-r, -r, -r) .. Point3::new(r, r, r)
+    }
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        let c = NativeVec3::new(self.c.x, self.c.y, self.c.z);
+        let mut z = NativeVec3::new(p.x, p.y, p.z);
         let mut dr = 1.0;
-        let mut r = 0.0;
+        let mut r = z.magnitude();
 
         for _ in 0..self.max_iters {
-            // TODO: this here should return the magnitude² as we need it ...
-            r = z.magnitude();
             if r > self.bailout {
                 break;
             }
 
-            // ... here in the ^7 thingy.
-            dr = r.powi(P as i32 - 1) * (P as f32) * dr + 1.0;
-            z = rotate::<P>(z) + p;
+            // Unlike for the mandelbulb, we derive by the starting point and
+            // not by `c`, so there is no `+ 1.0` here.
+            dr *= r.powi(P as i32 - 1) * (P as f32);
+            z = rotate::<_, P>(z) + c;
+            r = z.magnitude();
         }
 
         let ln_r = r.ln() * r;
         0.5 * ln_r / dr
     }
 
-    fn de_shader(&self) -> String {
-        let s = include_str!("mandelbulb.frag")
-            .replace("{BAILOUT}", &self.bailout.to_string())
-            .replace("{MAX_ITERS}", &self.max_iters.to_string())
-            .replace("{POWER}", &P.to_string());
-
-        s
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        glsl.function("juliabulb", include_str!("juliabulb.frag"), &[
+            ("BAILOUT", &self.bailout),
+            ("MAX_ITERS", &self.max_iters),
+            ("POWER", &(P as f32)),
+            ("C", &self.c),
+        ])
     }
 
     impl_batch_methods!();
 }
 
+/// The distance estimator shared by all mandelbulb variants. `rotate` has to
+/// perform the triplex "power" operation with the power `power`.
+#[inline(always)]
+fn estimate_distance<V: Vec3>(
+    p: Point3<f32>,
+    max_iters: u64,
+    bailout: f32,
+    power: f32,
+    rotate: impl Fn(V) -> V,
+) -> f32 {
+    let p = V::new(p.x, p.y, p.z);
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+
+    for _ in 0..max_iters {
+        // TODO: this here should return the magnitude² as we need it ...
+        r = z.magnitude();
+        if r > bailout {
+            break;
+        }
+
+        // ... here in the ^7 thingy.
+        dr = pow(r, power - 1.0) * power.abs() * dr + 1.0;
+        z = rotate(z) + p;
+    }
+
+    let ln_r = r.ln() * r;
+    0.5 * ln_r / dr
+}
+
+/// Double precision version of `estimate_distance`, which also returns the
+/// coloring data of the orbit. It's generic to also calculate the exact
+/// gradient with dual numbers (see `Shape::gradient_at`). This is only used
+/// for mesh generation, so we don't bother with SIMD here.
+fn estimate_distance_f64<S: Real>(
+    p: [S; 3],
+    max_iters: u64,
+    bailout: f32,
+    power: f64,
+) -> (S, ColorData) {
+    let bailout = f64::from(bailout);
+    let mut z = p;
+    let mut dr = S::constant(1.0);
+    let mut r = S::constant(0.0);
+    let mut color = ColorData::orbit(max_iters);
+
+    for i in 0..max_iters {
+        r = magnitude(z);
+        color.trap([z[0].value(), z[1].value(), z[2].value()]);
+        if r.value() > bailout {
+            color.escape(i, r.value(), bailout, power);
+            break;
+        }
+
+        dr = r.pow(power - 1.0) * S::constant(power.abs()) * dr + S::constant(1.0);
+        let [x, y, z_] = rotate_f64(z, power);
+        z = [x + p[0], y + p[1], z_ + p[2]];
+    }
+
+    let ln_r = r.ln() * r;
+    (S::constant(0.5) * ln_r / dr, color)
+}
+
+/// Returns the parameters of the mandelbulb shapes. Only the power differs
+/// between `Mandelbulb` and `DynMandelbulb`.
+fn params(power_range: RangeInclusive<f32>) -> Vec<ParamInfo> {
+    vec![
+        ParamInfo {
+            name: "max_iters",
+            ty: ParamType::Integer,
+            range: 1.0..=f32::MAX,
+        },
+        ParamInfo {
+            name: "bailout",
+            ty: ParamType::Float,
+            range: 1.0..=f32::MAX,
+        },
+        ParamInfo {
+            name: "power",
+            ty: ParamType::Float,
+            range: power_range,
+        },
+    ]
+}
+
+fn common_param(name: &str, max_iters: u64, bailout: f32) -> Option<ParamValue> {
+    match name {
+        "max_iters" => Some(max_iters.into()),
+        "bailout" => Some(bailout.into()),
+        _ => None,
+    }
+}
+
+/// Sets `max_iters` or `bailout`. The value has to be checked before.
+fn set_common_param(name: &str, value: ParamValue, max_iters: &mut u64, bailout: &mut f32) {
+    match name {
+        "max_iters" => *max_iters = value.as_integer(),
+        "bailout" => *bailout = value.as_float(),
+        _ => unreachable!(),
+    }
+}
+
+/// Emits the DE of the mandelbulb. `power` is either a constant or the name of
+/// a uniform.
+fn build_de_shader(
+    glsl: &mut GlslBuilder,
+    max_iters: u64,
+    bailout: f32,
+    power: &dyn ToGlsl,
+) -> Ident {
+    glsl.function("mandelbulb", include_str!("mandelbulb.frag"), &[
+        ("BAILOUT", &bailout),
+        ("MAX_ITERS", &max_iters),
+        ("POWER", power),
+    ])
+}
+
+/// Calculates `x^e`, but uses the faster `powi` for integer exponents. If `e`
+/// is a constant, the branch is optimized away.
+#[inline(always)]
+fn pow(x: f32, e: f32) -> f32 {
+    if e.fract() == 0.0 {
+        x.powi(e as i32)
+    } else {
+        x.powf(e)
+    }
+}
+
 /// This operation rotates the point as triplex number. This is equivalent to
 /// the squaring in the original 2D mandelbrot. First we convert the point
 /// to spherical coordinates, then we rotate and convert them back.
 #[inline(always)]
-fn rotate<const P: u8>(p: Vec3) -> Vec3 {
+fn rotate<V: Vec3, const P: u8>(p: V) -> V {
     // Handle special case (general formula is not able to handle points on
     // the z axis).
     if p.is_on_z_axis() {
-        return rotate_on_z_axis::<P>(p);
+        return rotate_on_z_axis(p, P as f32);
     }
 
 
@@ -107,25 +474,70 @@ fn rotate<const P: u8>(p: Vec3) -> Vec3 {
     match P {
         8 => rotate_inner_p8_scalar(p),
         // 8 => unsafe { rotate_inner_p8_simd(p) },
-        _ => rotate_inner_px_generic::<P>(p),
+        _ => rotate_inner_px_generic(p, P as f32),
     }
 }
 
+/// Same as `rotate`, but with a power only known at runtime.
+#[inline(always)]
+fn rotate_dyn<V: Vec3>(p: V, power: f32) -> V {
+    if p.is_on_z_axis() {
+        return rotate_on_z_axis(p, power);
+    }
+
+    if power == 8.0 {
+        rotate_inner_p8_scalar(p)
+    } else {
+        rotate_inner_px_generic(p, power)
+    }
+}
+
+/// Double precision version of `rotate_dyn` (see `estimate_distance_f64`).
+fn rotate_f64<S: Real>([x, y, z]: [S; 3], power: f64) -> [S; 3] {
+    let old_radius = magnitude([x, y, z]);
+
+    // Convert to spherical coordinates, scale and rotate
+    let new_radius = old_radius.pow(power);
+    let theta = (z / old_radius).acos() * S::constant(power);
+
+    // Same special cases as in `rotate_dyn`
+    if x.value() == 0.0 && y.value() == 0.0 {
+        return [S::constant(0.0), S::constant(0.0), new_radius * theta.cos()];
+    }
+    if power == 8.0 {
+        return rotate_inner_p8_polynomial(x, y, z);
+    }
+
+    let phi = y.atan2(x) * S::constant(power);
+
+    // Convert back to cartesian coordinates
+    [
+        new_radius * theta.sin() * phi.cos(),
+        new_radius * phi.sin() * theta.sin(),
+        new_radius * theta.cos(),
+    ]
+}
+
+fn magnitude<S: Real>([x, y, z]: [S; 3]) -> S {
+    (x * x + y * y + z * z).sqrt()
+}
+
 #[inline(never)]
 #[cold]
-fn rotate_on_z_axis<const P: u8>(p: Vec3) -> Vec3 {
+fn rotate_on_z_axis<V: Vec3>(p: V, power: f32) -> V {
     let old_radius = p.magnitude();
     let theta = (p.z() / old_radius).acos();
 
     // Scale and rotate the point
-    let new_radius = old_radius.powi(P.into());
-    let theta = theta * P as f32;
+    let new_radius = pow(old_radius, power);
+    let theta = theta * power;
 
     // Convert back to cartesian coordinates
-    Vec3::new(0.0, 0.0, new_radius * theta.cos())
+    V::new(0.0, 0.0, new_radius * theta.cos())
 }
 
-fn rotate_inner_px_generic<const P: u8>(p: Vec3) -> Vec3 {
+#[inline(always)]
+fn rotate_inner_px_generic<V: Vec3>(p: V, power: f32) -> V {
     let old_radius = p.magnitude();
 
     // Convert to spherical coordinates
@@ -133,22 +545,32 @@ fn rotate_inner_px_generic<const P: u8>(p: Vec3) -> Vec3 {
     let phi = f32::atan2(p.y(), p.x());
 
     // Scale and rotate the point
-    let new_radius = old_radius.powi(P.into());
-    let theta = theta * P as f32;
-    let phi = phi * P as f32;
+    let new_radius = pow(old_radius, power);
+    let theta = theta * power;
+    let phi = phi * power;
 
     // Convert back to cartesian coordinates
-    new_radius * Vec3::new(
+    V::new(
         theta.sin() * phi.cos(),
         phi.sin() * theta.sin(),
         theta.cos(),
-    )
+    ) * new_radius
 }
 
-fn rotate_inner_p8_scalar(p: Vec3) -> Vec3 {
-    let x = p.x();
-    let y = p.y();
-    let z = p.z();
+fn rotate_inner_p8_scalar<V: Vec3>(p: V) -> V {
+    let [x, y, z] = rotate_inner_p8_polynomial(p.x(), p.y(), p.z());
+    V::new(x, y, z)
+}
+
+/// The polynomial used by `rotate_inner_p8_scalar`. It's generic to be usable
+/// for the double precision path (see `estimate_distance_f64`), too.
+///
+/// This is `rotate_inner_px_generic` with the power 8, where `cos(8θ)`,
+/// `sin(8θ)`, `cos(8φ)` and `sin(8φ)` are expanded with de Moivre's formula
+/// (`(z + iρ)⁸` and `(x + iy)⁸` with `ρ² = x² + y²`).
+#[inline(always)]
+fn rotate_inner_p8_polynomial<F: Real>(x: F, y: F, z: F) -> [F; 3] {
+    let c = F::constant;
 
     let x2 = x * x;
     let x4 = x2 * x2;
@@ -170,33 +592,32 @@ fn rotate_inner_p8_scalar(p: Vec3) -> Vec3 {
     let rxy6 = rxy2 * rxy4;
     let rxy8 = rxy4 * rxy4;
 
-    let a = 1.0 + (
-        z8
-        - 28.0 * z6 * rxy2
-        + 70.0 * z4 * rxy4
-        - 28.0 * z2 * rxy6
-    ) / rxy8;
-
+    let b = c(8.0) * z
+        * rxy2.sqrt()
+        * (z2 - rxy2)
+        * (z4 - c(6.0) * z2 * rxy2 + rxy4)
+        / rxy8;
 
-    Vec3::new(
-        a * (
+    [
+        b * (
             x8
-            - 28.0 * x6 * y2
-            + 70.0 * x4 * y4
-            - 28.0 * x2 * y6
-            - y8
+            - c(28.0) * x6 * y2
+            + c(70.0) * x4 * y4
+            - c(28.0) * x2 * y6
+            + y8
         ),
-        8.0 * a * x * y * (
+        c(8.0) * b * x * y * (
             x6
-            - 7.0 * x4 * y2
-            + 7.0 * x2 * y4
+            - c(7.0) * x4 * y2
+            + c(7.0) * x2 * y4
             - y6
         ),
-        8.0 * z
-            * rxy2.sqrt()
-            * (z2 - rxy2)
-            * (z4 - 6.0 * z2 * rxy2 + rxy4),
-    )
+        z8
+        - c(28.0) * z6 * rxy2
+        + c(70.0) * z4 * rxy4
+        - c(28.0) * z2 * rxy6
+        + rxy8,
+    ]
 }
 
 #[cfg(all(
@@ -204,7 +625,7 @@ fn rotate_inner_p8_scalar(p: Vec3) -> Vec3 {
     target_feature = "sse4.1"
 ))]
 #[allow(dead_code)]
-unsafe fn rotate_inner_p8_simd(p: Vec3) -> Vec3 {
+unsafe fn rotate_inner_p8_simd(p: SseVec3) -> SseVec3 {
     use core::arch::x86_64::*;
 
     let p = p.0;
@@ -293,7 +714,7 @@ unsafe fn rotate_inner_p8_simd(p: Vec3) -> Vec3 {
 
     // `xtmp` is a scalar caculated as:
     //
-    //     xtmp = -y8 +
+    //     xtmp = y8 +
     //         + 1 * x8 * 1    +
     //         -28 * x6 * y2   +
     //         +70 * x4 * y4   +
@@ -308,7 +729,7 @@ unsafe fn rotate_inner_p8_simd(p: Vec3) -> Vec3 {
 
 
         let y8 = f32::from_bits(_mm_extract_ps(ys, 3) as u32);
-        _mm_cvtss_f32(tmp) - y8
+        _mm_cvtss_f32(tmp) + y8
     };
 
     // `ytmp` is a scalar caculated as:
@@ -358,141 +779,241 @@ unsafe fn rotate_inner_p8_simd(p: Vec3) -> Vec3 {
 
     // Caculate the final result. It is caculated as:
     //
-    //     z = 8 * 1 * ztmp
-    //     y = 8 * a * ytmp
-    //     x = 1 * a * xtmp
-    let xyztmp = _mm_set_ps(0.0, ztmp, ytmp, xtmp);
-    Vec3(
-        _mm_mul_ps(
-            _mm_mul_ps(xyztmp, a),
-            _mm_set_ps(0.0, 8.0, 8.0, 1.0),
-        )
-    )
+    //     b = 8 * ztmp / w⁸
+    //     z = a * w⁸
+    //     y = 8 * b * ytmp
+    //     x = 1 * b * xtmp
+    let w8 = f32::from_bits(_mm_extract_ps(ws, 3) as u32);
+    let a = _mm_cvtss_f32(a);
+    let b = 8.0 * ztmp / w8;
+    SseVec3(_mm_set_ps(0.0, a * w8, 8.0 * b * ytmp, b * xtmp))
 }
 
 
 
-/// A 3D vector stored in a 128bit SIMD register.
-///
-/// x is stored in the lowest bits, the highest 32 bits are unused.
-#[derive(Debug, Clone, Copy)]
-struct Vec3(__m128);
-
-#[cfg(target_arch = "x86_64")]
-impl Vec3 {
-    #[inline(always)]
-    fn new(x: f32, y: f32, z: f32) -> Self {
-        let r = unsafe { _mm_set_ps(0.0, z, y, x) };
-        Self(r)
-    }
-
-    #[inline(always)]
-    fn x(self) -> f32 {
-        unsafe { _mm_cvtss_f32(self.0) }
-    }
-
-    #[inline(always)]
-    fn y(self) -> f32 {
-        unsafe { f32::from_bits(_mm_extract_ps(self.0, 1) as u32) }
-    }
-
-    #[inline(always)]
-    fn z(self) -> f32 {
-        unsafe { f32::from_bits(_mm_extract_ps(self.0, 2) as u32) }
-    }
-
-}
-
-#[cfg(all(
-    target_arch = "x86_64",
-    target_feature = "sse4.1"
-))]
-impl Vec3 {
-    #[inline(always)]
-    fn magnitude(self) -> f32 {
-        unsafe {
-            let len_squared = _mm_dp_ps(self.0, self.0, 0b0111_0001);
-            let len = _mm_sqrt_ss(len_squared);
-            _mm_cvtss_f32(len)
-        }
-    }
+/// Evaluates the DE of the mandelbulb with power 8 for many points at once.
+/// Depending on the CPU, 8 (AVX2) or 4 (SSE4.1) points are evaluated in
+/// parallel. The results are the same as calling `estimate_distance` for
+/// each point (up to rounding errors). On other architectures (or with the
+/// `force-scalar` feature), the points are simply evaluated one by one.
+fn batch_estimate_distance_p8(points: &[Point3<f32>], max_iters: u64, bailout: f32) -> Vec<f32> {
+    let mut out = Vec::with_capacity(points.len());
 
-    #[inline(always)]
-    fn is_on_z_axis(self) -> bool {
-        unsafe {
-            let mask = _mm_set_epi32(0, 0, 0x7FFF_FFFF, 0x7FFF_FFFF);
-            _mm_test_all_zeros(_mm_castps_si128(self.0), mask) == 1
+    #[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            unsafe { batch_estimate_distance_p8_avx2(points, max_iters, bailout, &mut out) };
+            return out;
+        } else if is_x86_feature_detected!("sse4.1") {
+            unsafe { batch_estimate_distance_p8_sse41(points, max_iters, bailout, &mut out) };
+            return out;
         }
     }
-}
 
-#[cfg(all(
-    target_arch = "x86_64",
-    not(target_feature = "sse4.1")
-))]
-impl Vec3 {
-    #[inline(always)]
-    fn magnitude(self) -> f32 {
-        (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt()
-    }
+    out.extend(points.iter().map(|&p| {
+        estimate_distance::<NativeVec3>(p, max_iters, bailout, 8.0, rotate::<_, 8>)
+    }));
 
-    #[inline(always)]
-    fn is_on_z_axis(self) -> bool {
-        self.x() == 0.0 && self.y() == 0.0
-    }
+    out
 }
 
-impl Add for Vec3 {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        Self(unsafe { _mm_add_ps(self.0, other.0) })
-    }
+#[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+#[target_feature(enable = "avx2")]
+unsafe fn batch_estimate_distance_p8_avx2(
+    points: &[Point3<f32>],
+    max_iters: u64,
+    bailout: f32,
+    out: &mut Vec<f32>,
+) {
+    batch_estimate_distance_p8_lanes::<super::simd::Avx>(points, max_iters, bailout, out)
 }
 
-impl Sub for Vec3 {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
-        Self(unsafe { _mm_sub_ps(self.0, other.0) })
-    }
+#[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+#[target_feature(enable = "sse4.1")]
+unsafe fn batch_estimate_distance_p8_sse41(
+    points: &[Point3<f32>],
+    max_iters: u64,
+    bailout: f32,
+    out: &mut Vec<f32>,
+) {
+    batch_estimate_distance_p8_lanes::<super::simd::Sse>(points, max_iters, bailout, out)
 }
 
-impl Mul<f32> for Vec3 {
-    type Output = Self;
-    fn mul(self, other: f32) -> Self {
-        Self(unsafe { _mm_mul_ps(self.0, Self::new(other, other, other).0) })
-    }
-}
+/// The SIMD kernel of `batch_estimate_distance_p8`. This mirrors
+/// `estimate_distance` and `rotate_inner_p8_scalar`, but each lane holds a
+/// different point. Lanes which already escaped are masked out.
+///
+/// Must only be called with the CPU feature required by `V` enabled.
+#[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+#[inline(always)]
+unsafe fn batch_estimate_distance_p8_lanes<V: super::simd::F32Lanes>(
+    points: &[Point3<f32>],
+    max_iters: u64,
+    bailout: f32,
+    out: &mut Vec<f32>,
+) {
+    let c = |v: f32| V::splat(v);
+
+    // We need `V::LANES` values, but can't create arrays of that size
+    // generically. So we just use the maximum.
+    const MAX_LANES: usize = 8;
+    assert!(V::LANES <= MAX_LANES);
+
+    for chunk in points.chunks(V::LANES) {
+        // Transpose the points into one register per coordinate. The unused
+        // lanes of the last chunk are filled with zeroes.
+        let mut coords = [[0.0; MAX_LANES]; 3];
+        for (i, p) in chunk.iter().enumerate() {
+            coords[0][i] = p.x;
+            coords[1][i] = p.y;
+            coords[2][i] = p.z;
+        }
+        let px = V::load(&coords[0]);
+        let py = V::load(&coords[1]);
+        let pz = V::load(&coords[2]);
+
+        let (mut x, mut y, mut z) = (px, py, pz);
+        let mut dr = c(1.0);
+        let mut r = c(0.0);
+        let mut active = c(0.0).equal(c(0.0));
+
+        for _ in 0..max_iters {
+            let new_r = (x * x + y * y + z * z).sqrt();
+            r = active.select(new_r, r);
+            active = active.and(new_r.not_greater(c(bailout)));
+            if !active.any() {
+                break;
+            }
 
-impl Mul<Vec3> for f32 {
-    type Output = Vec3;
-    fn mul(self, other: Vec3) -> Vec3 {
-        other * self
+            let r2 = new_r * new_r;
+            let r4 = r2 * r2;
+            dr = active.select(r4 * r2 * new_r * c(8.0) * dr + c(1.0), dr);
+
+            // The triplex power, see `rotate_inner_p8_scalar`.
+            let x2 = x * x;
+            let x4 = x2 * x2;
+            let x6 = x4 * x2;
+            let x8 = x4 * x4;
+
+            let y2 = y * y;
+            let y4 = y2 * y2;
+            let y6 = y4 * y2;
+            let y8 = y4 * y4;
+
+            let z2 = z * z;
+            let z4 = z2 * z2;
+            let z6 = z4 * z2;
+            let z8 = z4 * z4;
+
+            let rxy2 = x2 + y2;
+            let rxy4 = rxy2 * rxy2;
+            let rxy6 = rxy2 * rxy4;
+            let rxy8 = rxy4 * rxy4;
+
+            let b = c(8.0) * z
+                * rxy2.sqrt()
+                * (z2 - rxy2)
+                * (z4 - c(6.0) * z2 * rxy2 + rxy4)
+                / rxy8;
+
+            let new_x = b * (
+                x8
+                - c(28.0) * x6 * y2
+                + c(70.0) * x4 * y4
+                - c(28.0) * x2 * y6
+                + y8
+            );
+            let new_y = c(8.0) * b * x * y * (
+                x6
+                - c(7.0) * x4 * y2
+                + c(7.0) * x2 * y4
+                - y6
+            );
+            let new_z = z8
+                - c(28.0) * z6 * rxy2
+                + c(70.0) * z4 * rxy4
+                - c(28.0) * z2 * rxy6
+                + rxy8;
+
+            // Points on the z axis (see `rotate_on_z_axis`): there, the power
+            // is simply `z⁸`.
+            let on_z_axis = rxy2.equal(c(0.0));
+            let new_x = on_z_axis.select(c(0.0), new_x);
+            let new_y = on_z_axis.select(c(0.0), new_y);
+            let new_z = on_z_axis.select(z8, new_z);
+
+            x = active.select(new_x + px, x);
+            y = active.select(new_y + py, y);
+            z = active.select(new_z + pz, z);
+        }
+
+        let mut rs = [0.0; MAX_LANES];
+        let mut drs = [0.0; MAX_LANES];
+        r.store(&mut rs);
+        dr.store(&mut drs);
+        out.extend((0..chunk.len()).map(|i| 0.5 * rs[i].ln() * rs[i] / drs[i]));
     }
 }
 
 #[cfg(test)]
 mod bench {
     use test::{Bencher, black_box};
-    use super::Vec3;
-    use super::super::BENCH_POINTS;
+    use super::super::{
+        BENCH_POINTS,
+        vec3::{ScalarVec3, Vec3},
+    };
 
-    #[bench]
-    fn rotate_inner_generic(b: &mut Bencher) {
-        b.iter(|| {
-            for &[x, y, z] in &BENCH_POINTS {
-                black_box(super::rotate_inner_px_generic::<8>(Vec3::new(x, y, z)));
+    #[cfg(target_arch = "x86_64")]
+    use super::super::vec3::SseVec3;
+
+    /// Generates the benchmarks for one `Vec3` backend.
+    macro_rules! backend_benches {
+        ($mod_name:ident, $vec:ty) => {
+            mod $mod_name {
+                use super::*;
+
+                #[bench]
+                fn rotate_inner_generic(b: &mut Bencher) {
+                    b.iter(|| {
+                        for &[x, y, z] in &BENCH_POINTS {
+                            let p = <$vec>::new(x, y, z);
+                            black_box(super::super::rotate_inner_px_generic(p, 8.0));
+                        }
+                    });
+                }
+
+                #[bench]
+                fn rotate_inner_p8_scalar(b: &mut Bencher) {
+                    b.iter(|| {
+                        for &[x, y, z] in &BENCH_POINTS {
+                            let p = <$vec>::new(x, y, z);
+                            black_box(super::super::rotate_inner_p8_scalar(p));
+                        }
+                    });
+                }
+
+                #[bench]
+                fn estimate_distance_p8(b: &mut Bencher) {
+                    b.iter(|| {
+                        BENCH_POINTS.iter()
+                            .map(|&p| super::super::estimate_distance::<$vec>(
+                                black_box(p.into()),
+                                8,
+                                5.0,
+                                8.0,
+                                super::super::rotate::<_, 8>,
+                            ))
+                            .sum::<f32>()
+                    });
+                }
             }
-        });
+        }
     }
 
-    #[bench]
-    fn rotate_inner_p8_scalar(b: &mut Bencher) {
-        b.iter(|| {
-            for &[x, y, z] in &BENCH_POINTS {
-                black_box(super::rotate_inner_p8_scalar(Vec3::new(x, y, z)));
-            }
-        });
-    }
+    backend_benches!(scalar, ScalarVec3);
+
+    #[cfg(target_arch = "x86_64")]
+    backend_benches!(sse, SseVec3);
 
     #[cfg(all(
         target_arch = "x86_64",
@@ -502,8 +1023,143 @@ mod bench {
     fn rotate_inner_p8_simd(b: &mut Bencher) {
         b.iter(|| {
             for &[x, y, z] in &BENCH_POINTS {
-                black_box(unsafe { super::rotate_inner_p8_simd(Vec3::new(x, y, z)) });
+                black_box(unsafe { super::rotate_inner_p8_simd(SseVec3::new(x, y, z)) });
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::BENCH_POINTS;
+
+    #[cfg(target_arch = "x86_64")]
+    use super::super::vec3::{ScalarVec3, SseVec3};
+
+    /// `BENCH_POINTS` and two points on the z axis. Their number is not a
+    /// multiple of 4 or 8, so the last chunk of the batch kernels is partial.
+    fn points() -> Vec<Point3<f32>> {
+        let mut points: Vec<_> = BENCH_POINTS.iter().map(|&p| p.into()).collect();
+        points.push(Point3::new(0.0, 0.0, 0.7));
+        points.push(Point3::new(0.0, 0.0, -1.05));
+        points
+    }
+
+    /// Compares the results of a batch evaluation with `estimate_distance`.
+    fn assert_same_as_single(points: &[Point3<f32>], batch: &[f32]) {
+        assert_eq!(points.len(), batch.len());
+        for (&p, &actual) in points.iter().zip(batch) {
+            let expected = estimate_distance::<NativeVec3>(p, 8, 2.5, 8.0, rotate::<_, 8>);
+            assert!(
+                (actual - expected).abs() <= 1e-5 + 1e-4 * expected.abs(),
+                "batch DE at {:?} is {}, but {} was expected",
+                p,
+                actual,
+                expected,
+            );
+        }
+    }
+
+    #[test]
+    fn batch_p8() {
+        let points = points();
+        assert_same_as_single(&points, &batch_estimate_distance_p8(&points, 8, 2.5));
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+    fn batch_p8_lanes() {
+        let points = points();
+        for &n in &[3, 4, 8, points.len()] {
+            let points = &points[..n];
+            if is_x86_feature_detected!("avx2") {
+                let mut out = Vec::new();
+                unsafe { batch_estimate_distance_p8_avx2(points, 8, 2.5, &mut out) };
+                assert_same_as_single(points, &out);
+            }
+            if is_x86_feature_detected!("sse4.1") {
+                let mut out = Vec::new();
+                unsafe { batch_estimate_distance_p8_sse41(points, 8, 2.5, &mut out) };
+                assert_same_as_single(points, &out);
+            }
+        }
+    }
+
+    /// Asserts that `actual` is (up to rounding errors) the power 8 triplex
+    /// rotation of `p`, calculated with the trigonometric formula.
+    fn assert_rotation_p8(p: [f32; 3], actual: [f32; 3]) {
+        let [x, y, z] = p;
+        let expected = rotate_inner_px_generic(NativeVec3::new(x, y, z), 8.0);
+        let expected = Vector3::new(expected.x(), expected.y(), expected.z());
+        let actual = Vector3::from(actual);
+        assert!(
+            (actual - expected).magnitude() <= 1e-4 * expected.magnitude().max(1.0),
+            "rotation of {:?} is {:?}, but {:?} was expected",
+            p,
+            actual,
+            expected,
+        );
+    }
+
+    #[test]
+    fn rotate_p8() {
+        for &[x, y, z] in &BENCH_POINTS {
+            let native = rotate_inner_p8_scalar(NativeVec3::new(x, y, z));
+            assert_rotation_p8([x, y, z], [native.x(), native.y(), native.z()]);
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                let scalar = rotate_inner_p8_scalar(ScalarVec3::new(x, y, z));
+                assert_rotation_p8([x, y, z], [scalar.x(), scalar.y(), scalar.z()]);
+            }
+
+            #[cfg(all(target_arch = "x86_64", target_feature = "sse4.1"))]
+            {
+                let simd = unsafe { rotate_inner_p8_simd(SseVec3::new(x, y, z)) };
+                assert_rotation_p8([x, y, z], [simd.x(), simd.y(), simd.z()]);
+            }
+        }
+    }
+
+    /// With two iterations, the batch kernels apply the rotation once, so
+    /// they have to match the trigonometric formula, too.
+    #[test]
+    fn batch_rotate_p8() {
+        let points: Vec<Point3<f32>> = BENCH_POINTS.iter().map(|&p| p.into()).collect();
+        let batch = batch_estimate_distance_p8(&points, 2, 2.5);
+        for (&p, &actual) in points.iter().zip(&batch) {
+            let expected = estimate_distance::<NativeVec3>(p, 2, 2.5, 8.0, |z| {
+                rotate_inner_px_generic(z, 8.0)
+            });
+            assert!(
+                (actual - expected).abs() <= 1e-5 + 1e-4 * expected.abs(),
+                "batch DE at {:?} is {}, but {} was expected",
+                p,
+                actual,
+                expected,
+            );
+        }
+    }
+
+    /// Both `Vec3` backends have to produce exactly the same distances.
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn backends_are_identical() {
+        for &p in &BENCH_POINTS {
+            let p = p.into();
+
+            let scalar = estimate_distance::<ScalarVec3>(p, 8, 5.0, 8.0, rotate::<_, 8>);
+            let sse = estimate_distance::<SseVec3>(p, 8, 5.0, 8.0, rotate::<_, 8>);
+            assert_eq!(scalar.to_bits(), sse.to_bits(), "power 8 at {:?}", p);
+
+            let scalar = estimate_distance::<ScalarVec3>(p, 8, 5.0, 3.0, rotate::<_, 3>);
+            let sse = estimate_distance::<SseVec3>(p, 8, 5.0, 3.0, rotate::<_, 3>);
+            assert_eq!(scalar.to_bits(), sse.to_bits(), "power 3 at {:?}", p);
+
+            let scalar = estimate_distance(p, 8, 5.0, 7.5, |z: ScalarVec3| rotate_dyn(z, 7.5));
+            let sse = estimate_distance(p, 8, 5.0, 7.5, |z: SseVec3| rotate_dyn(z, 7.5));
+            assert_eq!(scalar.to_bits(), sse.to_bits(), "power 7.5 at {:?}", p);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use cgmath::{prelude::*, Point3, Vector3};
+
+
+/// Maximum number of triangles in a leaf.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy of axis aligned boxes. It's used to quickly
+/// find the triangle closest to a point.
+///
+/// The tree is stored in a flat list of nodes. The root is the first node,
+/// the children of an inner node are stored next to each other.
+#[derive(Clone)]
+pub(super) struct Bvh {
+    nodes: Vec<Node>,
+
+    /// The indices of all triangles, ordered such that the triangles of each
+    /// leaf are consecutive.
+    order: Vec<u32>,
+}
+
+#[derive(Clone)]
+struct Node {
+    min: Point3<f64>,
+    max: Point3<f64>,
+
+    /// For leaves, this is the first index into `order`. For inner nodes, it
+    /// is the index of the first child (the second child follows).
+    start: u32,
+
+    /// The number of triangles in this leaf or 0 for inner nodes.
+    count: u32,
+}
+
+impl Bvh {
+    /// Builds the BVH for triangles with the given bounding boxes.
+    pub(super) fn build(bounds: &[(Point3<f64>, Point3<f64>)]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            order: (0..bounds.len() as u32).collect(),
+        };
+
+        if !bounds.is_empty() {
+            bvh.nodes.push(Node::empty());
+            bvh.build_node(0, 0, bounds.len(), bounds);
+        }
+
+        bvh
+    }
+
+    /// Initializes the node `node_idx` for the triangles `order[start..end]`
+    /// and recursively its children. They are split at the median along the
+    /// axis in which the triangles' centers are spread the most.
+    fn build_node(
+        &mut self,
+        node_idx: usize,
+        start: usize,
+        end: usize,
+        bounds: &[(Point3<f64>, Point3<f64>)],
+    ) {
+        let center = |t: u32| {
+            let (min, max) = bounds[t as usize];
+            Point3::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, (min.z + max.z) / 2.0)
+        };
+
+        let mut node = Node::empty();
+        let mut center_min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut center_max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &t in &self.order[start..end] {
+            let (min, max) = bounds[t as usize];
+            node.extend(min, max);
+
+            let c = center(t);
+            center_min = min_point(center_min, c);
+            center_max = max_point(center_max, c);
+        }
+
+        if end - start <= MAX_LEAF_SIZE {
+            node.start = start as u32;
+            node.count = (end - start) as u32;
+            self.nodes[node_idx] = node;
+            return;
+        }
+
+        let spread = center_max - center_min;
+        let axis = if spread.x >= spread.y && spread.x >= spread.z {
+            0
+        } else if spread.y >= spread.z {
+            1
+        } else {
+            2
+        };
+        self.order[start..end].sort_unstable_by(|&a, &b| {
+            center(a)[axis].partial_cmp(&center(b)[axis]).unwrap()
+        });
+
+        let first_child = self.nodes.len();
+        node.start = first_child as u32;
+        self.nodes[node_idx] = node;
+        self.nodes.push(Node::empty());
+        self.nodes.push(Node::empty());
+
+        let mid = (start + end) / 2;
+        self.build_node(first_child, start, mid, bounds);
+        self.build_node(first_child + 1, mid, end, bounds);
+    }
+
+    /// Finds the triangle closest to `p` and returns its index and squared
+    /// distance, or `None` if there are no triangles.
+    ///
+    /// `distance2` has to return the squared distance from `p` to the given
+    /// triangle.
+    pub(super) fn closest(
+        &self,
+        p: Point3<f64>,
+        mut distance2: impl FnMut(u32) -> f64,
+    ) -> Option<(u32, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<(u32, f64)> = None;
+        let mut stack = vec![(0, self.nodes[0].distance2(p))];
+        while let Some((node_idx, node_distance2)) = stack.pop() {
+            if best.is_some_and(|(_, d2)| node_distance2 >= d2) {
+                continue;
+            }
+
+            let node = &self.nodes[node_idx];
+            if node.count > 0 {
+                let start = node.start as usize;
+                for &t in &self.order[start..start + node.count as usize] {
+                    let d2 = distance2(t);
+                    if best.is_none_or(|(_, best_d2)| d2 < best_d2) {
+                        best = Some((t, d2));
+                    }
+                }
+            } else {
+                // Visit the closer child first (by pushing it last) as it
+                // will likely contain the closest triangle, which allows us
+                // to skip more nodes.
+                let a = node.start as usize;
+                let b = a + 1;
+                let (da, db) = (self.nodes[a].distance2(p), self.nodes[b].distance2(p));
+                if da < db {
+                    stack.push((b, db));
+                    stack.push((a, da));
+                } else {
+                    stack.push((a, da));
+                    stack.push((b, db));
+                }
+            }
+        }
+
+        best
+    }
+}
+
+impl Node {
+    fn empty() -> Self {
+        Self {
+            min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            start: 0,
+            count: 0,
+        }
+    }
+
+    fn extend(&mut self, min: Point3<f64>, max: Point3<f64>) {
+        self.min = min_point(self.min, min);
+        self.max = max_point(self.max, max);
+    }
+
+    /// The squared distance from `p` to this box (0 if `p` is inside).
+    fn distance2(&self, p: Point3<f64>) -> f64 {
+        let outside = |v: f64, min: f64, max: f64| (min - v).max(0.0).max(v - max);
+        Vector3::new(
+            outside(p.x, self.min.x, self.max.x),
+            outside(p.y, self.min.y, self.max.y),
+            outside(p.z, self.min.z, self.max.z),
+        ).magnitude2()
+    }
+}
+
+pub(super) fn min_point(a: Point3<f64>, b: Point3<f64>) -> Point3<f64> {
+    Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+pub(super) fn max_point(a: Point3<f64>, b: Point3<f64>) -> Point3<f64> {
+    Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
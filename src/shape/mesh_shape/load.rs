@@ -0,0 +1,174 @@
+use std::{fs, path::Path};
+use cgmath::Point3;
+
+use crate::prelude::*;
+
+
+/// A triangle mesh as stored in a file: vertex positions and triangles which
+/// index into `positions`.
+#[derive(Debug, Clone, Default)]
+pub(super) struct RawMesh {
+    pub positions: Vec<Point3<f64>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Loads the OBJ or STL file at `path`. The format is determined by the file
+/// extension.
+pub(super) fn load(path: &Path) -> Result<RawMesh> {
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let data = fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+    let mesh = match extension.as_deref() {
+        Some("obj") => {
+            let src = std::str::from_utf8(&data).context("OBJ file is not valid UTF-8")?;
+            parse_obj(src)
+        }
+        Some("stl") => parse_stl(&data),
+        _ => bail!("unknown mesh format of '{}' (expected .obj or .stl)", path.display()),
+    };
+
+    mesh.with_context(|| format!("failed to load mesh '{}'", path.display()))
+}
+
+/// Parses the vertices and faces of a Wavefront OBJ file. Everything else
+/// (normals, texture coordinates, materials, groups, ...) is ignored.
+/// Polygons are triangulated as triangle fans.
+pub(super) fn parse_obj(src: &str) -> Result<RawMesh> {
+    let mut mesh = RawMesh::default();
+
+    for (line_idx, line) in src.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let line = line.split('#').next().unwrap();
+        let mut parts = line.split_whitespace();
+
+        match parts.next() {
+            Some("v") => {
+                let mut coord = || -> Result<f64> {
+                    let s = parts.next()
+                        .ok_or_else(|| anyhow!("vertex with less than 3 coordinates"))?;
+                    s.parse().map_err(|_| anyhow!("invalid coordinate '{}'", s))
+                };
+                let p = Point3::new(coord()?, coord()?, coord()?);
+                mesh.positions.push(p);
+            }
+            Some("f") => {
+                let indices = parts
+                    .map(|vertex| obj_index(vertex, mesh.positions.len()))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("invalid face in line {}", line_num))?;
+                if indices.len() < 3 {
+                    bail!("face with less than 3 vertices in line {}", line_num);
+                }
+
+                for i in 1..indices.len() - 1 {
+                    mesh.triangles.push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
+
+/// Parses one vertex of an OBJ face (`v`, `v/vt`, `v//vn` or `v/vt/vn`) and
+/// returns the zero based position index. OBJ indices start at 1, negative
+/// indices are relative to the end of the list of vertices read so far.
+fn obj_index(vertex: &str, num_positions: usize) -> Result<u32> {
+    let s = vertex.split('/').next().unwrap();
+    let idx: i64 = s.parse().map_err(|_| anyhow!("invalid vertex index '{}'", s))?;
+
+    let resolved = if idx > 0 {
+        idx - 1
+    } else {
+        num_positions as i64 + idx
+    };
+    if idx == 0 || resolved < 0 || resolved >= num_positions as i64 {
+        bail!("vertex index {} out of range", idx);
+    }
+
+    Ok(resolved as u32)
+}
+
+/// Parses an ASCII or binary STL file. As STL files store three separate
+/// vertices for each triangle, vertices are not shared (see
+/// `MeshShape::new` which merges them).
+pub(super) fn parse_stl(data: &[u8]) -> Result<RawMesh> {
+    // Binary files have an 80 byte header (which may start with `solid`, too,
+    // unfortunately) followed by the number of triangles. If the size
+    // matches, we assume it's a binary file.
+    const HEADER_LEN: usize = 80;
+    const TRIANGLE_LEN: usize = 50;
+
+    if data.len() >= HEADER_LEN + 4 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == HEADER_LEN + 4 + count * TRIANGLE_LEN {
+            return Ok(parse_stl_binary(&data[HEADER_LEN + 4..], count));
+        }
+    }
+
+    if !data.starts_with(b"solid") {
+        bail!("neither a valid binary STL file nor an ASCII one (starting with 'solid')");
+    }
+    let src = std::str::from_utf8(data).context("ASCII STL file is not valid UTF-8")?;
+    parse_stl_ascii(src)
+}
+
+fn parse_stl_binary(data: &[u8], count: usize) -> RawMesh {
+    let f32_at = |offset: usize| {
+        let bytes = [data[offset], data[offset + 1], data[offset + 2], data[offset + 3]];
+        f64::from(f32::from_le_bytes(bytes))
+    };
+
+    let mut mesh = RawMesh::default();
+    for t in 0..count {
+        // Each triangle: normal, three vertices (each 3 `f32`) and two bytes
+        // "attribute byte count". We ignore the normal.
+        let base = t * 50 + 12;
+        for v in 0..3 {
+            let offset = base + v * 12;
+            let p = Point3::new(f32_at(offset), f32_at(offset + 4), f32_at(offset + 8));
+            mesh.positions.push(p);
+        }
+
+        let first = (t * 3) as u32;
+        mesh.triangles.push([first, first + 1, first + 2]);
+    }
+
+    mesh
+}
+
+fn parse_stl_ascii(src: &str) -> Result<RawMesh> {
+    let mut mesh = RawMesh::default();
+    let mut facet = Vec::with_capacity(3);
+
+    for (line_idx, line) in src.lines().enumerate() {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("vertex") => {
+                let coords = parts
+                    .map(|s| s.parse::<f64>().map_err(|_| anyhow!("invalid coordinate '{}'", s)))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("invalid vertex in line {}", line_idx + 1))?;
+                if coords.len() != 3 {
+                    bail!("vertex without 3 coordinates in line {}", line_idx + 1);
+                }
+                facet.push(Point3::new(coords[0], coords[1], coords[2]));
+            }
+            Some("endfacet") => {
+                if facet.len() != 3 {
+                    bail!("facet without 3 vertices ending in line {}", line_idx + 1);
+                }
+
+                let first = mesh.positions.len() as u32;
+                mesh.positions.append(&mut facet);
+                mesh.triangles.push([first, first + 1, first + 2]);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
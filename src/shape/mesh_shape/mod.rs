@@ -0,0 +1,470 @@
+use std::{collections::HashMap, ops::Range, path::Path};
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::prelude::*;
+use super::{
+    Shape,
+    glsl::{GlslBuilder, Ident},
+};
+use self::bvh::{max_point, min_point, Bvh};
+
+mod bvh;
+mod load;
+
+
+/// A shape defined by a closed triangle mesh, e.g. loaded from an OBJ or STL
+/// file.
+///
+/// Unlike for fractals, the distance to the surface is calculated exactly:
+/// the closest triangle is found via a BVH and the sign is determined with
+/// angle weighted pseudo normals (Bærentzen & Aanæs, "Signed Distance
+/// Computation Using the Angle Weighted Pseudonormal"). This is only correct
+/// for closed, manifold meshes whose triangles are consistently oriented.
+#[derive(Clone)]
+pub struct MeshShape {
+    positions: Vec<Point3<f64>>,
+    triangles: Vec<Triangle>,
+
+    /// The angle weighted pseudo normal of each vertex.
+    vertex_normals: Vec<Vector3<f64>>,
+
+    bvh: Bvh,
+    min: Point3<f64>,
+    max: Point3<f64>,
+}
+
+#[derive(Clone)]
+struct Triangle {
+    vertices: [u32; 3],
+    normal: Vector3<f64>,
+
+    /// The pseudo normal (sum of the normals of both adjacent triangles) of
+    /// each edge. Edge `i` goes from vertex `i` to vertex `(i + 1) % 3`.
+    edge_normals: [Vector3<f64>; 3],
+}
+
+/// The part of a triangle a point is closest to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Feature {
+    Vertex(usize),
+    Edge(usize),
+    Face,
+}
+
+impl MeshShape {
+    /// Loads the mesh from an OBJ or STL file (determined by the file
+    /// extension).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mesh = load::load(path)?;
+        Self::new(mesh.positions, mesh.triangles)
+            .with_context(|| format!("invalid mesh '{}'", path.display()))
+    }
+
+    /// Creates the shape from a triangle mesh, where each triangle consists
+    /// of three indices into `positions`.
+    ///
+    /// Vertices with identical positions are merged and degenerate triangles
+    /// are removed. The triangles should be ordered counter-clockwise when
+    /// seen from outside; if the mesh is inside out, all triangles are
+    /// flipped.
+    pub fn new(positions: Vec<Point3<f64>>, triangles: Vec<[u32; 3]>) -> Result<Self> {
+        if let Some(idx) = triangles.iter().flatten().find(|&&i| i as usize >= positions.len()) {
+            bail!("vertex index {} out of range (mesh has {} vertices)", idx, positions.len());
+        }
+
+        // Merge vertices with the same position, as e.g. STL files don't
+        // share vertices between triangles. Adding 0.0 turns -0.0 into 0.0.
+        let mut ids = HashMap::new();
+        let mut merged = Vec::new();
+        let remap = positions.iter().map(|p| {
+            let key = [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+            *ids.entry(key).or_insert_with(|| {
+                merged.push(*p);
+                merged.len() as u32 - 1
+            })
+        }).collect::<Vec<_>>();
+        let positions = merged;
+
+        let mut triangles = triangles.iter()
+            .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+            .filter(|t| {
+                let [a, b, c] = t.map(|i| positions[i as usize]);
+                (b - a).cross(c - a).magnitude2() > 0.0
+            })
+            .collect::<Vec<_>>();
+        if triangles.is_empty() {
+            bail!("mesh has no (non-degenerate) triangles");
+        }
+
+        // A mesh with outward facing triangles has a positive volume.
+        let volume: f64 = triangles.iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|i| positions[i as usize].to_vec());
+                a.dot(b.cross(c))
+            })
+            .sum();
+        if volume < 0.0 {
+            for t in &mut triangles {
+                t.swap(1, 2);
+            }
+        }
+
+        // Calculate the pseudo normals.
+        let normals = triangles.iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|i| positions[i as usize]);
+                (b - a).cross(c - a).normalize()
+            })
+            .collect::<Vec<_>>();
+
+        let mut vertex_normals = vec![Vector3::zero(); positions.len()];
+        let mut edge_normals = HashMap::new();
+        for (t, &normal) in triangles.iter().zip(&normals) {
+            for i in 0..3 {
+                let (a, b, c) = (t[i], t[(i + 1) % 3], t[(i + 2) % 3]);
+                let p = positions[a as usize];
+                let angle = (positions[b as usize] - p).angle(positions[c as usize] - p);
+                vertex_normals[a as usize] += normal * angle.0;
+
+                let edge = (a.min(b), a.max(b));
+                let e = edge_normals.entry(edge).or_insert((Vector3::zero(), 0));
+                e.0 += normal;
+                e.1 += 1;
+            }
+        }
+
+        let open_edges = edge_normals.values().filter(|(_, count)| *count != 2).count();
+        if open_edges > 0 {
+            warn!(
+                "mesh is not closed or not manifold ({} edges don't have exactly two adjacent \
+                    triangles), distances might have the wrong sign",
+                open_edges,
+            );
+        }
+
+        let triangles = triangles.iter().zip(&normals)
+            .map(|(&vertices, &normal)| {
+                let edge_normal = |i: usize| {
+                    let (a, b) = (vertices[i], vertices[(i + 1) % 3]);
+                    edge_normals[&(a.min(b), a.max(b))].0
+                };
+
+                Triangle {
+                    vertices,
+                    normal,
+                    edge_normals: [edge_normal(0), edge_normal(1), edge_normal(2)],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let bounds = triangles.iter()
+            .map(|t| {
+                let [a, b, c] = t.vertices.map(|i| positions[i as usize]);
+                (min_point(a, min_point(b, c)), max_point(a, max_point(b, c)))
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&bounds);
+
+        let (min, max) = bounds.iter().fold(
+            (bounds[0].0, bounds[0].1),
+            |(min, max), &(a, b)| (min_point(min, a), max_point(max, b)),
+        );
+
+        Ok(Self {
+            positions,
+            triangles,
+            vertex_normals,
+            bvh,
+            min,
+            max,
+        })
+    }
+
+    /// Returns the point on the surface closest to `p` and the pseudo normal
+    /// at that point.
+    fn closest_point(&self, p: Point3<f64>) -> (Point3<f64>, Vector3<f64>) {
+        let (t, _) = self.bvh.closest(p, |t| {
+            let (q, _) = self.closest_point_on(t as usize, p);
+            (q - p).magnitude2()
+        }).expect("mesh without triangles");
+
+        let (q, feature) = self.closest_point_on(t as usize, p);
+        let triangle = &self.triangles[t as usize];
+        let normal = match feature {
+            Feature::Vertex(i) => self.vertex_normals[triangle.vertices[i] as usize],
+            Feature::Edge(i) => triangle.edge_normals[i],
+            Feature::Face => triangle.normal,
+        };
+
+        (q, normal)
+    }
+
+    /// Returns the point on triangle `t` closest to `p` and the feature it
+    /// lies on. See "Real-Time Collision Detection" (Ericson), section 5.1.5.
+    fn closest_point_on(&self, t: usize, p: Point3<f64>) -> (Point3<f64>, Feature) {
+        let [a, b, c] = self.triangles[t].vertices.map(|i| self.positions[i as usize]);
+        let ab = b - a;
+        let ac = c - a;
+
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return (a, Feature::Vertex(0));
+        }
+
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return (b, Feature::Vertex(1));
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return (a + ab * (d1 / (d1 - d3)), Feature::Edge(0));
+        }
+
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return (c, Feature::Vertex(2));
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return (a + ac * (d2 / (d2 - d6)), Feature::Edge(2));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return (b + (c - b) * w, Feature::Edge(1));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        (a + ab * (vb * denom) + ac * (vc * denom), Feature::Face)
+    }
+}
+
+impl Shape for MeshShape {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.min_distance_from_f64(p.cast().unwrap()) as f32
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        let (q, normal) = self.closest_point(p);
+        let distance = (p - q).magnitude();
+        if (p - q).dot(normal) < 0.0 {
+            -distance
+        } else {
+            distance
+        }
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, _delta: f64) -> Vector3<f64> {
+        let (q, normal) = self.closest_point(p);
+        let diff = p - q;
+        let distance = diff.magnitude();
+
+        // On the surface, the gradient is not defined; the pseudo normal is
+        // the best we can do.
+        if distance == 0.0 {
+            normal.normalize()
+        } else if diff.dot(normal) < 0.0 {
+            -diff / distance
+        } else {
+            diff / distance
+        }
+    }
+
+    fn max_distance_from(&self, p: Point3<f32>) -> Option<f32> {
+        Some(self.min_distance_from(p))
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        self.min.cast().unwrap()..self.max.cast().unwrap()
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        // The triangles are not available on the GPU, so the ray marcher
+        // only sees the bounding box.
+        let min = self.min.cast::<f32>().unwrap().to_vec();
+        let max = self.max.cast::<f32>().unwrap().to_vec();
+        glsl.function("mesh", include_str!("../bounding_box.frag"), &[
+            ("CENTER", &((min + max) / 2.0)),
+            ("HALF_SIZE", &((max - min) / 2.0)),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{prelude::*, Point3, Vector3};
+
+    use crate::shape::{check::{check, CheckSettings}, Shape};
+    use super::{load, MeshShape};
+
+    /// A cube from -1 to 1, with quads and negative indices.
+    const CUBE_OBJ: &str = "
+        # cube
+        v -1 -1 -1
+        v  1 -1 -1
+        v  1  1 -1
+        v -1  1 -1
+        v -1 -1  1
+        v  1 -1  1
+        v  1  1  1
+        v -1  1  1
+        vn 0 0 1
+        f 1 4 3 2
+        f 5 6 7 8
+        f 1 2 6 5
+        f 2/1 3/2 7/3 6/4
+        f 3//1 4//1 8//1 7//1
+        f -8 -4 -1 -5
+    ";
+
+    fn cube() -> MeshShape {
+        let mesh = load::parse_obj(CUBE_OBJ).unwrap();
+        MeshShape::new(mesh.positions, mesh.triangles).unwrap()
+    }
+
+    /// The exact signed distance to the cube from -1 to 1.
+    fn cube_distance(p: Point3<f64>) -> f64 {
+        let d = p.to_vec().map(|c| c.abs() - 1.0);
+        d.map(|c| c.max(0.0)).magnitude() + d.x.max(d.y).max(d.z).min(0.0)
+    }
+
+    /// Returns the cube as binary STL with separate vertices per triangle.
+    fn cube_stl(mesh: &MeshShape) -> Vec<u8> {
+        let mut out = vec![0; 80];
+        out.extend_from_slice(&(mesh.triangles.len() as u32).to_le_bytes());
+        for t in &mesh.triangles {
+            out.extend_from_slice(&[0; 12]);
+            for &v in &t.vertices {
+                let p = mesh.positions[v as usize];
+                for &c in &[p.x, p.y, p.z] {
+                    out.extend_from_slice(&(c as f32).to_le_bytes());
+                }
+            }
+            out.extend_from_slice(&[0; 2]);
+        }
+
+        out
+    }
+
+    #[test]
+    fn exact_distance() {
+        let cube = cube();
+        assert_eq!(cube.positions.len(), 8);
+        assert_eq!(cube.triangles.len(), 12);
+
+        let points = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.5, -0.3, 0.9),
+            Point3::new(3.0, 0.2, -0.4),
+            Point3::new(2.0, 2.0, 0.0),
+            Point3::new(-1.5, 1.5, -2.5),
+            Point3::new(1.0, 0.3, 0.3),
+        ];
+        for &p in &points {
+            assert!((cube.min_distance_from_f64(p) - cube_distance(p)).abs() < 1e-12, "{:?}", p);
+        }
+
+        let gradient = cube.gradient_at(Point3::new(2.0, 2.0, 0.0), 0.0);
+        assert!((gradient - Vector3::new(1.0, 1.0, 0.0).normalize()).magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn stl_and_flipped() {
+        let cube = cube();
+        let binary = load::parse_stl(&cube_stl(&cube)).unwrap();
+        assert_eq!(binary.triangles.len(), 12);
+
+        // Flip all triangles: the shape has to be the same.
+        let flipped = binary.triangles.iter().map(|t| [t[0], t[2], t[1]]).collect();
+        let from_stl = MeshShape::new(binary.positions.clone(), flipped).unwrap();
+        assert_eq!(from_stl.positions.len(), 8);
+
+        let ascii = load::parse_stl(b"solid cube
+            facet normal 0 0 -1
+              outer loop
+                vertex -1 -1 -1
+                vertex -1 1 -1
+                vertex 1 1 -1
+              endloop
+            endfacet
+            endsolid cube
+        ").unwrap();
+        assert_eq!(ascii.positions, vec![
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(-1.0, 1.0, -1.0),
+            Point3::new(1.0, 1.0, -1.0),
+        ]);
+
+        for &p in &[Point3::new(0.2, 0.1, -0.3), Point3::new(1.4, -2.0, 0.5)] {
+            assert_eq!(from_stl.min_distance_from_f64(p), cube.min_distance_from_f64(p));
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(load::parse_obj("v 0 0 0\nf 1 2 3").is_err());
+        assert!(load::parse_obj("v 0 0\n").is_err());
+        assert!(load::parse_stl(b"not an stl file").is_err());
+        assert!(MeshShape::new(vec![Point3::origin(); 3], vec![[0, 1, 2]]).is_err());
+    }
+
+    #[test]
+    fn contract() {
+        check(&cube(), &CheckSettings::default()).assert_ok();
+
+        // A rough sphere has concave and convex edges.
+        let mut src = String::new();
+        let (rings, segments) = (12, 16);
+        src.push_str("v 0 0 1\nv 0 0 -1\n");
+        for r in 1..rings {
+            let theta = std::f64::consts::PI * r as f64 / rings as f64;
+            for s in 0..segments {
+                // Every other vertex is pushed in to make the surface bumpy.
+                let radius = if (r + s) % 2 == 0 { 1.0 } else { 0.8 };
+                let phi = 2.0 * std::f64::consts::PI * s as f64 / segments as f64;
+                src.push_str(&format!(
+                    "v {} {} {}\n",
+                    radius * theta.sin() * phi.cos(),
+                    radius * theta.sin() * phi.sin(),
+                    radius * theta.cos(),
+                ));
+            }
+        }
+
+        let vertex = |r: u32, s: u32| 3 + (r - 1) * segments + s % segments;
+        for s in 0..segments {
+            src.push_str(&format!("f 1 {} {}\n", vertex(1, s), vertex(1, s + 1)));
+            src.push_str(&format!(
+                "f 2 {} {}\n",
+                vertex(rings - 1, s + 1),
+                vertex(rings - 1, s),
+            ));
+            for r in 1..rings - 1 {
+                src.push_str(&format!(
+                    "f {} {} {} {}\n",
+                    vertex(r, s),
+                    vertex(r + 1, s),
+                    vertex(r + 1, s + 1),
+                    vertex(r, s + 1),
+                ));
+            }
+        }
+
+        let mesh = load::parse_obj(&src).unwrap();
+        let bumpy = MeshShape::new(mesh.positions, mesh.triangles).unwrap();
+        check(&bumpy, &CheckSettings::default()).assert_ok();
+    }
+}
@@ -0,0 +1,3 @@
+float {NAME}(vec3 point) {
+    return {INNER}(mix(point, abs(point), {MASK}));
+}
@@ -1,16 +1,49 @@
-use std::ops::Range;
-use cgmath::Point3;
+use std::{ops::Range, sync::Arc};
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::prelude::*;
+use self::glsl::{GlslBuilder, Ident};
 
 #[macro_use]
 mod util;
+mod color;
+mod csg;
+mod domain;
+mod dual;
+mod expr;
+pub mod glsl;
+mod julia;
+mod kifs;
+mod mandelbox;
 mod mandelbulb;
+mod mesh_shape;
+mod param;
+mod sampled;
 mod sphere;
+mod transformed;
+mod vec3;
+
+#[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+mod simd;
 
 #[cfg(test)]
 mod bench;
+#[cfg(test)]
+mod check;
 
-pub use self::mandelbulb::Mandelbulb;
+pub use self::color::ColorData;
+pub use self::csg::{Difference, Intersection, Union};
+pub use self::domain::{Bend, Mirror, Repeat, Twist};
+pub use self::expr::{parse_animated, ExprAnimation};
+pub use self::julia::QuaternionJulia;
+pub use self::kifs::KaleidoscopicIfs;
+pub use self::mandelbox::Mandelbox;
+pub use self::mandelbulb::{DynMandelbulb, Juliabulb, Mandelbulb};
+pub use self::mesh_shape::MeshShape;
+pub use self::param::{ParamInfo, ParamValue};
+pub use self::sampled::{Interpolation, SampledShape};
 pub use self::sphere::Sphere;
+pub use self::transformed::Transformed;
 
 /// Describes a 3D object that can be rendered by this application.
 ///
@@ -36,18 +69,63 @@ pub trait Shape: Sync + Send + 'static {
     /// lies on the shape's surface.
     fn min_distance_from(&self, p: Point3<f32>) -> f32;
 
-    fn bounding_box(&self) -> Range<Point3<f32>>;
+    /// Like `min_distance_from()`, but evaluated with double precision. This
+    /// is used to generate the mesh when zooming in very deep, where `f32`
+    /// can't distinguish neighboring grid points anymore.
+    ///
+    /// The default implementation simply falls back to the `f32` version, so
+    /// shapes should override this if they want to support deep zooms.
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.min_distance_from(p.cast().unwrap()).into()
+    }
+
+    /// Like `min_distance_from_f64()`, but additionally returns data which
+    /// can be used to color the surface at `p`, or `None` if the shape
+    /// doesn't provide such data.
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        (self.min_distance_from_f64(p), None)
+    }
 
-    // TODO: this method is hacky...
-    /// Returns a string containing the GLSL definition of the distance
-    /// estimator.
+    /// Returns the gradient of the distance estimator at `p`. Close to the
+    /// surface, it points away from the shape, so it can be used as surface
+    /// normal (see `normal_at()`).
     ///
-    /// The GLSL function needs to have this signature:
+    /// The default implementation uses central differences with the step size
+    /// `delta`, which costs six calls to `min_distance_from_f64()`. Shapes
+    /// should override this if they can calculate the gradient analytically
+    /// (in which case `delta` is ignored).
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        let d = |offset: Vector3<f64>| {
+            self.min_distance_from_f64(p + offset) - self.min_distance_from_f64(p - offset)
+        };
+
+        Vector3::new(
+            d(Vector3::unit_x() * delta),
+            d(Vector3::unit_y() * delta),
+            d(Vector3::unit_z() * delta),
+        ) / (2.0 * delta)
+    }
+
+    /// Returns the normalized surface normal at `p`, which should be close to
+    /// the surface. See `gradient_at()` for the meaning of `delta`.
+    fn normal_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        self.gradient_at(p, delta).normalize()
+    }
+
+    /// Returns an axis aligned box containing the whole surface. The box may
+    /// be infinite along some axes (e.g. for infinitely repeated shapes); the
+    /// mesh is then only generated in `MeshSettings::region`.
+    fn bounding_box(&self) -> Range<Point3<f32>>;
+
+    /// Emits the GLSL definition of the distance estimator into `glsl` and
+    /// returns the name of the emitted function. It has this signature:
     ///
     /// ```
-    /// float shape_de(vec3 point)
+    /// float NAME(vec3 point)
     /// ```
-    fn de_shader(&self) -> String;
+    ///
+    /// Use `glsl::de_shader` to get the complete shader code of a shape.
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident;
 
     /// Returns an upper bound of the distance from `p` to the closest surface
     /// point of the shape, or `None` if no such estimate can be made.
@@ -79,6 +157,33 @@ pub trait Shape: Sync + Send + 'static {
         self.min_distance_from(p) < 0.0
     }
 
+    /// Describes all parameters of this shape that can be inspected and
+    /// changed via `param()` and `set_param()`. This allows building
+    /// parameter editors and the like without knowing the concrete shape.
+    fn params(&self) -> Vec<ParamInfo> {
+        Vec::new()
+    }
+
+    /// Returns the current value of the parameter `name` or `None` if there
+    /// is no such parameter.
+    fn param(&self, _name: &str) -> Option<ParamValue> {
+        None
+    }
+
+    /// Sets the parameter `name` to `value`. Fails if there is no such
+    /// parameter or if the value is invalid for it (see `ParamInfo::check`).
+    fn set_param(&mut self, name: &str, _value: ParamValue) -> Result<()> {
+        bail!("shape has no parameter '{}'", name)
+    }
+
+    /// If the parameter `name` can't be changed in this shape because it's
+    /// fixed at compile time (like the power of `Mandelbulb<8>`), this
+    /// returns an equivalent shape in which it can be changed. Returns `None`
+    /// otherwise.
+    fn unfix_param(&self, _name: &str) -> Option<Arc<dyn Shape>> {
+        None
+    }
+
     /// Calls `min_distance_from()` for each given point and returns the
     /// results as vector. This is for use through a trait object to reduce
     /// the virtual call overhead.
@@ -88,6 +193,14 @@ pub trait Shape: Sync + Send + 'static {
     /// improve performance by writing a custom implementation.
     fn batch_min_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32>;
 
+    /// Returns `true` if `batch_min_distance_from()` is a lot faster than
+    /// calling `min_distance_from()` for each point (e.g. because it uses
+    /// SIMD). Only then the mesh is sampled with `f32` precision where that
+    /// is sufficient; otherwise `min_distance_from_f64()` is used.
+    fn has_fast_batch(&self) -> bool {
+        false
+    }
+
     /// Calls `max_distance_from()` for each given point and returns the
     /// results as vector. See `batch_min_distance_from()` for more
     /// information.
@@ -105,6 +218,114 @@ pub trait Shape: Sync + Send + 'static {
 }
 
 
+/// Shapes behind an `Arc` are shapes, too. This allows using shape trait
+/// objects with generic wrappers like `Transformed`.
+impl<S: Shape + ?Sized> Shape for Arc<S> {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        (**self).min_distance_from(p)
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        (**self).min_distance_from_f64(p)
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        (**self).colored_distance_from(p)
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        (**self).gradient_at(p, delta)
+    }
+
+    fn normal_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        (**self).normal_at(p, delta)
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        (**self).bounding_box()
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        (**self).build_de_shader(glsl)
+    }
+
+    fn max_distance_from(&self, p: Point3<f32>) -> Option<f32> {
+        (**self).max_distance_from(p)
+    }
+
+    fn bounded_distance_from(&self, p: Point3<f32>) -> (f32, Option<f32>) {
+        (**self).bounded_distance_from(p)
+    }
+
+    fn contains(&self, p: Point3<f32>) -> bool {
+        (**self).contains(p)
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        (**self).params()
+    }
+
+    fn param(&self, name: &str) -> Option<ParamValue> {
+        (**self).param(name)
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        Arc::get_mut(self)
+            .ok_or_else(|| anyhow!("can't change parameters of a shared shape"))?
+            .set_param(name, value)
+    }
+
+    fn unfix_param(&self, name: &str) -> Option<Arc<dyn Shape>> {
+        (**self).unfix_param(name)
+    }
+
+    fn batch_min_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32> {
+        (**self).batch_min_distance_from(points)
+    }
+
+    fn has_fast_batch(&self) -> bool {
+        (**self).has_fast_batch()
+    }
+
+    fn batch_max_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32> {
+        (**self).batch_max_distance_from(points)
+    }
+
+    fn batch_bounded_distance_from(&self, points: &[Point3<f32>]) -> Vec<(f32, f32)> {
+        (**self).batch_bounded_distance_from(points)
+    }
+}
+
+/// A shape which changes over time, e.g. a mandelbulb with an oscillating
+/// power.
+///
+/// Shapes are shared with the threads generating the mesh, so they can't be
+/// changed in place. Instead, a new shape is created for each point in time.
+/// Most shapes emit their values as constants in `build_de_shader`, so the
+/// DE shader has to be generated again for each point in time as well. Only
+/// some values are uniforms (e.g. the power of `DynMandelbulb`).
+pub trait AnimatedShape: Sync + Send + 'static {
+    /// Returns the shape at time `t` (in seconds since the start of the
+    /// animation). This is called once per frame, so expensive work should
+    /// be done up front.
+    fn at(&self, t: f32) -> Result<Arc<dyn Shape>>;
+
+    /// Returns `false` if `at` returns the same shape for all `t`, so there
+    /// is no need to call it repeatedly.
+    fn is_animated(&self) -> bool;
+}
+
+/// Normal shapes are animations which don't change.
+impl AnimatedShape for Arc<dyn Shape> {
+    fn at(&self, _t: f32) -> Result<Arc<dyn Shape>> {
+        Ok(self.clone())
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+}
+
 // Some points close to the surface of the mandelbulb which are used for
 // benchmarking.
 #[cfg(test)]
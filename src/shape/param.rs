@@ -0,0 +1,223 @@
+use std::{fmt, ops::RangeInclusive};
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+
+/// Describes one tunable parameter of a shape. See `Shape::params`.
+#[derive(Debug, Clone)]
+pub struct ParamInfo {
+    /// The name which is used to get and set this parameter.
+    pub name: &'static str,
+
+    pub ty: ParamType,
+
+    /// All valid values of this parameter. For vectors, this applies to each
+    /// component. Unbounded parameters use `f32::MIN` or `f32::MAX`.
+    pub range: RangeInclusive<f32>,
+}
+
+impl ParamInfo {
+    /// Makes sure `value` has the type of this parameter and lies in its
+    /// range.
+    pub fn check(&self, value: ParamValue) -> Result<()> {
+        if value.ty() != self.ty {
+            bail!(
+                "parameter '{}' has type {}, but a value of type {} was given",
+                self.name,
+                self.ty,
+                value.ty(),
+            );
+        }
+
+        let in_range = |v: f32| self.range.contains(&v);
+        let valid = match value {
+            ParamValue::Float(v) => in_range(v),
+            ParamValue::Integer(v) => in_range(v as f32),
+            ParamValue::Vector(v) => in_range(v.x) && in_range(v.y) && in_range(v.z),
+        };
+
+        if !valid {
+            bail!(
+                "value {} of parameter '{}' is not in the valid range {:?}",
+                value,
+                self.name,
+                self.range,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Looks up the parameter `name` in `params`, errors if it doesn't exist.
+pub(crate) fn find<'a>(params: &'a [ParamInfo], name: &str) -> Result<&'a ParamInfo> {
+    params.iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| anyhow!("shape has no parameter '{}'", name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Float,
+    Integer,
+    Vector,
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamType::Float => "float",
+            ParamType::Integer => "integer",
+            ParamType::Vector => "vector",
+        }.fmt(f)
+    }
+}
+
+/// The value of a shape parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ParamValue {
+    Float(f32),
+    Integer(u64),
+    Vector(Vector3<f32>),
+}
+
+impl ParamValue {
+    pub fn ty(&self) -> ParamType {
+        match self {
+            ParamValue::Float(_) => ParamType::Float,
+            ParamValue::Integer(_) => ParamType::Integer,
+            ParamValue::Vector(_) => ParamType::Vector,
+        }
+    }
+
+    /// Parses a value of type `ty` from a string. Vectors are written as
+    /// three comma separated numbers, e.g. `1,0,-2.5`.
+    pub fn parse(ty: ParamType, s: &str) -> Result<Self> {
+        let float = |s: &str| {
+            s.trim().parse::<f32>().with_context(|| format!("invalid number '{}'", s))
+        };
+
+        let value = match ty {
+            ParamType::Float => ParamValue::Float(float(s)?),
+            ParamType::Integer => {
+                let v = s.trim().parse().with_context(|| format!("invalid integer '{}'", s))?;
+                ParamValue::Integer(v)
+            }
+            ParamType::Vector => {
+                let components = s.split(',').map(float).collect::<Result<Vec<_>>>()?;
+                match *components {
+                    [x, y, z] => ParamValue::Vector(Vector3::new(x, y, z)),
+                    _ => bail!("expected three comma separated numbers, found '{}'", s),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Returns the float value. Panics if this isn't a float, so the value
+    /// should have been checked with `ParamInfo::check` before.
+    pub fn as_float(&self) -> f32 {
+        match *self {
+            ParamValue::Float(v) => v,
+            other => panic!("expected float parameter value, found {}", other),
+        }
+    }
+
+    /// Like `as_float`, but for integers.
+    pub fn as_integer(&self) -> u64 {
+        match *self {
+            ParamValue::Integer(v) => v,
+            other => panic!("expected integer parameter value, found {}", other),
+        }
+    }
+
+    /// Like `as_float`, but for vectors.
+    pub fn as_vector(&self) -> Vector3<f32> {
+        match *self {
+            ParamValue::Vector(v) => v,
+            other => panic!("expected vector parameter value, found {}", other),
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamValue::Float(v) => v.fmt(f),
+            ParamValue::Integer(v) => v.fmt(f),
+            ParamValue::Vector(v) => write!(f, "({}, {}, {})", v.x, v.y, v.z),
+        }
+    }
+}
+
+impl From<f32> for ParamValue {
+    fn from(v: f32) -> Self {
+        ParamValue::Float(v)
+    }
+}
+
+impl From<u64> for ParamValue {
+    fn from(v: u64) -> Self {
+        ParamValue::Integer(v)
+    }
+}
+
+impl From<Vector3<f32>> for ParamValue {
+    fn from(v: Vector3<f32>) -> Self {
+        ParamValue::Vector(v)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::{ParamInfo, ParamType, ParamValue};
+
+    #[test]
+    fn parse() {
+        let parse = |ty, s| ParamValue::parse(ty, s).ok();
+
+        assert_eq!(parse(ParamType::Float, "2.5"), Some(ParamValue::Float(2.5)));
+        assert_eq!(parse(ParamType::Float, " -3 "), Some(ParamValue::Float(-3.0)));
+        assert_eq!(parse(ParamType::Integer, "12"), Some(ParamValue::Integer(12)));
+        assert_eq!(
+            parse(ParamType::Vector, "1, 0,-2.5"),
+            Some(ParamValue::Vector(Vector3::new(1.0, 0.0, -2.5))),
+        );
+
+        assert_eq!(parse(ParamType::Float, ""), None);
+        assert_eq!(parse(ParamType::Float, "1,2,3"), None);
+        assert_eq!(parse(ParamType::Integer, "2.5"), None);
+        assert_eq!(parse(ParamType::Integer, "-1"), None);
+        assert_eq!(parse(ParamType::Vector, "1,2"), None);
+        assert_eq!(parse(ParamType::Vector, "1,2,3,4"), None);
+        assert_eq!(parse(ParamType::Vector, "1,x,3"), None);
+    }
+
+    #[test]
+    fn check() {
+        let info = |ty, range| ParamInfo { name: "x", ty, range };
+
+        let float = info(ParamType::Float, 1.0..=4.0);
+        assert!(float.check(ParamValue::Float(1.0)).is_ok());
+        assert!(float.check(ParamValue::Float(4.0)).is_ok());
+        assert!(float.check(ParamValue::Float(0.5)).is_err());
+        assert!(float.check(ParamValue::Float(f32::NAN)).is_err());
+        assert!(float.check(ParamValue::Integer(2)).is_err());
+
+        let integer = info(ParamType::Integer, 1.0..=f32::MAX);
+        assert!(integer.check(ParamValue::Integer(1)).is_ok());
+        assert!(integer.check(ParamValue::Integer(0)).is_err());
+        assert!(integer.check(ParamValue::Float(2.0)).is_err());
+
+        let vector = info(ParamType::Vector, -1.0..=1.0);
+        assert!(vector.check(ParamValue::Vector(Vector3::new(-1.0, 0.0, 1.0))).is_ok());
+        assert!(vector.check(ParamValue::Vector(Vector3::new(0.0, 0.0, 2.0))).is_err());
+        assert!(vector.check(ParamValue::Float(0.0)).is_err());
+    }
+}
@@ -0,0 +1,14 @@
+float {NAME}(vec3 point) {
+    // Unlike on the CPU, we only check the closest copy and its neighbors
+    // towards `point`. That's exact as long as the inner shape fits into one
+    // cell.
+    vec3 base = clamp(round(point / {PERIOD} - {SHIFT}) + {SHIFT}, -{LIMIT}, {LIMIT});
+    vec3 towards = sign(point - base * {PERIOD});
+    float d = {INNER}(point - base * {PERIOD});
+    for (int i = 1; i < 8; i++) {
+        vec3 offset = vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        vec3 cell = clamp(base + towards * offset, -{LIMIT}, {LIMIT});
+        d = min(d, {INNER}(point - cell * {PERIOD}));
+    }
+    return d;
+}
@@ -0,0 +1,408 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+};
+use cgmath::{prelude::*, Point3, Vector3};
+
+use crate::{
+    prelude::*,
+    util::grid::GridTable,
+};
+use super::{
+    Shape,
+    glsl::{GlslBuilder, Ident},
+};
+
+
+/// The first bytes of a file written by `SampledShape::save`, including the
+/// version of the format.
+const MAGIC: &[u8; 8] = b"CNTSDF02";
+
+/// Number of additional samples outside the bounding box on each side. Two
+/// are needed for the tricubic interpolation to use real values near the
+/// bounding box.
+const MARGIN_CELLS: u32 = 2;
+
+/// A shape defined by distance values sampled on a regular grid. Between the
+/// samples, the distance is interpolated.
+///
+/// This is useful to cache shapes which are expensive to evaluate (e.g.
+/// fractals with a high number of iterations) and to load volumes created by
+/// other programs. Of course, details smaller than the grid cells are lost
+/// and the distance is only approximately a lower bound.
+///
+/// Files written by `save` have this format (all values little endian):
+///
+/// - `MAGIC`
+/// - the number of samples per axis `size` (`u32`)
+/// - the position of the first sample (3 `f64`)
+/// - the size of a grid cell (3 `f64`)
+/// - the key (`u64`, see `with_key`)
+/// - `size³` distances (`f32`), ordered by `x`, then `y`, then `z`
+pub struct SampledShape {
+    grid: GridTable<f32>,
+    origin: Point3<f64>,
+    cell: Vector3<f64>,
+    interpolation: Interpolation,
+    key: u64,
+}
+
+/// How to calculate the distance between grid points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Linear interpolation of the 8 surrounding samples. Fast, but the
+    /// gradient is not continuous, so the edges of the grid cells are
+    /// visible in the shading.
+    Trilinear,
+
+    /// Catmull-Rom spline interpolation of the 64 surrounding samples.
+    Tricubic,
+}
+
+impl SampledShape {
+    /// Samples the distance field of `shape` with `resolution` samples along
+    /// each axis. The grid covers the shape's bounding box and a few cells
+    /// more.
+    pub fn sample(shape: &dyn Shape, resolution: u32, interpolation: Interpolation) -> Self {
+        assert!(resolution > 2 * MARGIN_CELLS + 1, "resolution {} too small", resolution);
+
+        let bb = shape.bounding_box();
+        let bb = bb.start.cast::<f64>().unwrap()..bb.end.cast::<f64>().unwrap();
+        let cell = (bb.end - bb.start) / (resolution - 1 - 2 * MARGIN_CELLS) as f64;
+        let origin = bb.start - cell * MARGIN_CELLS as f64;
+
+        let mut row = Vec::with_capacity(resolution as usize);
+        let grid = GridTable::fill_rows_with(resolution, |x, y, out| {
+            row.clear();
+            row.extend((0..resolution).map(|z| {
+                let offset = Vector3::new(x, y, z).cast::<f64>().unwrap().mul_element_wise(cell);
+                (origin + offset).cast::<f32>().unwrap()
+            }));
+            out.extend(shape.batch_min_distance_from(&row));
+        });
+
+        Self::new(grid, origin, cell, interpolation)
+    }
+
+    /// Creates the shape from existing samples. The sample `(x, y, z)` is at
+    /// `origin + (x, y, z) * cell`. The surface has to be inside the grid.
+    pub fn new(
+        grid: GridTable<f32>,
+        origin: Point3<f64>,
+        cell: Vector3<f64>,
+        interpolation: Interpolation,
+    ) -> Self {
+        Self { grid, origin, cell, interpolation, key: 0 }
+    }
+
+    /// Sets a value which identifies what was sampled (e.g. a hash of the
+    /// shape expression). It is stored in the file, so that users of `load`
+    /// can check whether the samples are still what they expect. The
+    /// default is 0.
+    pub fn with_key(self, key: u64) -> Self {
+        Self { key, ..self }
+    }
+
+    /// Returns the value set by `with_key`.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Returns the number of samples along each axis.
+    pub fn resolution(&self) -> u32 {
+        self.grid.size()
+    }
+
+    /// Loads samples from a file written by `save`.
+    pub fn load(path: impl AsRef<Path>, interpolation: Interpolation) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("failed to open '{}'", path.display()))?;
+        Self::read(&mut BufReader::new(file), interpolation)
+            .with_context(|| format!("failed to load sampled shape '{}'", path.display()))
+    }
+
+    /// Writes all samples to the given file. See the type documentation for
+    /// the format.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+        self.write(&mut BufWriter::new(file))
+            .with_context(|| format!("failed to write '{}'", path.display()))
+    }
+
+    fn read(r: &mut impl Read, interpolation: Interpolation) -> Result<Self> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a sampled shape file (or unsupported version)");
+        }
+
+        let mut size = [0; 4];
+        r.read_exact(&mut size)?;
+        let size = u32::from_le_bytes(size);
+        if size < 2 {
+            bail!("invalid number of samples per axis: {}", size);
+        }
+
+        let mut buf = [0; 8];
+        let mut read_f64 = || -> Result<f64> {
+            r.read_exact(&mut buf)?;
+            Ok(f64::from_le_bytes(buf))
+        };
+        let origin = Point3::new(read_f64()?, read_f64()?, read_f64()?);
+        let cell = Vector3::new(read_f64()?, read_f64()?, read_f64()?);
+        if !(origin.x.is_finite() && origin.y.is_finite() && origin.z.is_finite()) {
+            bail!("invalid grid origin {:?}", origin);
+        }
+        if !(0..3).all(|axis| cell[axis] > 0.0 && cell[axis].is_finite()) {
+            bail!("invalid grid cell size {:?}", cell);
+        }
+
+        let mut key = [0; 8];
+        r.read_exact(&mut key)?;
+        let key = u64::from_le_bytes(key);
+
+        // We don't trust `size` to allocate the buffer up front: a corrupt
+        // file could make us request huge amounts of memory. So we read
+        // whatever is there (but not more than expected) and compare.
+        let len = u64::from(size).checked_pow(3)
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| anyhow!("invalid number of samples per axis: {}", size))?;
+        let mut bytes = Vec::new();
+        r.take(len + 1).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            bail!("expected {}³ samples, but the file has the wrong size", size);
+        }
+        let data = bytes.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        let grid = GridTable::from_data(size, data);
+        Ok(Self::new(grid, origin, cell, interpolation).with_key(key))
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&self.grid.size().to_le_bytes())?;
+        for &v in &[self.origin.x, self.origin.y, self.origin.z] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        for &v in &[self.cell.x, self.cell.y, self.cell.z] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.write_all(&self.key.to_le_bytes())?;
+        for &d in self.grid.data() {
+            w.write_all(&d.to_le_bytes())?;
+        }
+        w.flush()?;
+
+        Ok(())
+    }
+
+    /// Interpolates the samples at `u`, given in grid coordinates (the
+    /// sample `(x, y, z)` is at `u = (x, y, z)`). `u` has to be inside of
+    /// the grid.
+    fn interpolate(&self, u: Vector3<f64>) -> f64 {
+        let last = self.grid.size() - 1;
+
+        // The cell containing `u` and the position of `u` within it.
+        let cell = |c: f64| (c.floor() as u32).min(last - 1);
+        let (x, y, z) = (cell(u.x), cell(u.y), cell(u.z));
+        let t = u - Vector3::new(x, y, z).cast::<f64>().unwrap();
+
+        let sample = |dx: i32, dy: i32, dz: i32| {
+            let i = |base: u32, d: i32| (base as i32 + d).max(0).min(last as i32) as u32;
+            f64::from(self.grid[(i(x, dx), i(y, dy), i(z, dz))])
+        };
+
+        match self.interpolation {
+            Interpolation::Trilinear => {
+                let mut sum = 0.0;
+                for &(dx, dy, dz) in &CORNERS {
+                    let w = |d: i32, t: f64| if d == 0 { 1.0 - t } else { t };
+                    sum += w(dx, t.x) * w(dy, t.y) * w(dz, t.z) * sample(dx, dy, dz);
+                }
+                sum
+            }
+            Interpolation::Tricubic => {
+                let (wx, wy, wz) = (catmull_rom(t.x), catmull_rom(t.y), catmull_rom(t.z));
+                let mut sum = 0.0;
+                for dx in -1..=2 {
+                    for dy in -1..=2 {
+                        for dz in -1..=2 {
+                            let w = wx[(dx + 1) as usize] * wy[(dy + 1) as usize]
+                                * wz[(dz + 1) as usize];
+                            sum += w * sample(dx, dy, dz);
+                        }
+                    }
+                }
+                sum
+            }
+        }
+    }
+}
+
+const CORNERS: [(i32, i32, i32); 8] = [
+    (0, 0, 0), (0, 0, 1), (0, 1, 0), (0, 1, 1),
+    (1, 0, 0), (1, 0, 1), (1, 1, 0), (1, 1, 1),
+];
+
+/// Returns the weights of the four samples at -1, 0, 1 and 2 for a
+/// Catmull-Rom spline evaluated at `t` (between sample 0 and 1).
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+impl Shape for SampledShape {
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.min_distance_from_f64(p.cast().unwrap()) as f32
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        let last = (self.grid.size() - 1) as f64;
+        let u = (p - self.origin).div_element_wise(self.cell);
+        let clamped = u.map(|c| c.max(0.0).min(last));
+        let d = self.interpolate(clamped);
+
+        // Outside of the grid we only know that the surface is inside. Let
+        // `q` be the closest point on the grid's boundary. For every point
+        // `s` in the grid, the angle `p q s` is at least 90°, so the
+        // distance from `p` to the surface is at least `sqrt(|pq|² + d(q)²)`.
+        let outside = (u - clamped).mul_element_wise(self.cell).magnitude2();
+        if outside == 0.0 {
+            d
+        } else {
+            (outside + d.max(0.0).powi(2)).sqrt()
+        }
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        let last = (self.grid.size() - 1) as f64;
+        let end = self.origin + self.cell * last;
+        self.origin.cast().unwrap()..end.cast().unwrap()
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        // The samples are not available on the GPU, so the ray marcher only
+        // sees the bounding box.
+        let bb = self.bounding_box();
+        let (min, max) = (bb.start.to_vec(), bb.end.to_vec());
+        glsl.function("sampled", include_str!("bounding_box.frag"), &[
+            ("CENTER", &((min + max) / 2.0)),
+            ("HALF_SIZE", &((max - min) / 2.0)),
+        ])
+    }
+
+    impl_batch_methods!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use crate::shape::{Shape, Sphere};
+    use super::{Interpolation, SampledShape};
+
+    const POINTS: [[f64; 3]; 6] = [
+        [0.2, -0.1, 0.3],
+        [0.31, -0.2, 0.57],
+        [0.85, 0.1, -0.05],
+        [-0.4, 0.45, 0.6],
+        [0.7, 0.7, 0.7],
+        [0.1, -0.78, 0.05],
+    ];
+
+    #[test]
+    fn approximates_shape() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.8);
+        for &(interpolation, max_error) in &[
+            (Interpolation::Trilinear, 2e-3),
+            (Interpolation::Tricubic, 2e-4),
+        ] {
+            let sampled = SampledShape::sample(&sphere, 40, interpolation);
+            for &p in &POINTS {
+                let p = Point3::from(p);
+                let error = sampled.min_distance_from_f64(p) - sphere.min_distance_from_f64(p);
+                assert!(error.abs() < max_error, "{:?} at {:?}: {}", interpolation, p, error);
+            }
+        }
+
+        // Outside of the grid, the distance is a lower bound.
+        let sampled = SampledShape::sample(&sphere, 20, Interpolation::Tricubic);
+        for &p in &[Point3::new(3.0, -2.0, 1.0), Point3::new(1.2, 0.1, 0.0)] {
+            let d = sampled.min_distance_from_f64(p);
+            assert!(d > 0.0 && d <= sphere.min_distance_from_f64(p), "{:?}: {}", p, d);
+        }
+
+        // Sampled values are reproduced exactly.
+        let sampled = SampledShape::sample(&sphere, 16, Interpolation::Tricubic);
+        let p = sampled.origin + sampled.cell * 5.0;
+        let expected = sphere.min_distance_from(p.cast().unwrap());
+        assert_eq!(sampled.min_distance_from_f64(p), f64::from(expected));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let sphere = Sphere::new(Point3::new(0.1, 0.2, -0.3), 0.5);
+        let sampled = SampledShape::sample(&sphere, 12, Interpolation::Trilinear)
+            .with_key(0x0123_4567_89ab_cdef);
+
+        let mut bytes = Vec::new();
+        sampled.write(&mut bytes).unwrap();
+        let loaded = SampledShape::read(&mut &bytes[..], Interpolation::Trilinear).unwrap();
+        assert_eq!(loaded.grid.data(), sampled.grid.data());
+        assert_eq!(loaded.key(), 0x0123_4567_89ab_cdef);
+        assert_eq!(loaded.resolution(), 12);
+        assert_eq!(loaded.bounding_box(), sampled.bounding_box());
+        for &p in &POINTS {
+            let p = Point3::from(p);
+            assert_eq!(loaded.min_distance_from_f64(p), sampled.min_distance_from_f64(p));
+        }
+
+        assert!(SampledShape::read(&mut &bytes[..100], Interpolation::Trilinear).is_err());
+        assert!(SampledShape::read(&mut &b"not a file"[..], Interpolation::Trilinear).is_err());
+
+        // Trailing data is an error, too.
+        let mut longer = bytes.clone();
+        longer.extend(&[0; 4]);
+        assert!(SampledShape::read(&mut &longer[..], Interpolation::Trilinear).is_err());
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.5);
+        let mut bytes = Vec::new();
+        SampledShape::sample(&sphere, 8, Interpolation::Trilinear).write(&mut bytes).unwrap();
+
+        // The header is the magic, the size (at 8) and origin and cell (at
+        // 12 and 36).
+        let patched = |offset: usize, value: &[u8]| {
+            let mut out = bytes.clone();
+            out[offset..offset + value.len()].copy_from_slice(value);
+            SampledShape::read(&mut &out[..], Interpolation::Trilinear)
+        };
+
+        // Huge sizes must fail without allocating memory for the samples.
+        assert!(patched(8, &4000u32.to_le_bytes()).is_err());
+        assert!(patched(8, &u32::MAX.to_le_bytes()).is_err());
+        assert!(patched(8, &1u32.to_le_bytes()).is_err());
+
+        assert!(patched(12, &f64::NAN.to_le_bytes()).is_err());
+        for &cell in &[0.0, -0.1, f64::NAN, f64::INFINITY] {
+            assert!(patched(36, &cell.to_le_bytes()).is_err());
+            assert!(patched(52, &cell.to_le_bytes()).is_err());
+        }
+    }
+}
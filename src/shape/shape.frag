@@ -1,3 +1,3 @@
-float shape_de(vec3 point) {
-    return length(vec3({X}, {Y}, {Z}) - point) - {RADIUS};
+float {NAME}(vec3 point) {
+    return length({CENTER} - point) - {RADIUS};
 }
@@ -0,0 +1,187 @@
+use std::{
+    arch::x86_64::*,
+    ops::{Add, Div, Mul, Sub},
+};
+
+
+/// Multiple `f32` values in one SIMD register. This allows writing kernels
+/// which evaluate several points at once generically for SSE and AVX.
+///
+/// Values of these types can only be created by the unsafe `splat` and
+/// `load`, which require the CPU feature of the respective instruction set.
+/// Generic kernels should be `#[inline(always)]` and only be called from a
+/// function with the matching `#[target_feature]`; otherwise the intrinsics
+/// won't be inlined.
+///
+/// Masks are represented by the same type: all bits of a lane are set for
+/// `true` and cleared for `false`.
+pub(crate) trait F32Lanes:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+{
+    const LANES: usize;
+
+    /// Sets all lanes to `v`.
+    unsafe fn splat(v: f32) -> Self;
+
+    /// Loads the first `LANES` values of `src`.
+    unsafe fn load(src: &[f32]) -> Self;
+
+    /// Stores all lanes into the first `LANES` values of `dst`.
+    fn store(self, dst: &mut [f32]);
+
+    fn sqrt(self) -> Self;
+
+    /// Mask of all lanes which are *not* greater than `other`. Unlike `<=`,
+    /// this is `true` if one of the values is NaN.
+    fn not_greater(self, other: Self) -> Self;
+
+    /// Mask of all lanes which are equal to `other`.
+    fn equal(self, other: Self) -> Self;
+
+    /// Bitwise and, i.e. the intersection of two masks.
+    fn and(self, other: Self) -> Self;
+
+    /// Takes the lanes of `a` where `self` (a mask) is set, and the lanes of
+    /// `b` otherwise.
+    fn select(self, a: Self, b: Self) -> Self;
+
+    /// Returns `true` if any lane of the mask `self` is set.
+    fn any(self) -> bool;
+}
+
+/// Four lanes in a SSE register. Requires SSE4.1.
+#[derive(Clone, Copy)]
+pub(crate) struct Sse(__m128);
+
+/// Eight lanes in an AVX register. Requires AVX.
+#[derive(Clone, Copy)]
+pub(crate) struct Avx(__m256);
+
+macro_rules! impl_op {
+    ($ty:ident, $trait:ident, $method:ident, $intrinsic:ident) => {
+        impl $trait for $ty {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $method(self, rhs: Self) -> Self {
+                unsafe { $ty($intrinsic(self.0, rhs.0)) }
+            }
+        }
+    }
+}
+
+impl_op!(Sse, Add, add, _mm_add_ps);
+impl_op!(Sse, Sub, sub, _mm_sub_ps);
+impl_op!(Sse, Mul, mul, _mm_mul_ps);
+impl_op!(Sse, Div, div, _mm_div_ps);
+
+impl_op!(Avx, Add, add, _mm256_add_ps);
+impl_op!(Avx, Sub, sub, _mm256_sub_ps);
+impl_op!(Avx, Mul, mul, _mm256_mul_ps);
+impl_op!(Avx, Div, div, _mm256_div_ps);
+
+impl F32Lanes for Sse {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    unsafe fn splat(v: f32) -> Self {
+        Sse(_mm_set1_ps(v))
+    }
+
+    #[inline(always)]
+    unsafe fn load(src: &[f32]) -> Self {
+        assert!(src.len() >= Self::LANES);
+        Sse(_mm_loadu_ps(src.as_ptr()))
+    }
+
+    #[inline(always)]
+    fn store(self, dst: &mut [f32]) {
+        assert!(dst.len() >= Self::LANES);
+        unsafe { _mm_storeu_ps(dst.as_mut_ptr(), self.0) }
+    }
+
+    #[inline(always)]
+    fn sqrt(self) -> Self {
+        unsafe { Sse(_mm_sqrt_ps(self.0)) }
+    }
+
+    #[inline(always)]
+    fn not_greater(self, other: Self) -> Self {
+        unsafe { Sse(_mm_cmpngt_ps(self.0, other.0)) }
+    }
+
+    #[inline(always)]
+    fn equal(self, other: Self) -> Self {
+        unsafe { Sse(_mm_cmpeq_ps(self.0, other.0)) }
+    }
+
+    #[inline(always)]
+    fn and(self, other: Self) -> Self {
+        unsafe { Sse(_mm_and_ps(self.0, other.0)) }
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        unsafe { Sse(_mm_blendv_ps(b.0, a.0, self.0)) }
+    }
+
+    #[inline(always)]
+    fn any(self) -> bool {
+        unsafe { _mm_movemask_ps(self.0) != 0 }
+    }
+}
+
+impl F32Lanes for Avx {
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    unsafe fn splat(v: f32) -> Self {
+        Avx(_mm256_set1_ps(v))
+    }
+
+    #[inline(always)]
+    unsafe fn load(src: &[f32]) -> Self {
+        assert!(src.len() >= Self::LANES);
+        Avx(_mm256_loadu_ps(src.as_ptr()))
+    }
+
+    #[inline(always)]
+    fn store(self, dst: &mut [f32]) {
+        assert!(dst.len() >= Self::LANES);
+        unsafe { _mm256_storeu_ps(dst.as_mut_ptr(), self.0) }
+    }
+
+    #[inline(always)]
+    fn sqrt(self) -> Self {
+        unsafe { Avx(_mm256_sqrt_ps(self.0)) }
+    }
+
+    #[inline(always)]
+    fn not_greater(self, other: Self) -> Self {
+        unsafe { Avx(_mm256_cmp_ps(self.0, other.0, _CMP_NGT_UQ)) }
+    }
+
+    #[inline(always)]
+    fn equal(self, other: Self) -> Self {
+        unsafe { Avx(_mm256_cmp_ps(self.0, other.0, _CMP_EQ_OQ)) }
+    }
+
+    #[inline(always)]
+    fn and(self, other: Self) -> Self {
+        unsafe { Avx(_mm256_and_ps(self.0, other.0)) }
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        unsafe { Avx(_mm256_blendv_ps(b.0, a.0, self.0)) }
+    }
+
+    #[inline(always)]
+    fn any(self) -> bool {
+        unsafe { _mm256_movemask_ps(self.0) != 0 }
+    }
+}
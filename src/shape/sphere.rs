@@ -1,7 +1,12 @@
 use std::ops::Range;
 use cgmath::{prelude::*, Point3, Vector3};
 
-use super::Shape;
+use crate::prelude::*;
+use super::{
+    Shape,
+    glsl::{GlslBuilder, Ident},
+    param::{self, ParamInfo, ParamType, ParamValue},
+};
 
 #[derive(Clone)]
 pub struct Sphere {
@@ -10,7 +15,6 @@ pub struct Sphere {
 }
 
 impl Sphere {
-    #[allow(dead_code)]  // TODO
     pub fn new(center: Point3<f32>, radius: f32) -> Self {
         Sphere {
             center,
@@ -34,18 +38,58 @@ impl Shape for Sphere {
         (self.center - p).magnitude() - self.radius
     }
 
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        let center = self.center.cast::<f64>().unwrap();
+        (center - p).magnitude() - f64::from(self.radius)
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, _delta: f64) -> Vector3<f64> {
+        (p - self.center.cast::<f64>().unwrap()).normalize()
+    }
+
     fn max_distance_from(&self, p: Point3<f32>) -> Option<f32> {
         Some(self.min_distance_from(p))
     }
 
-    fn de_shader(&self) -> String {
-        let s = include_str!("shape.frag")
-            .replace("{X}", &self.center.x.to_string())
-            .replace("{Y}", &self.center.y.to_string())
-            .replace("{Z}", &self.center.z.to_string())
-            .replace("{RADIUS}", &self.radius.to_string());
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        glsl.function("sphere", include_str!("shape.frag"), &[
+            ("CENTER", &self.center.to_vec()),
+            ("RADIUS", &self.radius),
+        ])
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo {
+                name: "center",
+                ty: ParamType::Vector,
+                range: f32::MIN..=f32::MAX,
+            },
+            ParamInfo {
+                name: "radius",
+                ty: ParamType::Float,
+                range: f32::MIN_POSITIVE..=f32::MAX,
+            },
+        ]
+    }
+
+    fn param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "center" => Some(self.center.to_vec().into()),
+            "radius" => Some(self.radius.into()),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        param::find(&self.params(), name)?.check(value)?;
+        match name {
+            "center" => self.center = Point3::from_vec(value.as_vector()),
+            "radius" => self.radius = value.as_float(),
+            _ => unreachable!(),
+        }
 
-        s
+        Ok(())
     }
 
     impl_batch_methods!();
@@ -0,0 +1,4 @@
+float {NAME}(vec3 point) {
+    vec3 inner = {INV_ROTATION} * (point - {TRANSLATION}) * {INV_SCALE};
+    return {INNER}(inner) * {SCALE};
+}
@@ -0,0 +1,167 @@
+use std::ops::Range;
+use cgmath::{prelude::*, Decomposed, Matrix3, Point3, Quaternion, Vector3};
+
+use super::{ColorData, Shape, glsl::{GlslBuilder, Ident}};
+
+
+/// A shape that is scaled (uniformly), rotated and then translated.
+///
+/// Non-uniform scaling is not supported, as it would distort the distance
+/// field of the inner shape in a way that can't easily be corrected.
+#[derive(Clone)]
+pub struct Transformed<S> {
+    shape: S,
+
+    /// Transforms points from the inner shape's space into world space.
+    transform: Decomposed<Vector3<f32>, Quaternion<f32>>,
+
+    /// Transforms points from world space into the inner shape's space.
+    inverse: Decomposed<Vector3<f32>, Quaternion<f32>>,
+}
+
+impl<S: Shape> Transformed<S> {
+    /// Creates a new transformed shape. The inner shape is first scaled by
+    /// `scale`, then rotated by `rotation` and finally moved by
+    /// `translation`.
+    pub fn new(
+        shape: S,
+        translation: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: f32,
+    ) -> Self {
+        assert!(scale > 0.0);
+
+        let transform = Decomposed {
+            scale,
+            rot: rotation.normalize(),
+            disp: translation,
+        };
+
+        Self {
+            shape,
+            transform,
+            // We checked the scale above, so the transformation is invertible
+            inverse: transform.inverse_transform().unwrap(),
+        }
+    }
+
+    /// Moves the given shape by `translation`.
+    pub fn translated(shape: S, translation: Vector3<f32>) -> Self {
+        Self::new(shape, translation, Quaternion::one(), 1.0)
+    }
+
+    /// Rotates the given shape around the origin.
+    pub fn rotated(shape: S, rotation: Quaternion<f32>) -> Self {
+        Self::new(shape, Vector3::zero(), rotation, 1.0)
+    }
+
+    /// Scales the given shape (with the origin as fix point).
+    pub fn scaled(shape: S, scale: f32) -> Self {
+        Self::new(shape, Vector3::zero(), Quaternion::one(), scale)
+    }
+
+    /// Transforms a world space point into the inner shape's space.
+    fn to_inner(&self, p: Point3<f32>) -> Point3<f32> {
+        self.inverse.transform_point(p)
+    }
+
+    /// Like `to_inner`, but with double precision.
+    fn to_inner_f64(&self, p: Point3<f64>) -> Point3<f64> {
+        let inverse = Decomposed {
+            scale: f64::from(self.inverse.scale),
+            rot: self.inverse.rot.cast().unwrap(),
+            disp: self.inverse.disp.cast().unwrap(),
+        };
+        inverse.transform_point(p)
+    }
+}
+
+impl<S: Shape> Shape for Transformed<S> {
+    // All distances in the inner shape's space are scaled by the same factor
+    // when transformed to world space (rotation and translation preserve
+    // distances). So we just need to scale the inner estimates.
+
+    fn min_distance_from(&self, p: Point3<f32>) -> f32 {
+        self.shape.min_distance_from(self.to_inner(p)) * self.transform.scale
+    }
+
+    fn min_distance_from_f64(&self, p: Point3<f64>) -> f64 {
+        self.shape.min_distance_from_f64(self.to_inner_f64(p)) * f64::from(self.transform.scale)
+    }
+
+    fn colored_distance_from(&self, p: Point3<f64>) -> (f64, Option<ColorData>) {
+        let (d, color) = self.shape.colored_distance_from(self.to_inner_f64(p));
+        (d * f64::from(self.transform.scale), color)
+    }
+
+    fn gradient_at(&self, p: Point3<f64>, delta: f64) -> Vector3<f64> {
+        // The scale cancels out: distances are scaled by `scale`, positions
+        // by `1 / scale`. So we only need to rotate the inner gradient.
+        let scale = f64::from(self.transform.scale);
+        let inner = self.shape.gradient_at(self.to_inner_f64(p), delta / scale);
+        self.transform.rot.cast::<f64>().unwrap().rotate_vector(inner)
+    }
+
+    fn max_distance_from(&self, p: Point3<f32>) -> Option<f32> {
+        self.shape.max_distance_from(self.to_inner(p)).map(|d| d * self.transform.scale)
+    }
+
+    fn bounded_distance_from(&self, p: Point3<f32>) -> (f32, Option<f32>) {
+        let (min, max) = self.shape.bounded_distance_from(self.to_inner(p));
+        let scale = self.transform.scale;
+        (min * scale, max.map(|d| d * scale))
+    }
+
+    fn contains(&self, p: Point3<f32>) -> bool {
+        self.shape.contains(self.to_inner(p))
+    }
+
+    fn bounding_box(&self) -> Range<Point3<f32>> {
+        // We transform all eight corners of the inner bounding box and take
+        // the axis aligned box around those. This is not the tightest box for
+        // rotated shapes, but it always contains the whole shape.
+        let Range { start, end } = self.shape.bounding_box();
+
+        // Rotating infinite corners would result in NaNs (`inf * 0`). An
+        // infinite box stays infinite along all axes after rotation anyway.
+        let finite = |p: Point3<f32>| p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+        if !finite(start) || !finite(end) {
+            let inf = f32::INFINITY;
+            return Point3::new(-inf, -inf, -inf)..Point3::new(inf, inf, inf);
+        }
+
+        let corners = [
+            Point3::new(start.x, start.y, start.z),
+            Point3::new(start.x, start.y,   end.z),
+            Point3::new(start.x,   end.y, start.z),
+            Point3::new(start.x,   end.y,   end.z),
+            Point3::new(  end.x, start.y, start.z),
+            Point3::new(  end.x, start.y,   end.z),
+            Point3::new(  end.x,   end.y, start.z),
+            Point3::new(  end.x,   end.y,   end.z),
+        ];
+
+        let first = self.transform.transform_point(corners[0]);
+        corners[1..].iter()
+            .map(|&c| self.transform.transform_point(c))
+            .fold(first .. first, |bb, p| {
+                Point3::new(bb.start.x.min(p.x), bb.start.y.min(p.y), bb.start.z.min(p.z))
+                    .. Point3::new(bb.end.x.max(p.x), bb.end.y.max(p.y), bb.end.z.max(p.z))
+            })
+    }
+
+    fn build_de_shader(&self, glsl: &mut GlslBuilder) -> Ident {
+        let inner = glsl.shape(&self.shape);
+        let inv_rotation: Matrix3<f32> = self.transform.rot.conjugate().into();
+
+        glsl.function("transformed", include_str!("transformed.frag"), &[
+            ("INNER", &inner),
+            ("INV_ROTATION", &inv_rotation),
+            ("INV_SCALE", &(1.0 / self.transform.scale)),
+            ("TRANSLATION", &self.transform.disp),
+            ("SCALE", &self.transform.scale),
+        ])
+    }
+
+    impl_batch_methods!();
+}
@@ -0,0 +1,8 @@
+float {NAME}(vec3 point) {
+    float angle = -{RATE} * point.z;
+    vec2 xy = mat2(cos(angle), sin(angle), -sin(angle), cos(angle)) * point.xy;
+
+    float t = abs({RATE}) * max(length(point.xy), {RADIUS});
+    float lipschitz = t / 2.0 + sqrt(1.0 + t * t / 4.0);
+    return {INNER}(vec3(xy, point.z)) / lipschitz;
+}
@@ -4,6 +4,11 @@ macro_rules! impl_batch_methods {
             points.iter().map(|&p| self.min_distance_from(p)).collect()
         }
 
+        impl_batch_methods!(without_min);
+    };
+
+    // For shapes which implement `batch_min_distance_from` themselves.
+    (without_min) => {
         fn batch_max_distance_from(&self, points: &[Point3<f32>]) -> Vec<f32> {
             points.iter().map(|&p| self.max_distance_from(p).unwrap()).collect()
         }
@@ -14,5 +19,5 @@ macro_rules! impl_batch_methods {
                 (min, max.unwrap())
             }).collect()
         }
-    }
+    };
 }
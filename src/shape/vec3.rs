@@ -0,0 +1,191 @@
+use std::ops::{Add, Mul, Sub};
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+
+/// A 3D vector used by the `f32` distance estimators of the mandelbulb
+/// shapes. There are two backends: `SseVec3` stores the vector in one SIMD
+/// register and is used on x86_64; `ScalarVec3` is plain Rust and works
+/// everywhere. Which one is used is decided by `NativeVec3`.
+///
+/// Both backends produce bit-identical results.
+pub(crate) trait Vec3:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<f32, Output = Self>
+{
+    fn new(x: f32, y: f32, z: f32) -> Self;
+
+    fn x(self) -> f32;
+    fn y(self) -> f32;
+    fn z(self) -> f32;
+
+    fn magnitude(self) -> f32;
+
+    /// Returns `true` if x and y are both zero.
+    fn is_on_z_axis(self) -> bool;
+}
+
+/// The backend used by all shapes. The scalar one can be forced with the
+/// `force-scalar` feature (mainly to test it on x86_64).
+#[cfg(all(target_arch = "x86_64", not(feature = "force-scalar")))]
+pub(crate) type NativeVec3 = SseVec3;
+
+/// The backend used by all shapes. The scalar one can be forced with the
+/// `force-scalar` feature (mainly to test it on x86_64).
+#[cfg(not(all(target_arch = "x86_64", not(feature = "force-scalar"))))]
+pub(crate) type NativeVec3 = ScalarVec3;
+
+
+/// Portable implementation of `Vec3`.
+#[cfg_attr(all(target_arch = "x86_64", not(feature = "force-scalar")), allow(dead_code))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ScalarVec3 {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Vec3 for ScalarVec3 {
+    #[inline(always)]
+    fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    #[inline(always)]
+    fn x(self) -> f32 {
+        self.x
+    }
+
+    #[inline(always)]
+    fn y(self) -> f32 {
+        self.y
+    }
+
+    #[inline(always)]
+    fn z(self) -> f32 {
+        self.z
+    }
+
+    #[inline(always)]
+    fn magnitude(self) -> f32 {
+        // Same order of additions as `_mm_dp_ps` to get the same result.
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    #[inline(always)]
+    fn is_on_z_axis(self) -> bool {
+        self.x == 0.0 && self.y == 0.0
+    }
+}
+
+impl Add for ScalarVec3 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for ScalarVec3 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f32> for ScalarVec3 {
+    type Output = Self;
+    fn mul(self, other: f32) -> Self {
+        Self::new(self.x * other, self.y * other, self.z * other)
+    }
+}
+
+
+/// A 3D vector stored in a 128bit SIMD register.
+///
+/// x is stored in the lowest bits, the highest 32 bits are unused.
+#[cfg(target_arch = "x86_64")]
+#[cfg_attr(feature = "force-scalar", allow(dead_code))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SseVec3(pub(crate) __m128);
+
+#[cfg(target_arch = "x86_64")]
+impl Vec3 for SseVec3 {
+    #[inline(always)]
+    fn new(x: f32, y: f32, z: f32) -> Self {
+        let r = unsafe { _mm_set_ps(0.0, z, y, x) };
+        Self(r)
+    }
+
+    #[inline(always)]
+    fn x(self) -> f32 {
+        unsafe { _mm_cvtss_f32(self.0) }
+    }
+
+    #[inline(always)]
+    fn y(self) -> f32 {
+        unsafe { f32::from_bits(_mm_extract_ps(self.0, 1) as u32) }
+    }
+
+    #[inline(always)]
+    fn z(self) -> f32 {
+        unsafe { f32::from_bits(_mm_extract_ps(self.0, 2) as u32) }
+    }
+
+    #[cfg(target_feature = "sse4.1")]
+    #[inline(always)]
+    fn magnitude(self) -> f32 {
+        unsafe {
+            let len_squared = _mm_dp_ps(self.0, self.0, 0b0111_0001);
+            let len = _mm_sqrt_ss(len_squared);
+            _mm_cvtss_f32(len)
+        }
+    }
+
+    #[cfg(not(target_feature = "sse4.1"))]
+    #[inline(always)]
+    fn magnitude(self) -> f32 {
+        (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt()
+    }
+
+    #[cfg(target_feature = "sse4.1")]
+    #[inline(always)]
+    fn is_on_z_axis(self) -> bool {
+        unsafe {
+            let mask = _mm_set_epi32(0, 0, 0x7FFF_FFFF, 0x7FFF_FFFF);
+            _mm_test_all_zeros(_mm_castps_si128(self.0), mask) == 1
+        }
+    }
+
+    #[cfg(not(target_feature = "sse4.1"))]
+    #[inline(always)]
+    fn is_on_z_axis(self) -> bool {
+        self.x() == 0.0 && self.y() == 0.0
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Add for SseVec3 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self(unsafe { _mm_add_ps(self.0, other.0) })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Sub for SseVec3 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self(unsafe { _mm_sub_ps(self.0, other.0) })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Mul<f32> for SseVec3 {
+    type Output = Self;
+    fn mul(self, other: f32) -> Self {
+        Self(unsafe { _mm_mul_ps(self.0, Self::new(other, other, other).0) })
+    }
+}
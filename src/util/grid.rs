@@ -32,6 +32,46 @@ impl<T> GridTable<T> {
 
         GridTable { size, data }
     }
+
+    /// Like `fill_with`, but the values are created one row (all values with
+    /// the same `x` and `y`) at a time. This allows using batch APIs. The
+    /// filler has to push exactly `size` values (for `z` from 0 to `size - 1`)
+    /// to the given vector.
+    pub fn fill_rows_with<F>(size: u32, mut filler: F) -> Self
+        where F: FnMut(u32, u32, &mut Vec<T>)
+    {
+        assert!(size >= 2);
+
+        let mut data = Vec::with_capacity((size as usize).pow(3));
+
+        for (x, y) in iter::square(size) {
+            let len_before = data.len();
+            filler(x, y, &mut data);
+            assert_eq!(data.len() - len_before, size as usize);
+        }
+
+        GridTable { size, data }
+    }
+
+    /// Creates a table from values in the same order as `fill_with` creates
+    /// them (and `data()` returns them). Panics if `data` doesn't contain
+    /// exactly `size³` values.
+    pub fn from_data(size: u32, data: Vec<T>) -> Self {
+        assert!(size >= 2);
+        assert_eq!(data.len(), (size as usize).pow(3));
+
+        GridTable { size, data }
+    }
+
+    /// The number of values along each axis.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// All values, ordered by `x`, then `y`, then `z`.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
 }
 
 impl<T> Index<(u32, u32, u32)> for GridTable<T> {